@@ -14,6 +14,10 @@ var<uniform> atmosphere: AtmosphereSettings;
 var<uniform> view: View;
 @group(0) @binding(5)
 var<uniform> lights: pbr_types::Lights;
+@group(0) @binding(6)
+var optical_depth_lut: texture_2d<f32>;
+@group(0) @binding(7)
+var multi_scattering_lut: texture_2d<f32>;
 
 const PI: f32 = 3.1415927;
 
@@ -24,6 +28,20 @@ struct AtmosphereSettings {
     num_optical_depth_points: u32,
     density_falloff: f32,
     scattering_coeffs: vec3<f32>,
+    center: vec3<f32>,
+    mie_coeffs: vec3<f32>,
+    mie_absorption: f32,
+    mie_falloff: f32,
+    mie_anisotropy: f32,
+    ozone_coeffs: vec3<f32>,
+    ozone_height: f32,
+    ozone_width: f32,
+    sun_disk_angle: f32,
+    sun_disk_intensity: f32,
+    sun_limb_darkening: f32,
+    sun_direction: vec3<f32>,
+    sun_scale: f32,
+    sun_index: u32,
     #ifdef SIXTEEN_BYTE_ALIGNMENT
     _webgl2_padding: vec3<f32>,
     #endif
@@ -52,46 +70,126 @@ fn ray_sphere_intersection(center: vec3<f32>, radius: f32, ro: vec3<f32>, rd: ve
     return vec2(10000000000000.0, 0.0);
 }
 
-fn point_density(pos: vec3<f32>) -> f32 {
-    let height = (length(pos) - atmosphere.ocean_radius) / (atmosphere.radius - atmosphere.ocean_radius);
-    let local_density = exp(-height * atmosphere.density_falloff) * (1.0 - height);
-    return local_density;
+// densities of the air, the haze and the ozone layer
+fn point_density(pos: vec3<f32>) -> vec3<f32> {
+    let height = (length(pos - atmosphere.center) - atmosphere.ocean_radius) / (atmosphere.radius - atmosphere.ocean_radius);
+    let rayleigh = exp(-height * atmosphere.density_falloff) * (1.0 - height);
+    let mie = exp(-height * atmosphere.mie_falloff) * (1.0 - height);
+    let ozone = max(1.0 - abs(height - atmosphere.ozone_height) / max(atmosphere.ozone_width, 0.001), 0.0);
+    return vec3(rayleigh, mie, ozone);
 }
 
-fn optical_depth(ray_pos: vec3<f32>, ray_dir: vec3<f32>, ray_length: f32) -> f32 {
-    var sample_point = ray_pos;
-    var depth = 0.0;
-    let step_size = ray_length / (f32(atmosphere.num_optical_depth_points) - 1.0);
+// haze absorbs as well as scatters, and ozone only absorbs
+fn extinction(depth: vec3<f32>) -> vec3<f32> {
+    return depth.x * atmosphere.scattering_coeffs
+        + depth.y * (atmosphere.mie_coeffs + atmosphere.mie_absorption)
+        + depth.z * atmosphere.ozone_coeffs;
+}
+
+// both phase functions are scaled so isotropic scattering is 1, as it was before there were any
+fn rayleigh_phase(cos_theta: f32) -> f32 {
+    return 0.75 * (1.0 + cos_theta * cos_theta);
+}
+
+fn henyey_greenstein(cos_theta: f32, g: f32) -> f32 {
+    let g2 = g * g;
+    return (1.0 - g2) / pow(1.0 + g2 - 2.0 * g * cos_theta, 1.5);
+}
 
-    for (var i = 0u; i < atmosphere.num_optical_depth_points; i++) {
-        let local_density = point_density(sample_point);
-        depth += local_density * step_size;
-        sample_point += ray_dir * step_size;
+// both lookups are indexed by the cosine of an angle from straight up, from -1 to 1, and by height
+// from sea level to the edge of the atmosphere; they hold floats, so are filtered by hand
+fn load_lut(lut: texture_2d<f32>, pos: vec3<f32>, dir: vec3<f32>) -> vec4<f32> {
+    let size = vec2<f32>(textureDimensions(lut));
+    let offset = pos - atmosphere.center;
+    let height = saturate((length(offset) - atmosphere.ocean_radius) / (atmosphere.radius - atmosphere.ocean_radius));
+    let cos_zenith = dot(normalize(offset), dir);
+    let texel = vec2(cos_zenith * 0.5 + 0.5, height) * (size - 1.0);
+
+    let base = vec2<i32>(floor(texel));
+    let max_texel = vec2<i32>(size) - 1;
+    let f = fract(texel);
+    let a = textureLoad(lut, min(base, max_texel), 0);
+    let b = textureLoad(lut, min(base + vec2(1, 0), max_texel), 0);
+    let c = textureLoad(lut, min(base + vec2(0, 1), max_texel), 0);
+    let d = textureLoad(lut, min(base + vec2(1, 1), max_texel), 0);
+    return mix(mix(a, b, f.x), mix(c, d, f.x), f.y);
+}
+
+// optical depth between two points on a ray, as the difference of the depths out of the atmosphere
+// from each, looking whichever way along the ray points up so neither passes through the planet
+fn optical_depth_between(start: vec3<f32>, end: vec3<f32>, ray_dir: vec3<f32>) -> vec3<f32> {
+    if (dot(end - atmosphere.center, ray_dir) >= 0.0) {
+        return max(load_lut(optical_depth_lut, start, ray_dir).rgb - load_lut(optical_depth_lut, end, ray_dir).rgb, vec3(0.0));
     }
+    return max(load_lut(optical_depth_lut, end, -ray_dir).rgb - load_lut(optical_depth_lut, start, -ray_dir).rgb, vec3(0.0));
+}
+
+// the sun shines on each planet from along its own orbit, so that replaces the light standing in for it
+fn light_direction(i: u32) -> vec3<f32> {
+    return select(lights.directional_lights[i].direction_to_light, atmosphere.sun_direction, i == atmosphere.sun_index);
+}
 
-    return depth;
+fn light_color(i: u32) -> vec3<f32> {
+    return lights.directional_lights[i].color.rgb * select(1.0, atmosphere.sun_scale, i == atmosphere.sun_index);
+}
+
+// lights are weighed against the brightest of them, so a lone white sun lights the atmosphere the
+// same however bright it is
+fn brightest_light() -> f32 {
+    var brightest = 0.0001;
+    for (var i = 0u; i < lights.n_directional_lights; i++) {
+        let color = light_color(i);
+        brightest = max(brightest, max(color.r, max(color.g, color.b)));
+    }
+    return brightest;
 }
 
 fn get_light(ray_pos: vec3<f32>, ray_dir: vec3<f32>, ray_length: f32) -> vec4<f32> {
     var in_scatter_pos = ray_pos;
-    var in_scattered_light = vec4(0.0);
+    var in_scattered_light = vec3(0.0);
+    var opacity = 0.0;
     let step_size = ray_length / (f32(atmosphere.num_sample_points) - 1.0);
-    let dir_to_sun = lights.directional_lights[0].direction_to_light;
+    let brightest = brightest_light();
 
     for (var i = 0u; i < atmosphere.num_sample_points; i++) {
-        let sun_ray_length = ray_sphere_intersection(vec3(0.0), atmosphere.radius, in_scatter_pos, dir_to_sun).y;
-        let sun_ray_depth = optical_depth(in_scatter_pos, dir_to_sun, sun_ray_length);
-        let view_ray_depth = optical_depth(in_scatter_pos, -ray_dir, step_size * f32(i));
-        let transmittance = exp(-(sun_ray_depth + view_ray_depth) * vec4(atmosphere.scattering_coeffs, 1.0));
+        let view_ray_depth = optical_depth_between(ray_pos, in_scatter_pos, ray_dir);
+        let view_transmittance = exp(-extinction(view_ray_depth));
         let local_density = point_density(in_scatter_pos);
+        let scattering = local_density.x * atmosphere.scattering_coeffs + local_density.y * atmosphere.mie_coeffs;
+
+        for (var j = 0u; j < lights.n_directional_lights; j++) {
+            let light_color = light_color(j) / brightest;
+            let dir_to_sun = light_direction(j);
+            let cos_theta = dot(ray_dir, dir_to_sun);
+
+            let sun_ray_depth = load_lut(optical_depth_lut, in_scatter_pos, dir_to_sun).rgb;
+            let transmittance = exp(-extinction(sun_ray_depth)) * view_transmittance;
+            // light scattered more than once is isotropic, scaled by 4 pi like the phase functions
+            let multi_scattering = load_lut(multi_scattering_lut, in_scatter_pos, dir_to_sun).rgb * 4.0 * PI;
+
+            let single_scattering = local_density.x * atmosphere.scattering_coeffs * rayleigh_phase(cos_theta)
+                + local_density.y * atmosphere.mie_coeffs * henyey_greenstein(cos_theta, atmosphere.mie_anisotropy);
+            in_scattered_light += (single_scattering * transmittance + scattering * multi_scattering * view_transmittance) * light_color * step_size;
+            // how much the air hides what's behind it
+            opacity += local_density.x * exp(-(sun_ray_depth.x + view_ray_depth.x)) * max(light_color.r, max(light_color.g, light_color.b)) * step_size;
+        }
 
-        in_scattered_light += local_density * transmittance * vec4(atmosphere.scattering_coeffs, 1.0) * step_size;
         in_scatter_pos += ray_dir * step_size;
     }
 
-    return in_scattered_light;
+    return vec4(in_scattered_light, opacity);
 }
 
+// the sun's disk, dimmer towards its edge where we look through more of its own atmosphere
+fn sun_disk(ray_dir: vec3<f32>, dir_to_sun: vec3<f32>) -> f32 {
+    let angle = acos(clamp(dot(ray_dir, dir_to_sun), -1.0, 1.0));
+    if (angle >= atmosphere.sun_disk_angle) {
+        return 0.0;
+    }
+    let r = angle / atmosphere.sun_disk_angle;
+    let mu = sqrt(1.0 - r * r);
+    return atmosphere.sun_disk_intensity * (1.0 - atmosphere.sun_limb_darkening * (1.0 - mu));
+}
 
 @fragment
 fn fragment(in: FullscreenVertexOutput) -> @location(0) vec4<f32> {
@@ -105,20 +203,32 @@ fn fragment(in: FullscreenVertexOutput) -> @location(0) vec4<f32> {
     let ray_pos = view.world_position.xyz;
     let ray_dir = normalize(view_vector.xyz);
 
-    let dst_to_ocean = ray_sphere_intersection(vec3(0.0), atmosphere.ocean_radius, ray_pos, ray_dir).x;
+    let dst_to_ocean = ray_sphere_intersection(atmosphere.center, atmosphere.ocean_radius, ray_pos, ray_dir).x;
     let dst_to_surface = min(scene_depth, dst_to_ocean);
 
-    let atmosphere_hit_info = ray_sphere_intersection(vec3(0.0), atmosphere.radius, ray_pos, ray_dir);
+    let atmosphere_hit_info = ray_sphere_intersection(atmosphere.center, atmosphere.radius, ray_pos, ray_dir);
     let dst_to_atmosphere = atmosphere_hit_info.x;
     let dst_thru_atmosphere = min(atmosphere_hit_info.y, dst_to_surface - dst_to_atmosphere);
 
+    var color = old_col.xyz;
+    // light from the sun itself reaches the camera through the whole atmosphere
+    var sun_transmittance = vec3(1.0);
     if (dst_thru_atmosphere > 0.0) {
         let hit_pos = ray_pos + ray_dir * dst_to_atmosphere;
         let light = get_light(hit_pos, ray_dir, dst_thru_atmosphere);
-        return vec4(mix(old_col.xyz, light.xyz, light.w), 1.0);
+        color = mix(color, light.xyz, light.w);
+        sun_transmittance = exp(-extinction(load_lut(optical_depth_lut, hit_pos, ray_dir).rgb));
+    }
+
+    // the suns only show where nothing is in front of them
+    if (dst_to_surface >= 10000000000000.0 && atmosphere.sun_disk_intensity > 0.0) {
+        let brightest = brightest_light();
+        for (var i = 0u; i < lights.n_directional_lights; i++) {
+            color += sun_disk(ray_dir, light_direction(i)) * light_color(i) / brightest * sun_transmittance;
+        }
     }
 
-    return old_col;
+    return vec4(color, 1.0);
     // return vec4(vec3(nonlinear_depth), 1.0);
     // return vec4(vec3(atmosphere_hit_info.y / (atmosphere.radius * 2.0)), 1.0);
 }
\ No newline at end of file
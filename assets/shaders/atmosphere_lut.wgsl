@@ -0,0 +1,187 @@
+struct AtmosphereSettings {
+    radius: f32,
+    ocean_radius: f32,
+    num_sample_points: u32,
+    num_optical_depth_points: u32,
+    density_falloff: f32,
+    scattering_coeffs: vec3<f32>,
+    center: vec3<f32>,
+    mie_coeffs: vec3<f32>,
+    mie_absorption: f32,
+    mie_falloff: f32,
+    mie_anisotropy: f32,
+    ozone_coeffs: vec3<f32>,
+    ozone_height: f32,
+    ozone_width: f32,
+    sun_disk_angle: f32,
+    sun_disk_intensity: f32,
+    sun_limb_darkening: f32,
+    sun_direction: vec3<f32>,
+    sun_scale: f32,
+    sun_index: u32,
+    #ifdef SIXTEEN_BYTE_ALIGNMENT
+    _webgl2_padding: vec3<f32>,
+    #endif
+}
+
+@group(0) @binding(0)
+var<uniform> atmosphere: AtmosphereSettings;
+@group(0) @binding(1)
+var optical_depth_output: texture_storage_2d<rgba32float, write>;
+@group(0) @binding(2)
+var optical_depth_lut: texture_2d<f32>;
+@group(0) @binding(3)
+var multi_scattering_output: texture_storage_2d<rgba32float, write>;
+
+const PI: f32 = 3.1415927;
+const GOLDEN_ANGLE: f32 = 2.3999632;
+// directions averaged over, and steps along each, when gathering light scattered more than once
+const MULTI_SCATTERING_DIRECTIONS: u32 = 64u;
+const MULTI_SCATTERING_STEPS: u32 = 20u;
+
+fn ray_sphere_intersection(center: vec3<f32>, radius: f32, ro: vec3<f32>, rd: vec3<f32>) -> vec2<f32> {
+    let offset = ro - center;
+    let a = dot(rd, rd);
+    let half_b = dot(offset, rd);
+    let c = dot(offset, offset) - radius * radius;
+
+    let discriminant = half_b * half_b - a * c;
+    if (discriminant >= 0.0) {
+        let s = sqrt(discriminant);
+        let dst_near = max(0.0, (-half_b - s) / a);
+        let dst_far = (-half_b + s) / a;
+
+        if (dst_far >= 0.0) {
+            return vec2(dst_near, dst_far - dst_near);
+        }
+    }
+    return vec2(10000000000000.0, 0.0);
+}
+
+// the same densities of air, haze and ozone as atmosphere.wgsl, measured from a centre at the origin
+fn point_density(pos: vec3<f32>) -> vec3<f32> {
+    let height = (length(pos) - atmosphere.ocean_radius) / (atmosphere.radius - atmosphere.ocean_radius);
+    let rayleigh = exp(-height * atmosphere.density_falloff) * (1.0 - height);
+    let mie = exp(-height * atmosphere.mie_falloff) * (1.0 - height);
+    let ozone = max(1.0 - abs(height - atmosphere.ozone_height) / max(atmosphere.ozone_width, 0.001), 0.0);
+    return vec3(rayleigh, mie, ozone);
+}
+
+fn extinction(depth: vec3<f32>) -> vec3<f32> {
+    return depth.x * atmosphere.scattering_coeffs
+        + depth.y * (atmosphere.mie_coeffs + atmosphere.mie_absorption)
+        + depth.z * atmosphere.ozone_coeffs;
+}
+
+fn optical_depth(ray_pos: vec3<f32>, ray_dir: vec3<f32>, ray_length: f32) -> vec3<f32> {
+    var sample_point = ray_pos;
+    var depth = vec3(0.0);
+    let step_size = ray_length / (f32(atmosphere.num_optical_depth_points) - 1.0);
+
+    for (var i = 0u; i < atmosphere.num_optical_depth_points; i++) {
+        let local_density = point_density(sample_point);
+        depth += local_density * step_size;
+        sample_point += ray_dir * step_size;
+    }
+
+    return depth;
+}
+
+// texel centres map to heights from sea level to the edge of the atmosphere, and to the cosine of
+// the angle from straight up, from -1 to 1
+fn lut_position(id: vec2<u32>, size: vec2<u32>) -> vec3<f32> {
+    let uv = vec2<f32>(id) / vec2<f32>(size - 1u);
+    let height = mix(atmosphere.ocean_radius, atmosphere.radius, uv.y);
+    return vec3(0.0, height, 0.0);
+}
+
+fn lut_direction(id: vec2<u32>, size: vec2<u32>) -> vec3<f32> {
+    let cos_zenith = f32(id.x) / f32(size.x - 1u) * 2.0 - 1.0;
+    return vec3(sqrt(max(1.0 - cos_zenith * cos_zenith, 0.0)), cos_zenith, 0.0);
+}
+
+fn load_optical_depth(pos: vec3<f32>, dir: vec3<f32>) -> vec3<f32> {
+    let size = vec2<f32>(textureDimensions(optical_depth_lut));
+    let height = saturate((length(pos) - atmosphere.ocean_radius) / (atmosphere.radius - atmosphere.ocean_radius));
+    let cos_zenith = dot(normalize(pos), dir);
+    let texel = vec2(cos_zenith * 0.5 + 0.5, height) * (size - 1.0);
+
+    let base = vec2<i32>(floor(texel));
+    let max_texel = vec2<i32>(size) - 1;
+    let f = fract(texel);
+    let a = textureLoad(optical_depth_lut, min(base, max_texel), 0).rgb;
+    let b = textureLoad(optical_depth_lut, min(base + vec2(1, 0), max_texel), 0).rgb;
+    let c = textureLoad(optical_depth_lut, min(base + vec2(0, 1), max_texel), 0).rgb;
+    let d = textureLoad(optical_depth_lut, min(base + vec2(1, 1), max_texel), 0).rgb;
+    return mix(mix(a, b, f.x), mix(c, d, f.x), f.y);
+}
+
+
+// Optical depth of the air, haze and ozone from each height along each direction out to the edge of
+// the atmosphere.
+@compute @workgroup_size(8, 8, 1)
+fn transmittance(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(optical_depth_output);
+    if (any(id.xy >= size)) {
+        return;
+    }
+
+    let pos = lut_position(id.xy, size);
+    let dir = lut_direction(id.xy, size);
+    let ray_length = ray_sphere_intersection(vec3(0.0), atmosphere.radius, pos, dir).y;
+
+    textureStore(optical_depth_output, id.xy, vec4(optical_depth(pos, dir, ray_length), 1.0));
+}
+
+// Light scattered two or more times, by height and the sun's angle from straight up, following
+// Hillaire's approximation: second order scattering gathered from every direction, then summed as
+// a geometric series of further bounces.
+@compute @workgroup_size(8, 8, 1)
+fn multi_scattering(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(multi_scattering_output);
+    if (any(id.xy >= size)) {
+        return;
+    }
+
+    let pos = lut_position(id.xy, size);
+    let dir_to_sun = lut_direction(id.xy, size);
+
+    var second_order = vec3(0.0);
+    var transfer = vec3(0.0);
+    for (var i = 0u; i < MULTI_SCATTERING_DIRECTIONS; i++) {
+        // spread the directions evenly over the sphere
+        let cos_theta = 1.0 - 2.0 * (f32(i) + 0.5) / f32(MULTI_SCATTERING_DIRECTIONS);
+        let sin_theta = sqrt(max(1.0 - cos_theta * cos_theta, 0.0));
+        let phi = f32(i) * GOLDEN_ANGLE;
+        let ray_dir = vec3(sin_theta * cos(phi), cos_theta, sin_theta * sin(phi));
+
+        let atmosphere_length = ray_sphere_intersection(vec3(0.0), atmosphere.radius, pos, ray_dir).y;
+        let ground_distance = ray_sphere_intersection(vec3(0.0), atmosphere.ocean_radius, pos, ray_dir).x;
+        let ray_length = min(atmosphere_length, ground_distance);
+        let step_size = ray_length / f32(MULTI_SCATTERING_STEPS);
+
+        var view_depth = vec3(0.0);
+        for (var j = 0u; j < MULTI_SCATTERING_STEPS; j++) {
+            let sample_pos = pos + ray_dir * step_size * (f32(j) + 0.5);
+            let density = point_density(sample_pos);
+            let view_transmittance = exp(-extinction(view_depth));
+            let sun_transmittance = exp(-extinction(load_optical_depth(sample_pos, dir_to_sun)));
+            // nothing reaches the night side of the planet
+            let sunlit = select(1.0, 0.0, ray_sphere_intersection(vec3(0.0), atmosphere.ocean_radius, sample_pos, dir_to_sun).y > 0.0);
+
+            let scattering = (density.x * atmosphere.scattering_coeffs + density.y * atmosphere.mie_coeffs) * step_size / (4.0 * PI);
+            second_order += view_transmittance * sun_transmittance * sunlit * scattering;
+            transfer += view_transmittance * scattering;
+            view_depth += density * step_size;
+        }
+    }
+
+    // each direction covers an equal share of the sphere, and the light gathered from them is
+    // scattered isotropically once more on its way to the next bounce
+    let solid_angle = 4.0 * PI / f32(MULTI_SCATTERING_DIRECTIONS);
+    second_order *= solid_angle / (4.0 * PI);
+    transfer *= solid_angle;
+
+    let multi_scattering = second_order / max(1.0 - transfer, vec3(0.001));
+    textureStore(multi_scattering_output, id.xy, vec4(multi_scattering, 1.0));
+}
@@ -0,0 +1,228 @@
+#import bevy_core_pipeline::fullscreen_vertex_shader FullscreenVertexOutput
+#import bevy_pbr::mesh_view_types as pbr_types
+#import bevy_render::view View
+
+@group(0) @binding(0)
+var screen_texture: texture_2d<f32>;
+@group(0) @binding(1)
+var depth_texture: texture_depth_2d;
+@group(0) @binding(2)
+var texture_sampler: sampler;
+@group(0) @binding(3)
+var<uniform> clouds: CloudSettings;
+@group(0) @binding(4)
+var<uniform> view: View;
+@group(0) @binding(5)
+var<uniform> lights: pbr_types::Lights;
+
+const PI: f32 = 3.1415927;
+
+struct CloudSettings {
+    center: vec3<f32>,
+    rotation: mat3x3<f32>,
+    inner_radius: f32,
+    outer_radius: f32,
+    surface_radius: f32,
+    coverage: f32,
+    density: f32,
+    noise_scale: f32,
+    detail_scale: f32,
+    shadow_strength: f32,
+    wind_offset: vec3<f32>,
+    detail_offset: vec3<f32>,
+    color: vec3<f32>,
+    num_steps: u32,
+    num_light_steps: u32,
+    enabled: u32,
+    sun_direction: vec3<f32>,
+    sun_scale: f32,
+    sun_index: u32,
+}
+
+fn linearize_depth(depth: f32) -> f32 {
+    return view.projection[3][2] / depth;
+}
+
+fn ray_sphere_intersection(center: vec3<f32>, radius: f32, ro: vec3<f32>, rd: vec3<f32>) -> vec2<f32> {
+    let offset = ro - center;
+    let a = dot(rd, rd);
+    let half_b = dot(offset, rd);
+    let c = dot(offset, offset) - radius * radius;
+
+    let discriminant = half_b * half_b - a * c;
+    if (discriminant >= 0.0) {
+        let s = sqrt(discriminant);
+        let dst_near = max(0.0, (-half_b - s) / a);
+        let dst_far = (-half_b + s) / a;
+
+        if (dst_far >= 0.0) {
+            return vec2(dst_near, dst_far - dst_near);
+        }
+    }
+    return vec2(10000000000000.0, 0.0);
+}
+
+fn hash(p: vec3<f32>) -> f32 {
+    let q = fract(p * 0.3183099 + 0.1) * 17.0;
+    return fract(q.x * q.y * q.z * (q.x + q.y + q.z));
+}
+
+fn value_noise(p: vec3<f32>) -> f32 {
+    let i = floor(p);
+    let f = fract(p);
+    let u = f * f * (3.0 - 2.0 * f);
+
+    return mix(
+        mix(
+            mix(hash(i), hash(i + vec3(1.0, 0.0, 0.0)), u.x),
+            mix(hash(i + vec3(0.0, 1.0, 0.0)), hash(i + vec3(1.0, 1.0, 0.0)), u.x),
+            u.y,
+        ),
+        mix(
+            mix(hash(i + vec3(0.0, 0.0, 1.0)), hash(i + vec3(1.0, 0.0, 1.0)), u.x),
+            mix(hash(i + vec3(0.0, 1.0, 1.0)), hash(i + vec3(1.0, 1.0, 1.0)), u.x),
+            u.y,
+        ),
+        u.z,
+    );
+}
+
+fn fbm(p: vec3<f32>, octaves: u32) -> f32 {
+    var sum = 0.0;
+    var amplitude = 0.5;
+    var total = 0.0;
+    var q = p;
+    for (var i = 0u; i < octaves; i++) {
+        sum += value_noise(q) * amplitude;
+        total += amplitude;
+        amplitude *= 0.5;
+        q *= 2.03;
+    }
+    return sum / total;
+}
+
+// extinction at a point in world space, from noise in planet space so the clouds turn with the planet
+fn cloud_density(pos: vec3<f32>) -> f32 {
+    let local_position = (pos - clouds.center) * clouds.rotation;
+    let height = (length(local_position) - clouds.inner_radius) / (clouds.outer_radius - clouds.inner_radius);
+    // rounded bottoms, and tops that thin out
+    let profile = smoothstep(0.0, 0.15, height) * (1.0 - smoothstep(0.5, 1.0, height));
+
+    let base = fbm(local_position * clouds.noise_scale + clouds.wind_offset, 4u);
+    let shape = saturate((base - (1.0 - clouds.coverage)) / max(clouds.coverage, 0.01)) * profile;
+    // erode the edges more than the cores
+    let detail = fbm(local_position * clouds.detail_scale + clouds.detail_offset, 2u);
+    return saturate(shape - detail * 0.35 * (1.0 - shape)) * clouds.density;
+}
+
+fn henyey_greenstein(cos_theta: f32, g: f32) -> f32 {
+    let g2 = g * g;
+    return (1.0 - g2) / (4.0 * PI * pow(1.0 + g2 - 2.0 * g * cos_theta, 1.5));
+}
+
+// a bright forward lobe for silver linings and a weak backward one, scaled so isotropic scattering is 1
+fn cloud_phase(cos_theta: f32) -> f32 {
+    return mix(henyey_greenstein(cos_theta, -0.2), henyey_greenstein(cos_theta, 0.7), 0.7) * 4.0 * PI;
+}
+
+fn light_optical_depth(pos: vec3<f32>, dir_to_light: vec3<f32>) -> f32 {
+    let ray_length = ray_sphere_intersection(clouds.center, clouds.outer_radius, pos, dir_to_light).y;
+    let step_size = ray_length / f32(max(clouds.num_light_steps, 1u));
+    var depth = 0.0;
+    for (var i = 0u; i < clouds.num_light_steps; i++) {
+        depth += cloud_density(pos + dir_to_light * step_size * (f32(i) + 0.5)) * step_size;
+    }
+    return depth;
+}
+
+// the sun shines on each planet from along its own orbit, so that replaces the light standing in for it
+fn light_direction(i: u32) -> vec3<f32> {
+    return select(lights.directional_lights[i].direction_to_light, clouds.sun_direction, i == clouds.sun_index);
+}
+
+fn light_color(i: u32) -> vec3<f32> {
+    return lights.directional_lights[i].color.rgb * select(1.0, clouds.sun_scale, i == clouds.sun_index);
+}
+
+// light reaching a point in the clouds from every sun, plus a dim sky light on the day side
+fn cloud_light(pos: vec3<f32>, ray_dir: vec3<f32>) -> vec3<f32> {
+    let up = normalize(pos - clouds.center);
+    var light = vec3(0.0);
+    for (var i = 0u; i < lights.n_directional_lights; i++) {
+        let dir_to_light = light_direction(i);
+        // the planet itself shadows the far side
+        let planet_shadow = ray_sphere_intersection(clouds.center, clouds.surface_radius, pos, dir_to_light).y;
+        let sunlit = select(1.0, 0.0, planet_shadow > 0.0);
+
+        let optical_depth = light_optical_depth(pos, dir_to_light);
+        // the powder term darkens the edges facing the sun, where little light has scattered in yet
+        let transmittance = exp(-optical_depth) * (1.0 - exp(-optical_depth * 2.0) * 0.5);
+        let phase = cloud_phase(dot(ray_dir, dir_to_light));
+        let sky = smoothstep(-0.2, 0.3, dot(up, dir_to_light)) * 0.15;
+
+        light += light_color(i) * (transmittance * phase * sunlit + sky);
+    }
+    return light * clouds.color;
+}
+
+
+@fragment
+fn fragment(in: FullscreenVertexOutput) -> @location(0) vec4<f32> {
+    let old_col = textureLoad(screen_texture, vec2<i32>(in.position.xy), 0);
+    if (clouds.enabled == 0u || clouds.num_steps == 0u) {
+        return old_col;
+    }
+
+    var view_vector = view.inverse_projection * (vec4(in.uv * 2.0 - 1.0, 0.0, 1.0) * vec4(1.0, -1.0, 1.0, 1.0));
+    view_vector = view_vector * view.inverse_view;
+
+    let nonlinear_depth = textureLoad(depth_texture, vec2<i32>(in.position.xy), 0);
+    let scene_depth = linearize_depth(nonlinear_depth);
+
+    let ray_pos = view.world_position.xyz;
+    let ray_dir = normalize(view_vector.xyz);
+
+    let dst_to_ground = ray_sphere_intersection(clouds.center, clouds.surface_radius, ray_pos, ray_dir).x;
+    let dst_to_surface = min(scene_depth, dst_to_ground);
+
+    let outer_hit = ray_sphere_intersection(clouds.center, clouds.outer_radius, ray_pos, ray_dir);
+    let inner_hit = ray_sphere_intersection(clouds.center, clouds.inner_radius, ray_pos, ray_dir);
+
+    // only march the part of the ray inside the shell, skipping the hollow below it
+    var start = outer_hit.x;
+    var end = min(outer_hit.x + outer_hit.y, dst_to_surface);
+    if (length(ray_pos - clouds.center) < clouds.inner_radius) {
+        start = max(start, inner_hit.x + inner_hit.y);
+    } else {
+        end = min(end, inner_hit.x);
+    }
+
+    if (end <= start) {
+        return old_col;
+    }
+
+    let step_size = (end - start) / f32(clouds.num_steps);
+    // jitter the start of each ray to trade banding for noise
+    var dst = start + step_size * hash(vec3(in.position.xy, 0.0));
+    var transmittance = 1.0;
+    var scattered = vec3(0.0);
+
+    for (var i = 0u; i < clouds.num_steps; i++) {
+        let pos = ray_pos + ray_dir * dst;
+        let density = cloud_density(pos);
+
+        if (density > 0.001) {
+            let step_transmittance = exp(-density * step_size);
+            // integrate the in-scattered light over the step, rather than taking it at one point
+            scattered += transmittance * cloud_light(pos, ray_dir) * (1.0 - step_transmittance);
+            transmittance *= step_transmittance;
+
+            if (transmittance < 0.01) {
+                break;
+            }
+        }
+        dst += step_size;
+    }
+
+    return vec4(old_col.rgb * transmittance + scattered, 1.0);
+}
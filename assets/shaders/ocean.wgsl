@@ -17,6 +17,10 @@ struct OceanMaterial {
     wave_strength: f32,
     wave_speed: f32,
     wave_scale: f32,
+    center: vec3<f32>,
+    sun_direction: vec3<f32>,
+    sun_scale: f32,
+    sun_index: u32,
 }
 
 @group(1) @binding(0) var<uniform> ocean: OceanMaterial;
@@ -25,6 +29,16 @@ struct OceanMaterial {
 @group(1) @binding(3) var wave_normals_texture_2: texture_2d<f32>;
 @group(1) @binding(4) var wave_normals_sampler_2: sampler;
 
+// the sun shines on each planet from along its own orbit, so that replaces the light standing in for
+// it. its shadow maps were cast the other way, so aren't used
+fn light_direction(i: u32) -> vec3<f32> {
+    return select(view_bindings::lights.directional_lights[i].direction_to_light, ocean.sun_direction, i == ocean.sun_index);
+}
+
+fn light_color(i: u32) -> vec3<f32> {
+    return view_bindings::lights.directional_lights[i].color.rgb * select(1.0, ocean.sun_scale, i == ocean.sun_index);
+}
+
 fn linearize_depth(depth: f32) -> f32 {
     return view_bindings::view.projection[3][2] / depth;
 }
@@ -118,13 +132,13 @@ fn fragment(in: MeshVertexOutput) -> @location(0) vec4<f32> {
 
     let ray_pos = view_bindings::view.world_position.xyz;
     let ray_dir = normalize(view_vector);
-    let hit_info = ray_sphere_intersection(vec3(0.0), ocean.radius, ray_pos, ray_dir);
+    let hit_info = ray_sphere_intersection(ocean.center, ocean.radius, ray_pos, ray_dir);
     let dst_to_ocean = hit_info.x;
     let dst_thru_ocean = hit_info.y;
     let ocean_view_depth = min(dst_thru_ocean, scene_depth - linearize_depth(in.position.z));
 
     if (ocean_view_depth > 0.0) {
-        let ocean_hit_pos = ray_pos + ray_dir * dst_to_ocean;
+        let ocean_hit_pos = ray_pos + ray_dir * dst_to_ocean - ocean.center;
         let ocean_sphere_normal = normalize(ocean_hit_pos);
 
         let wave_offset_1 = vec2(ocean.time * ocean.wave_speed, ocean.time * ocean.wave_speed * 0.8);
@@ -138,10 +152,9 @@ fn fragment(in: MeshVertexOutput) -> @location(0) vec4<f32> {
         var ocean_col = mix(ocean.color_2.xyz, ocean.color_1.xyz, optical_depth);
 
         for (var i = 0u; i < view_bindings::lights.n_directional_lights; i++) {
-            let directional_light = view_bindings::lights.directional_lights[i];
-            let to_light = directional_light.direction_to_light;
+            let to_light = light_direction(i);
             let to_eye = -ray_dir;
-            let shadow = shadows::fetch_directional_shadow(i, in.world_position, ocean_normal, view_z);
+            let shadow = select(shadows::fetch_directional_shadow(i, in.world_position, ocean_normal, view_z), 1.0, i == ocean.sun_index);
             let diffuse = saturate(dot(ocean_sphere_normal, to_light)) * shadow;
 
             let half_angle = normalize(to_light + to_eye);
@@ -177,7 +190,7 @@ fn fragment(in: MeshVertexOutput) -> @location(0) vec4<f32> {
             let specular = (spec_highlight * fresnel * geometric_attenuation) / (4.0 * v_dot_n * l_dot_n);
 
             ocean_col *= vec3(diffuse);
-            ocean_col += specular * directional_light.color.xyz * shadow;
+            ocean_col += specular * light_color(i) * shadow;
         }
 
         return vec4(ocean_col, alpha);
@@ -1,38 +1,105 @@
 #import bevy_pbr::mesh_vertex_output MeshVertexOutput
 #import bevy_pbr::mesh_view_bindings as view_bindings
+#import bevy_pbr::mesh_view_types FOG_MODE_OFF, DIRECTIONAL_LIGHT_FLAGS_SHADOWS_ENABLED_BIT, POINT_LIGHT_FLAGS_SHADOWS_ENABLED_BIT
+#import bevy_pbr::mesh_bindings mesh
+#import bevy_pbr::mesh_types MESH_FLAGS_SHADOW_RECEIVER_BIT
+#import bevy_pbr::pbr_functions as pbr_functions
+#import bevy_pbr::lighting as lighting
 #import bevy_pbr::shadows as shadows
-
-struct ColorEntry {
-    color: vec3<f32>,
-    elevation: f32,
-    _padding: vec3<f32>,
-    steepness: f32,
-}
+#import bevy_pbr::clustered_forward as clustering
+#import bevy_pbr::ambient as ambient
+#import bevy_core_pipeline::tonemapping screen_space_dither, powsafe, tone_mapping
 
 struct PlanetMaterial {
     min_elevation: f32,
     max_elevation: f32,
-    n_colors: u32,
     normal_strength: f32,
     normal_scale: f32,
+    n_layers: u32,
+    sea_level: f32,
+    separate_underwater: u32,
+    roughness: f32,
+    center: vec3<f32>,
+    pole: vec3<f32>,
+    sky_color: vec3<f32>,
+    rotation: mat3x3<f32>,
+    sun_direction: vec3<f32>,
+    sun_scale: f32,
+    sun_index: u32,
     #ifdef SIXTEEN_BYTE_ALIGNMENT
     _webgl2_padding: vec3<f32>,
     #endif
 };
 
+const PI: f32 = 3.14159265358979;
+
 @group(1) @binding(0) var<uniform> planet: PlanetMaterial;
-@group(1) @binding(1) var<storage, read> colors: array<ColorEntry>;
+@group(1) @binding(1) var color_lut_texture: texture_3d<f32>;
+@group(1) @binding(9) var color_lut_sampler: sampler;
+@group(1) @binding(10) var underwater_lut_texture: texture_3d<f32>;
 @group(1) @binding(2) var surface_normals_texture: texture_2d<f32>;
 @group(1) @binding(3) var surface_normals_sampler: sampler;
 
+struct TerrainLayer {
+    tint: vec3<f32>,
+    roughness: f32,
+    elevation: vec2<f32>,
+    steepness: vec2<f32>,
+    blend: f32,
+    triplanar_scale: f32,
+    normal_strength: f32,
+}
+
+@group(1) @binding(4) var<storage, read> layers: array<TerrainLayer>;
+@group(1) @binding(5) var layer_albedo_texture: texture_2d_array<f32>;
+@group(1) @binding(6) var layer_sampler: sampler;
+@group(1) @binding(7) var layer_normals_texture: texture_2d_array<f32>;
+@group(1) @binding(8) var layer_roughness_texture: texture_2d_array<f32>;
+
+struct CloudSettings {
+    center: vec3<f32>,
+    rotation: mat3x3<f32>,
+    inner_radius: f32,
+    outer_radius: f32,
+    surface_radius: f32,
+    coverage: f32,
+    density: f32,
+    noise_scale: f32,
+    detail_scale: f32,
+    shadow_strength: f32,
+    wind_offset: vec3<f32>,
+    detail_offset: vec3<f32>,
+    color: vec3<f32>,
+    num_steps: u32,
+    num_light_steps: u32,
+    enabled: u32,
+    sun_direction: vec3<f32>,
+    sun_scale: f32,
+    sun_index: u32,
+}
+
+@group(1) @binding(11) var<uniform> clouds: CloudSettings;
+
 fn inv_lerp(v: f32, a: f32, b: f32) -> f32 {
     return saturate((v - a) / (b - a));
 }
 
+// coords run from 0 to 1 between the centres of the first and last texels along each axis
+fn sample_color_lut(lut: texture_3d<f32>, coords: vec3<f32>) -> vec3<f32> {
+    let size = vec3<f32>(textureDimensions(lut));
+    let uvw = (saturate(coords) * (size - 1.0) + 0.5) / size;
+    return textureSample(lut, color_lut_sampler, uvw).rgb;
+}
+
 fn unpack_normal(normal: vec4<f32>) -> vec3<f32> {
     return normal.xyz * 2.0 - 0.5;
 }
 
+// the layer arrays are plain tangent space maps, with flat at (0.5, 0.5, 1)
+fn unpack_layer_normal(normal: vec4<f32>) -> vec3<f32> {
+    return normalize(normal.xyz * 2.0 - 1.0);
+}
+
 fn blend_rnm(a: vec3<f32>, b: vec3<f32>) -> vec3<f32> {
     var n1 = a;
     var n2 = b;
@@ -43,23 +110,18 @@ fn blend_rnm(a: vec3<f32>, b: vec3<f32>) -> vec3<f32> {
     return n1 * dot(n1, n2) / n1.z - n2;
 }
 
-fn triplanar_normal(pos: vec3<f32>, normal: vec3<f32>, scale: f32, offset: vec2<f32>, map_texture: texture_2d<f32>, map_sampler: sampler) -> vec3<f32> {
-    let abs_normal = abs(normal);
-
-    var blend_weight = saturate(normal * normal * normal * normal);
-    blend_weight /= dot(blend_weight, vec3(1.0));
-
-    let uv_x = pos.zy * scale + offset;
-    let uv_y = pos.xz * scale + offset;
-    let uv_z = pos.xy * scale + offset;
+fn triplanar_weights(normal: vec3<f32>) -> vec3<f32> {
+    let blend_weight = saturate(normal * normal * normal * normal);
+    return blend_weight / dot(blend_weight, vec3(1.0));
+}
 
-    var tan_normal_x = unpack_normal(textureSample(map_texture, map_sampler, fract(uv_x)));
-    var tan_normal_y = unpack_normal(textureSample(map_texture, map_sampler, fract(uv_y)));
-    var tan_normal_z = unpack_normal(textureSample(map_texture, map_sampler, fract(uv_z)));
+fn blend_triplanar_normals(normal: vec3<f32>, tangent_x: vec3<f32>, tangent_y: vec3<f32>, tangent_z: vec3<f32>) -> vec3<f32> {
+    let abs_normal = abs(normal);
+    let blend_weight = triplanar_weights(normal);
 
-    tan_normal_x = blend_rnm(vec3(normal.zy, abs_normal.x), tan_normal_x);
-    tan_normal_y = blend_rnm(vec3(normal.xz, abs_normal.y), tan_normal_y);
-    tan_normal_z = blend_rnm(vec3(normal.xy, abs_normal.z), tan_normal_z);
+    var tan_normal_x = blend_rnm(vec3(normal.zy, abs_normal.x), tangent_x);
+    var tan_normal_y = blend_rnm(vec3(normal.xz, abs_normal.y), tangent_y);
+    var tan_normal_z = blend_rnm(vec3(normal.xy, abs_normal.z), tangent_z);
 
     let axis_sign = sign(normal);
     tan_normal_x.z *= axis_sign.x;
@@ -73,6 +135,211 @@ fn triplanar_normal(pos: vec3<f32>, normal: vec3<f32>, scale: f32, offset: vec2<
     );
 }
 
+fn triplanar_normal(pos: vec3<f32>, normal: vec3<f32>, scale: f32, offset: vec2<f32>, map_texture: texture_2d<f32>, map_sampler: sampler) -> vec3<f32> {
+    let uv_x = pos.zy * scale + offset;
+    let uv_y = pos.xz * scale + offset;
+    let uv_z = pos.xy * scale + offset;
+
+    return blend_triplanar_normals(
+        normal,
+        unpack_normal(textureSample(map_texture, map_sampler, fract(uv_x))),
+        unpack_normal(textureSample(map_texture, map_sampler, fract(uv_y))),
+        unpack_normal(textureSample(map_texture, map_sampler, fract(uv_z))),
+    );
+}
+
+// the layer arrays repeat, so these don't need to wrap their uvs
+fn triplanar_sample_layer(pos: vec3<f32>, normal: vec3<f32>, scale: f32, layer: u32, map_texture: texture_2d_array<f32>) -> vec4<f32> {
+    let blend_weight = triplanar_weights(normal);
+    return textureSample(map_texture, layer_sampler, pos.zy * scale, layer) * blend_weight.x +
+        textureSample(map_texture, layer_sampler, pos.xz * scale, layer) * blend_weight.y +
+        textureSample(map_texture, layer_sampler, pos.xy * scale, layer) * blend_weight.z;
+}
+
+fn triplanar_normal_layer(pos: vec3<f32>, normal: vec3<f32>, scale: f32, layer: u32) -> vec3<f32> {
+    return blend_triplanar_normals(
+        normal,
+        unpack_layer_normal(textureSample(layer_normals_texture, layer_sampler, pos.zy * scale, layer)),
+        unpack_layer_normal(textureSample(layer_normals_texture, layer_sampler, pos.xz * scale, layer)),
+        unpack_layer_normal(textureSample(layer_normals_texture, layer_sampler, pos.xy * scale, layer)),
+    );
+}
+
+// 1 inside the band, fading to 0 over `blend` past either end of it
+fn band_weight(value: f32, band: vec2<f32>, blend: f32) -> f32 {
+    let width = max(blend, 0.0001);
+    return smoothstep(band.x - width, band.x, value) * (1.0 - smoothstep(band.y, band.y + width, value));
+}
+
+fn ray_sphere_intersection(center: vec3<f32>, radius: f32, ro: vec3<f32>, rd: vec3<f32>) -> vec2<f32> {
+    let offset = ro - center;
+    let a = dot(rd, rd);
+    let half_b = dot(offset, rd);
+    let c = dot(offset, offset) - radius * radius;
+
+    let discriminant = half_b * half_b - a * c;
+    if (discriminant >= 0.0) {
+        let s = sqrt(discriminant);
+        let dst_near = max(0.0, (-half_b - s) / a);
+        let dst_far = (-half_b + s) / a;
+
+        if (dst_far >= 0.0) {
+            return vec2(dst_near, dst_far - dst_near);
+        }
+    }
+    return vec2(10000000000000.0, 0.0);
+}
+
+// the same cloud density as clouds.wgsl, so the shadows line up with the clouds drawn above them
+fn hash(p: vec3<f32>) -> f32 {
+    let q = fract(p * 0.3183099 + 0.1) * 17.0;
+    return fract(q.x * q.y * q.z * (q.x + q.y + q.z));
+}
+
+fn value_noise(p: vec3<f32>) -> f32 {
+    let i = floor(p);
+    let f = fract(p);
+    let u = f * f * (3.0 - 2.0 * f);
+
+    return mix(
+        mix(
+            mix(hash(i), hash(i + vec3(1.0, 0.0, 0.0)), u.x),
+            mix(hash(i + vec3(0.0, 1.0, 0.0)), hash(i + vec3(1.0, 1.0, 0.0)), u.x),
+            u.y,
+        ),
+        mix(
+            mix(hash(i + vec3(0.0, 0.0, 1.0)), hash(i + vec3(1.0, 0.0, 1.0)), u.x),
+            mix(hash(i + vec3(0.0, 1.0, 1.0)), hash(i + vec3(1.0, 1.0, 1.0)), u.x),
+            u.y,
+        ),
+        u.z,
+    );
+}
+
+fn fbm(p: vec3<f32>, octaves: u32) -> f32 {
+    var sum = 0.0;
+    var amplitude = 0.5;
+    var total = 0.0;
+    var q = p;
+    for (var i = 0u; i < octaves; i++) {
+        sum += value_noise(q) * amplitude;
+        total += amplitude;
+        amplitude *= 0.5;
+        q *= 2.03;
+    }
+    return sum / total;
+}
+
+fn cloud_density(pos: vec3<f32>) -> f32 {
+    let local_position = (pos - clouds.center) * clouds.rotation;
+    let height = (length(local_position) - clouds.inner_radius) / (clouds.outer_radius - clouds.inner_radius);
+    let profile = smoothstep(0.0, 0.15, height) * (1.0 - smoothstep(0.5, 1.0, height));
+
+    let base = fbm(local_position * clouds.noise_scale + clouds.wind_offset, 4u);
+    let shape = saturate((base - (1.0 - clouds.coverage)) / max(clouds.coverage, 0.01)) * profile;
+    let detail = fbm(local_position * clouds.detail_scale + clouds.detail_offset, 2u);
+    return saturate(shape - detail * 0.35 * (1.0 - shape)) * clouds.density;
+}
+
+// how much light gets through the cloud layer on its way down to a point. the density is only
+// looked up once, halfway through the layer, as the shadows are soft enough not to need more
+fn cloud_shadow(pos: vec3<f32>, dir_to_light: vec3<f32>) -> f32 {
+    if (clouds.enabled == 0u) {
+        return 1.0;
+    }
+
+    let inner_hit = ray_sphere_intersection(clouds.center, clouds.inner_radius, pos, dir_to_light);
+    let outer_hit = ray_sphere_intersection(clouds.center, clouds.outer_radius, pos, dir_to_light);
+    // peaks can reach up into the layer, where the ray starts in the clouds straight away
+    let start = select(0.0, inner_hit.x + inner_hit.y, length(pos - clouds.center) < clouds.inner_radius);
+    let end = outer_hit.x + outer_hit.y;
+    if (outer_hit.y <= 0.0 || end <= start) {
+        return 1.0;
+    }
+
+    let optical_depth = cloud_density(pos + dir_to_light * (start + end) * 0.5) * (end - start);
+    return mix(1.0, exp(-optical_depth), clouds.shadow_strength);
+}
+
+// the sun shines on each planet from along its own orbit, so that replaces the light standing in for
+// it. its shadow maps were cast the other way, so aren't used
+fn light_direction(i: u32) -> vec3<f32> {
+    return select(view_bindings::lights.directional_lights[i].direction_to_light, planet.sun_direction, i == planet.sun_index);
+}
+
+fn light_color(i: u32) -> vec3<f32> {
+    return view_bindings::lights.directional_lights[i].color.rgb * select(1.0, planet.sun_scale, i == planet.sun_index);
+}
+
+// `lighting::directional_light`, for light of `light_color` arriving from `incident_light`
+fn directional_light(incident_light: vec3<f32>, light_color: vec3<f32>, roughness: f32, NdotV: f32, normal: vec3<f32>, view: vec3<f32>, F0: vec3<f32>, f_ab: vec2<f32>, diffuse_color: vec3<f32>) -> vec3<f32> {
+    let half_vector = normalize(incident_light + view);
+    let NoL = saturate(dot(normal, incident_light));
+    let NoH = saturate(dot(normal, half_vector));
+    let LoH = saturate(dot(incident_light, half_vector));
+
+    let diffuse = diffuse_color * lighting::Fd_Burley(roughness, NdotV, NoL, LoH);
+    let specular_light = lighting::specular(F0, roughness, half_vector, NdotV, NoL, NoH, LoH, 1.0, f_ab);
+    return (specular_light + diffuse) * light_color * NoL;
+}
+
+// the standard material's lighting, except each sun's light is dimmed by the clouds it comes through
+fn surface_light(in: pbr_functions::PbrInput, view_z: f32) -> vec3<f32> {
+    let perceptual_roughness = in.material.perceptual_roughness;
+    let roughness = lighting::perceptualRoughnessToRoughness(perceptual_roughness);
+    let NdotV = max(dot(in.N, in.V), 0.0001);
+    let reflectance = in.material.reflectance;
+    let F0 = vec3(0.16 * reflectance * reflectance);
+    let diffuse_color = in.material.base_color.rgb;
+    let R = reflect(-in.V, in.N);
+    let f_ab = lighting::F_AB(perceptual_roughness, NdotV);
+    let receives_shadows = (in.flags & MESH_FLAGS_SHADOW_RECEIVER_BIT) != 0u;
+
+    var light = vec3(0.0);
+
+    let cluster_index = clustering::fragment_cluster_index(in.frag_coord.xy, view_z, in.is_orthographic);
+    let offset_and_counts = clustering::unpack_offset_and_counts(cluster_index);
+    let point_lights_end = offset_and_counts[0] + offset_and_counts[1];
+    for (var i = offset_and_counts[0]; i < point_lights_end; i++) {
+        let light_id = clustering::get_light_id(i);
+        var shadow = 1.0;
+        if (receives_shadows && (view_bindings::point_lights.data[light_id].flags & POINT_LIGHT_FLAGS_SHADOWS_ENABLED_BIT) != 0u) {
+            shadow = shadows::fetch_point_shadow(light_id, in.world_position, in.world_normal);
+        }
+        light += lighting::point_light(in.world_position.xyz, light_id, roughness, NdotV, in.N, in.V, R, F0, f_ab, diffuse_color) * shadow;
+    }
+    for (var i = point_lights_end; i < point_lights_end + offset_and_counts[2]; i++) {
+        let light_id = clustering::get_light_id(i);
+        var shadow = 1.0;
+        if (receives_shadows && (view_bindings::point_lights.data[light_id].flags & POINT_LIGHT_FLAGS_SHADOWS_ENABLED_BIT) != 0u) {
+            shadow = shadows::fetch_spot_shadow(light_id, in.world_position, in.world_normal);
+        }
+        light += lighting::spot_light(in.world_position.xyz, light_id, roughness, NdotV, in.N, in.V, R, F0, f_ab, diffuse_color) * shadow;
+    }
+
+    for (var i = 0u; i < view_bindings::lights.n_directional_lights; i++) {
+        let direction = light_direction(i);
+        var shadow = cloud_shadow(in.world_position.xyz, direction);
+        if (receives_shadows && i != planet.sun_index
+                && (view_bindings::lights.directional_lights[i].flags & DIRECTIONAL_LIGHT_FLAGS_SHADOWS_ENABLED_BIT) != 0u) {
+            shadow *= shadows::fetch_directional_shadow(i, in.world_position, in.world_normal, view_z);
+        }
+        light += directional_light(direction, light_color(i) * shadow, roughness, NdotV, in.N, in.V, F0, f_ab, diffuse_color);
+    }
+
+    return light + ambient::ambient_light(in.world_position, in.N, in.V, NdotV, diffuse_color, F0, perceptual_roughness, in.occlusion);
+}
+
+// light scattered down by the atmosphere, bright on the day side and fading out past the terminator
+fn sky_light(up: vec3<f32>, normal: vec3<f32>) -> vec3<f32> {
+    var light = vec3(0.0);
+    for (var i = 0u; i < view_bindings::lights.n_directional_lights; i++) {
+        let daylight = smoothstep(-0.2, 0.3, dot(up, light_direction(i)));
+        light += light_color(i) * daylight;
+    }
+    // the sky only covers the upper hemisphere
+    return light * planet.sky_color * (0.5 + 0.5 * dot(normal, up));
+}
 
 @fragment
 fn fragment(in: MeshVertexOutput) -> @location(0) vec4<f32> {
@@ -83,37 +350,76 @@ fn fragment(in: MeshVertexOutput) -> @location(0) vec4<f32> {
         view_bindings::view.inverse_view[3].z,
     ), in.world_position);
 
-    let elevation = length(in.world_position.xyz);
+    let local_position = in.world_position.xyz - planet.center;
+    let elevation = length(local_position);
     let norm_elevation = inv_lerp(elevation, planet.min_elevation, planet.max_elevation);
 
-    let local_up = in.world_position.xyz / elevation;
+    let local_up = local_position / elevation;
     let steepness = 1.0 - dot(in.world_normal, local_up);
 
-    let color_pos = vec2(norm_elevation, steepness);
-    var planet_col = vec3(0.0);
-    var amount = 0.0;
-    for (var i = 0u; i < planet.n_colors; i++) {
-        let col_entry = colors[i];
-        let position = vec2(col_entry.elevation, col_entry.steepness);
-        let dist = distance(color_pos, position) / 1.5 + 0.000001;
-        let gauss_dist = 1.0 - exp(-dist * dist * 10.0);
-        let strength = 1.0 - min(0.000001, 1.0 * log(gauss_dist)) - 1.0;
-
-        planet_col = (col_entry.color * strength + planet_col * amount) / (amount + strength);
-        amount += strength;
-    }
+    let latitude = asin(min(abs(dot(local_up, planet.pole)), 1.0)) / (PI / 2.0);
+    // with a separate seabed, land is keyed on height above sea level and the seabed on depth below it
+    let separate_underwater = planet.separate_underwater != 0u;
+    let land_elevation = select(norm_elevation, inv_lerp(elevation, planet.sea_level, planet.max_elevation), separate_underwater);
+    let depth = inv_lerp(elevation, planet.sea_level, planet.min_elevation);
+    let land_col = sample_color_lut(color_lut_texture, vec3(land_elevation, steepness, latitude));
+    let underwater_col = sample_color_lut(underwater_lut_texture, vec3(depth, steepness, latitude));
+    var planet_col = select(land_col, underwater_col, separate_underwater && elevation < planet.sea_level);
+
+    // textures are projected in the planet's own frame, so they turn with it instead of sliding over it
+    let planet_position = local_position * planet.rotation;
+    let planet_normal = in.world_normal * planet.rotation;
 
     var surface_normal = in.world_normal.xyz;
-    let surface_bumps = triplanar_normal(in.world_position.xyz, surface_normal, planet.normal_scale, vec2(0.0), surface_normals_texture, surface_normals_sampler);
+    let surface_bumps = planet.rotation * triplanar_normal(planet_position, planet_normal, planet.normal_scale, vec2(0.0), surface_normals_texture, surface_normals_sampler);
     surface_normal = normalize(mix(surface_normal, surface_bumps, planet.normal_strength));
 
-    for (var i = 0u; i < view_bindings::lights.n_directional_lights; i++) {
-        let directional_light = view_bindings::lights.directional_lights[i];
-        let to_light = directional_light.direction_to_light;
-        let shadow = shadows::fetch_directional_shadow(i, in.world_position, surface_normal, view_z);
-        let diffuse = saturate(dot(surface_normal, to_light));
-        planet_col *= diffuse * shadow;
+    // paint each terrain layer over the gradient and those before it
+    var roughness = planet.roughness;
+    for (var i = 0u; i < planet.n_layers; i++) {
+        let layer = layers[i];
+        let weight = band_weight(norm_elevation, layer.elevation, layer.blend) * band_weight(steepness, layer.steepness, layer.blend);
+
+        let albedo = triplanar_sample_layer(planet_position, planet_normal, layer.triplanar_scale, i, layer_albedo_texture).rgb * layer.tint;
+        let layer_roughness = triplanar_sample_layer(planet_position, planet_normal, layer.triplanar_scale, i, layer_roughness_texture).r * layer.roughness;
+        let layer_bumps = planet.rotation * triplanar_normal_layer(planet_position, planet_normal, layer.triplanar_scale, i);
+        let layer_normal = normalize(mix(in.world_normal, layer_bumps, layer.normal_strength));
+
+        planet_col = mix(planet_col, albedo, weight);
+        roughness = mix(roughness, layer_roughness, weight);
+        surface_normal = normalize(mix(surface_normal, layer_normal, weight));
     }
 
-    return vec4(planet_col, 1.0);
+    // light it the same way as the standard material, so it sits in the scene like any other mesh
+    var pbr_input = pbr_functions::pbr_input_new();
+    pbr_input.material.base_color = vec4(planet_col, 1.0);
+    pbr_input.material.perceptual_roughness = clamp(roughness, 0.089, 1.0);
+    pbr_input.material.metallic = 0.0;
+    pbr_input.frag_coord = in.position;
+    pbr_input.world_position = in.world_position;
+    pbr_input.world_normal = in.world_normal;
+    pbr_input.N = surface_normal;
+    pbr_input.is_orthographic = view_bindings::view.projection[3].w == 1.0;
+    pbr_input.V = pbr_functions::calculate_view(in.world_position, pbr_input.is_orthographic);
+    pbr_input.flags = mesh.flags;
+
+    let lit = surface_light(pbr_input, view_z) + planet_col * sky_light(local_up, surface_normal);
+    var output_color = vec4(lit, 1.0);
+
+    if (view_bindings::fog.mode != FOG_MODE_OFF) {
+        output_color = pbr_functions::apply_fog(view_bindings::fog, output_color, in.world_position.xyz, view_bindings::view.world_position.xyz);
+    }
+
+#ifdef TONEMAP_IN_SHADER
+    output_color = tone_mapping(output_color, view_bindings::view.color_grading);
+#ifdef DEBAND_DITHER
+    var output_rgb = output_color.rgb;
+    output_rgb = powsafe(output_rgb, 1.0 / 2.2);
+    output_rgb = output_rgb + screen_space_dither(in.position.xy);
+    output_rgb = powsafe(output_rgb, 2.2);
+    output_color = vec4(output_rgb, output_color.a);
+#endif
+#endif
+
+    return output_color;
 }
\ No newline at end of file
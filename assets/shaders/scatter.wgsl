@@ -0,0 +1,75 @@
+#import bevy_pbr::mesh_functions as mesh_functions
+#import bevy_pbr::mesh_bindings mesh
+#import bevy_pbr::mesh_view_bindings as view_bindings
+#import bevy_pbr::pbr_functions as pbr_functions
+#import bevy_pbr::mesh_view_types FOG_MODE_OFF
+#import bevy_core_pipeline::tonemapping screen_space_dither, powsafe, tone_mapping
+
+struct Vertex {
+    @location(0) position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+    @location(5) color: vec4<f32>,
+
+    // per instance, the transform relative to the planet and a tint
+    @location(8) i_transform_0: vec4<f32>,
+    @location(9) i_transform_1: vec4<f32>,
+    @location(10) i_transform_2: vec4<f32>,
+    @location(11) i_transform_3: vec4<f32>,
+    @location(12) i_color: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) world_position: vec4<f32>,
+    @location(1) world_normal: vec3<f32>,
+    @location(2) color: vec4<f32>,
+};
+
+@vertex
+fn vertex(vertex: Vertex) -> VertexOutput {
+    let instance = mat4x4<f32>(vertex.i_transform_0, vertex.i_transform_1, vertex.i_transform_2, vertex.i_transform_3);
+    let model = mesh.model * instance;
+
+    var out: VertexOutput;
+    out.world_position = mesh_functions::mesh_position_local_to_world(model, vec4(vertex.position, 1.0));
+    out.clip_position = mesh_functions::mesh_position_world_to_clip(out.world_position);
+    // instances are only ever scaled uniformly, so the model matrix works for normals too
+    out.world_normal = normalize((model * vec4(vertex.normal, 0.0)).xyz);
+    out.color = vertex.color * vertex.i_color;
+    return out;
+}
+
+@fragment
+fn fragment(in: VertexOutput) -> @location(0) vec4<f32> {
+    // lit like the terrain they stand on, by every light and the ambient light
+    var pbr_input = pbr_functions::pbr_input_new();
+    pbr_input.material.base_color = vec4(in.color.rgb, 1.0);
+    pbr_input.material.perceptual_roughness = 0.8;
+    pbr_input.material.metallic = 0.0;
+    pbr_input.frag_coord = in.clip_position;
+    pbr_input.world_position = in.world_position;
+    pbr_input.world_normal = in.world_normal;
+    pbr_input.N = in.world_normal;
+    pbr_input.is_orthographic = view_bindings::view.projection[3].w == 1.0;
+    pbr_input.V = pbr_functions::calculate_view(in.world_position, pbr_input.is_orthographic);
+    pbr_input.flags = mesh.flags;
+
+    var output_color = pbr_functions::pbr(pbr_input);
+
+    if (view_bindings::fog.mode != FOG_MODE_OFF) {
+        output_color = pbr_functions::apply_fog(view_bindings::fog, output_color, in.world_position.xyz, view_bindings::view.world_position.xyz);
+    }
+
+#ifdef TONEMAP_IN_SHADER
+    output_color = tone_mapping(output_color, view_bindings::view.color_grading);
+#ifdef DEBAND_DITHER
+    var output_rgb = output_color.rgb;
+    output_rgb = powsafe(output_rgb, 1.0 / 2.2);
+    output_rgb = output_rgb + screen_space_dither(in.clip_position.xy);
+    output_rgb = powsafe(output_rgb, 2.2);
+    output_color = vec4(output_rgb, output_color.a);
+#endif
+#endif
+
+    return vec4(output_color.rgb, 1.0);
+}
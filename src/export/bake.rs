@@ -0,0 +1,132 @@
+use std::{io, path::Path};
+
+use bevy::prelude::*;
+use image::{ImageFormat, RgbImage};
+use serde::{Serialize, Deserialize};
+
+use crate::{gen::{shape::ShapeGenerator, heightmap::{HeightmapProjection, suffixed_path}}, render::planet_mat::normalized_latitude, ui::color::UiColorSettings};
+
+use super::heightmap::ProjectionImage;
+
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum NormalSpace {
+    /// Relative to the image axes and the sphere's surface, for meshes uv mapped with the same projection.
+    #[default]
+    Tangent,
+    /// The planet-space normal, independent of how the texture is mapped.
+    Object,
+}
+
+impl NormalSpace {
+    pub const ALL: [NormalSpace; 2] = [NormalSpace::Tangent, NormalSpace::Object];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            NormalSpace::Tangent => "Tangent Space",
+            NormalSpace::Object => "Object Space",
+        }
+    }
+}
+
+pub struct BakeOptions {
+    pub projection: HeightmapProjection,
+    pub resolution: u32,
+    pub normal_space: NormalSpace,
+}
+
+/// Albedo and normal map for one image of the projection.
+pub struct BakedTextures {
+    pub suffix: &'static str,
+    pub albedo: RgbImage,
+    pub normal: RgbImage,
+}
+
+#[derive(Clone, Copy, Default)]
+struct SurfaceSample {
+    elevation: f32,
+    normal: Vec3,
+    /// Image right, image up and outwards from the sphere at this pixel.
+    tangent_frame: Mat3,
+}
+
+fn sample_surface(image: &ProjectionImage, uv: Vec2, direction: Vec3, shape_gen: &ShapeGenerator) -> SurfaceSample {
+    let epsilon = image.pixel_angle();
+    let pixel = Vec2::new(1.0 / image.width as f32, 1.0 / image.height as f32);
+
+    // image rows go down, so up along the image is towards smaller v
+    let right = image.direction(uv + Vec2::new(pixel.x, 0.0)) - image.direction(uv - Vec2::new(pixel.x, 0.0));
+    let up = image.direction(uv - Vec2::new(0.0, pixel.y)) - image.direction(uv + Vec2::new(0.0, pixel.y));
+    let tangent = right.reject_from(direction).normalize_or_zero();
+    let bitangent = direction.cross(tangent);
+    let bitangent = if bitangent.dot(up) < 0.0 { -bitangent } else { bitangent };
+
+    SurfaceSample {
+        elevation: shape_gen.get_elevation(direction),
+        normal: shape_gen.get_normal(direction, epsilon),
+        tangent_frame: Mat3::from_cols(tangent, bitangent, direction),
+    }
+}
+
+fn to_srgb_pixel(color: [f32; 3]) -> [u8; 3] {
+    let [r, g, b, _] = Color::rgb_linear(color[0], color[1], color[2]).as_rgba_u8();
+    [r, g, b]
+}
+
+/// Evaluates the same elevation, depth and steepness color blend as `planet.wgsl` over every pixel of the projection.
+pub fn bake_textures(
+    shape_gen: &ShapeGenerator,
+    colors: &UiColorSettings,
+    options: &BakeOptions,
+) -> Vec<BakedTextures> {
+    let images = options.projection.images(options.resolution);
+    let samples: Vec<Vec<SurfaceSample>> = images.iter()
+        .map(|image| image.sample(|uv, direction| sample_surface(image, uv, direction, shape_gen)))
+        .collect();
+
+    let (min_elevation, max_elevation) = samples.iter().flatten()
+        .fold((f32::MAX, f32::MIN), |(min, max), x| (min.min(x.elevation), max.max(x.elevation)));
+
+    images.iter().zip(samples.iter()).map(|(image, samples)| {
+        let mut albedo = RgbImage::new(image.width, image.height);
+        let mut normal = RgbImage::new(image.width, image.height);
+
+        for ((sample, albedo_pixel), normal_pixel) in samples.iter().zip(albedo.pixels_mut()).zip(normal.pixels_mut()) {
+            let steepness = 1.0 - sample.normal.dot(sample.tangent_frame.z_axis);
+            let latitude = normalized_latitude(sample.tangent_frame.z_axis);
+            albedo_pixel.0 = to_srgb_pixel(colors.sample(sample.elevation, (min_elevation, max_elevation), shape_gen.sea_level, steepness, latitude));
+
+            let encoded_normal = match options.normal_space {
+                NormalSpace::Tangent => sample.tangent_frame.transpose() * sample.normal,
+                NormalSpace::Object => sample.normal,
+            };
+            // normal maps are stored linearly
+            normal_pixel.0 = (encoded_normal * 0.5 + 0.5).to_array().map(|x| (x.clamp(0.0, 1.0) * 255.0).round() as u8);
+        }
+
+        BakedTextures {
+            suffix: image.suffix,
+            albedo,
+            normal,
+        }
+    }).collect()
+}
+
+/// Bakes the textures and writes them to `{base_path}_albedo.png` and `{base_path}_normal.png`, with the
+/// face appended for cubemaps.
+pub fn export_textures(
+    base_path: impl AsRef<Path>,
+    shape_gen: &ShapeGenerator,
+    colors: &UiColorSettings,
+    options: &BakeOptions,
+) -> io::Result<()> {
+    let base_path = base_path.as_ref();
+
+    for textures in bake_textures(shape_gen, colors, options) {
+        textures.albedo.save_with_format(suffixed_path(base_path, &["albedo", textures.suffix], "png"), ImageFormat::Png)
+            .map_err(io::Error::other)?;
+        textures.normal.save_with_format(suffixed_path(base_path, &["normal", textures.suffix], "png"), ImageFormat::Png)
+            .map_err(io::Error::other)?;
+    }
+    Ok(())
+}
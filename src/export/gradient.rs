@@ -0,0 +1,268 @@
+use std::{io, fmt::Write, path::Path};
+
+use bevy::prelude::*;
+use image::RgbImage;
+
+use crate::render::planet_mat::{ColorGradient, ColorInterpolation, KeyEasing};
+
+
+/// Width of an exported strip image, one pixel per step along elevation.
+const STRIP_WIDTH: u32 = 256;
+const STRIP_HEIGHT: u32 = 16;
+
+/// The kinds of file a gradient can be read from and written to, picked by extension.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum GradientFormat {
+    /// A GIMP gradient, with one segment between each pair of keys.
+    Ggr,
+    /// One key per row, optionally with a header naming the columns.
+    Csv,
+    /// An image whose middle row runs from the lowest to the highest elevation.
+    Strip,
+}
+
+impl GradientFormat {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "ggr" => Some(GradientFormat::Ggr),
+            "csv" => Some(GradientFormat::Csv),
+            "png" | "tif" | "tiff" => Some(GradientFormat::Strip),
+            _ => None,
+        }
+    }
+}
+
+fn unsupported_format(path: &Path) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, format!("{} isn't a .ggr, .csv or image file", path.display()))
+}
+
+fn invalid_data(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+// gradient files store gamma encoded colors, while keys are linear
+fn srgb_to_linear(color: [f32; 3]) -> [f32; 3] {
+    let [r, g, b, _] = Color::rgb(color[0], color[1], color[2]).as_linear_rgba_f32();
+    [r, g, b]
+}
+
+fn linear_to_srgb(color: [f32; 3]) -> [f32; 3] {
+    let [r, g, b, _] = Color::rgb_linear(color[0], color[1], color[2]).as_rgba_f32();
+    [r, g, b]
+}
+
+
+/// Reads a gradient as keys along elevation. Strip images are sampled at `strip_keys` evenly spaced points.
+pub fn import_gradient(path: &Path, strip_keys: u32) -> io::Result<ColorGradient> {
+    match GradientFormat::from_path(path).ok_or_else(|| unsupported_format(path))? {
+        GradientFormat::Ggr => parse_ggr(&std::fs::read_to_string(path)?),
+        GradientFormat::Csv => parse_csv(&std::fs::read_to_string(path)?),
+        GradientFormat::Strip => {
+            let image = image::open(path).map_err(io::Error::other)?.to_rgb8();
+            Ok(sample_strip(&image, strip_keys.max(2)))
+        }
+    }
+}
+
+/// Writes the gradient's enabled keys along elevation. Steepness and latitude only survive in csv files.
+pub fn export_gradient(gradient: &ColorGradient, path: &Path) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    match GradientFormat::from_path(path).ok_or_else(|| unsupported_format(path))? {
+        GradientFormat::Ggr => std::fs::write(path, write_ggr(gradient, &path.file_stem().unwrap_or_default().to_string_lossy())),
+        GradientFormat::Csv => std::fs::write(path, write_csv(gradient)),
+        GradientFormat::Strip => {
+            let keys = gradient.sorted();
+            let mut image = RgbImage::new(STRIP_WIDTH, STRIP_HEIGHT);
+            for (x, _, pixel) in image.enumerate_pixels_mut() {
+                let color = linear_to_srgb(gradient.evaluate(x as f32 / (STRIP_WIDTH - 1) as f32, &keys));
+                pixel.0 = color.map(|x| (x.clamp(0.0, 1.0) * 255.0).round() as u8);
+            }
+            image.save(path).map_err(io::Error::other)
+        }
+    }
+}
+
+
+fn ggr_easing(blend_type: u32) -> KeyEasing {
+    match blend_type {
+        1 | 4 => KeyEasing::EaseIn,
+        2 => KeyEasing::EaseInOut,
+        3 => KeyEasing::EaseOut,
+        5 => KeyEasing::Step,
+        _ => KeyEasing::Linear,
+    }
+}
+
+fn ggr_blend_type(easing: KeyEasing) -> u32 {
+    match easing {
+        KeyEasing::Linear => 0,
+        KeyEasing::EaseIn => 4,
+        KeyEasing::EaseOut => 3,
+        KeyEasing::EaseInOut => 2,
+        KeyEasing::Step => 5,
+    }
+}
+
+/// Each segment becomes a key at its left end, plus one for the right end of the last. Where a
+/// segment doesn't start on the color the one before it ended on, its own color wins.
+fn parse_ggr(contents: &str) -> io::Result<ColorGradient> {
+    let mut lines = contents.lines().map(str::trim).filter(|line| !line.is_empty());
+    if lines.next() != Some("GIMP Gradient") {
+        return Err(invalid_data("missing the GIMP Gradient header"));
+    }
+
+    let mut line = lines.next().ok_or_else(|| invalid_data("missing the segment count"))?;
+    if line.starts_with("Name:") {
+        line = lines.next().ok_or_else(|| invalid_data("missing the segment count"))?;
+    }
+    let count: usize = line.parse().map_err(|_| invalid_data(format!("invalid segment count {}", line)))?;
+
+    let mut keys = Vec::with_capacity(count + 1);
+    let mut all_hsv = count > 0;
+    for _ in 0..count {
+        let line = lines.next().ok_or_else(|| invalid_data(format!("expected {} segments", count)))?;
+        let values: Vec<f32> = line.split_whitespace()
+            .map(|x| x.parse().map_err(|_| invalid_data(format!("invalid segment {}", line))))
+            .collect::<io::Result<_>>()?;
+        if values.len() < 13 {
+            return Err(invalid_data(format!("invalid segment {}", line)));
+        }
+
+        let (left, right) = (values[0], values[2]);
+        let left_color = srgb_to_linear([values[3], values[4], values[5]]);
+        let right_color = srgb_to_linear([values[7], values[8], values[9]]);
+        all_hsv &= values[12] as u32 != 0;
+
+        keys.truncate(keys.len().saturating_sub(1));
+        keys.push((left, left_color, ggr_easing(values[11] as u32)));
+        keys.push((right, right_color, KeyEasing::Linear));
+    }
+
+    let interpolation = if all_hsv { ColorInterpolation::Hsv } else { ColorInterpolation::Rgb };
+    Ok(ColorGradient::from_keys(keys, interpolation))
+}
+
+fn write_ggr(gradient: &ColorGradient, name: &str) -> String {
+    let mut keys = gradient.sorted();
+    if keys.is_empty() {
+        keys.push((0.0, [0.0; 3], KeyEasing::Linear));
+    }
+
+    // gimp gradients have to cover the whole range, so hold the end colors out to it
+    let first = keys[0];
+    if first.0 > 0.0 {
+        keys.insert(0, (0.0, first.1, KeyEasing::Linear));
+    }
+    let last = keys[keys.len() - 1];
+    if last.0 < 1.0 {
+        keys.push((1.0, last.1, KeyEasing::Linear));
+    }
+
+    let color_type = if gradient.interpolation == ColorInterpolation::Hsv { 1 } else { 0 };
+    let mut contents = format!("GIMP Gradient\nName: {}\n{}\n", name, keys.len() - 1);
+    for window in keys.windows(2) {
+        let (start, end) = (window[0], window[1]);
+        let [r0, g0, b0] = linear_to_srgb(start.1);
+        let [r1, g1, b1] = linear_to_srgb(end.1);
+        let _ = writeln!(
+            contents,
+            "{:.6} {:.6} {:.6} {:.6} {:.6} {:.6} 1.000000 {:.6} {:.6} {:.6} 1.000000 {} {}",
+            start.0, (start.0 + end.0) * 0.5, end.0, r0, g0, b0, r1, g1, b1, ggr_blend_type(start.2), color_type,
+        );
+    }
+    contents
+}
+
+
+fn parse_hex_color(value: &str) -> Option<[f32; 3]> {
+    let hex = value.strip_prefix('#')?;
+    if hex.len() != 6 {
+        return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok().map(|x| x as f32 / 255.0);
+    Some([channel(0)?, channel(2)?, channel(4)?])
+}
+
+/// Rows are `position,r,g,b` or `position,#rrggbb` unless a header names the columns, out of
+/// `elevation` (or `position`), `steepness`, `latitude`, `red`, `green`, `blue`, `color` and `easing`.
+/// Channels above 1 are taken to be out of 255.
+fn parse_csv(contents: &str) -> io::Result<ColorGradient> {
+    let mut rows = contents.lines().map(str::trim).filter(|line| !line.is_empty()).peekable();
+
+    let header: Vec<String> = match rows.peek() {
+        Some(first) if first.split(',').next().is_some_and(|x| x.trim().parse::<f32>().is_err()) => {
+            rows.next().unwrap().split(',').map(|x| x.trim().to_ascii_lowercase()).collect()
+        }
+        _ => Vec::new(),
+    };
+    let column = |names: &[&str], default: Option<usize>| {
+        if header.is_empty() { default } else { header.iter().position(|x| names.contains(&x.as_str())) }
+    };
+    let elevation_column = column(&["elevation", "position"], Some(0));
+    let steepness_column = column(&["steepness"], None);
+    let latitude_column = column(&["latitude"], None);
+    let channel_columns = [column(&["red", "r"], Some(1)), column(&["green", "g"], Some(2)), column(&["blue", "b"], Some(3))];
+    let hex_column = column(&["color", "hex"], Some(1));
+    let easing_column = column(&["easing"], None);
+
+    let mut gradient = ColorGradient::from_keys([], ColorInterpolation::Rgb);
+    for row in rows {
+        let fields: Vec<&str> = row.split(',').map(str::trim).collect();
+        let number = |column: Option<usize>| column.and_then(|i| fields.get(i)).and_then(|x| x.parse::<f32>().ok());
+
+        let Some(elevation) = number(elevation_column) else {
+            return Err(invalid_data(format!("invalid row {}", row)));
+        };
+        let color = match hex_column.and_then(|i| fields.get(i)).and_then(|x| parse_hex_color(x)) {
+            Some(color) => color,
+            None => {
+                let channels = channel_columns.map(number);
+                let [Some(r), Some(g), Some(b)] = channels else {
+                    return Err(invalid_data(format!("invalid color in row {}", row)));
+                };
+                let scale = if r > 1.0 || g > 1.0 || b > 1.0 { 255.0 } else { 1.0 };
+                [r / scale, g / scale, b / scale]
+            }
+        };
+
+        gradient.add(srgb_to_linear(color), elevation, number(steepness_column).unwrap_or(0.0));
+        let id = gradient.len() - 1;
+        if let Some(latitude) = number(latitude_column) {
+            *gradient.get_latitude_mut(id) = latitude;
+            gradient.latitude_axis = true;
+        }
+        if let Some(name) = easing_column.and_then(|i| fields.get(i)) {
+            *gradient.get_easing_mut(id) = KeyEasing::ALL.into_iter().find(|x| x.name().eq_ignore_ascii_case(name)).unwrap_or_default();
+        }
+    }
+    Ok(gradient)
+}
+
+fn write_csv(gradient: &ColorGradient) -> String {
+    // a latitude column turns the latitude axis on when it's read back in
+    let latitude_header = if gradient.latitude_axis { "latitude," } else { "" };
+    let mut contents = format!("elevation,steepness,{}red,green,blue,easing\n", latitude_header);
+    for id in 0..gradient.len() {
+        if !gradient.get_enabled(id) { continue };
+        let (u, v, color, _) = *gradient.get(id);
+        let latitude = if gradient.latitude_axis { format!("{},", gradient.get_latitude(id)) } else { String::new() };
+        let [r, g, b] = linear_to_srgb(color);
+        let _ = writeln!(contents, "{},{},{}{},{},{},{}", u, v, latitude, r, g, b, gradient.get_easing(id).name());
+    }
+    contents
+}
+
+
+fn sample_strip(image: &RgbImage, keys: u32) -> ColorGradient {
+    let y = image.height() / 2;
+    ColorGradient::from_keys((0..keys).map(|i| {
+        let t = i as f32 / (keys - 1) as f32;
+        let x = (t * (image.width() - 1) as f32).round() as u32;
+        let color = image.get_pixel(x, y).0.map(|x| x as f32 / 255.0);
+        (t, srgb_to_linear(color), KeyEasing::Linear)
+    }), ColorInterpolation::Rgb)
+}
@@ -0,0 +1,209 @@
+use std::{fs::File, io::{self, BufWriter}, path::Path};
+
+use bevy::{prelude::*, tasks::{ComputeTaskPool, TaskPool}};
+use exr::prelude::{AnyChannel, AnyChannels, Encoding, FlatSamples, Image as ExrImage, Layer, LayerAttributes, SmallVec, WritableImage};
+use image::{ImageBuffer, ImageFormat, Luma};
+use serde::{Serialize, Deserialize};
+use tiff::encoder::{TiffEncoder, colortype::Gray32Float};
+
+use crate::gen::{shape::ShapeGenerator, heightmap::{HeightmapProjection, CUBEMAP_FACE_NAMES, cubemap_direction, equirectangular_direction, suffixed_path}};
+
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum HeightmapFormat {
+    /// Elevation normalized between the minimum and maximum, as 16-bit grayscale.
+    #[default]
+    Png16,
+    /// Absolute elevation as 32-bit floats.
+    Exr32,
+    /// Absolute elevation as 32-bit floats.
+    Tiff32,
+}
+
+impl HeightmapFormat {
+    pub const ALL: [HeightmapFormat; 3] = [HeightmapFormat::Png16, HeightmapFormat::Exr32, HeightmapFormat::Tiff32];
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            HeightmapFormat::Png16 => "png",
+            HeightmapFormat::Exr32 => "exr",
+            HeightmapFormat::Tiff32 => "tiff",
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            HeightmapFormat::Png16 => "PNG (16-bit)",
+            HeightmapFormat::Exr32 => "EXR (32-bit float)",
+            HeightmapFormat::Tiff32 => "TIFF (32-bit float)",
+        }
+    }
+}
+
+/// One image of a projection, either the whole equirectangular map or a single cubemap face.
+pub struct ProjectionImage {
+    /// Appended to the file name, empty for equirectangular maps.
+    pub suffix: &'static str,
+    pub width: u32,
+    pub height: u32,
+    face: Option<usize>,
+}
+
+impl ProjectionImage {
+    pub fn direction(&self, uv: Vec2) -> Vec3 {
+        match self.face {
+            Some(face) => cubemap_direction(face, uv),
+            None => equirectangular_direction(uv),
+        }
+    }
+
+    /// Angle on the unit sphere covered by one pixel at the centre of the image.
+    pub fn pixel_angle(&self) -> f32 {
+        match self.face {
+            Some(_) => 2.0 / self.width as f32,
+            None => std::f32::consts::PI / self.height as f32,
+        }
+    }
+
+    /// Evaluates `f` with the uv and direction of every pixel centre, in rows from top to bottom.
+    pub fn sample<T: Clone + Default + Send>(&self, f: impl Fn(Vec2, Vec3) -> T + Sync) -> Vec<T> {
+        let mut pixels = vec![T::default(); (self.width * self.height) as usize];
+        let f = &f;
+        let size = Vec2::new(self.width as f32, self.height as f32);
+
+        ComputeTaskPool::init(TaskPool::default).scope(|scope| {
+            for (y, row) in pixels.chunks_mut(self.width as usize).enumerate() {
+                scope.spawn(async move {
+                    for (x, pixel) in row.iter_mut().enumerate() {
+                        let uv = (Vec2::new(x as f32, y as f32) + 0.5) / size;
+                        *pixel = f(uv, self.direction(uv));
+                    }
+                });
+            }
+        });
+
+        pixels
+    }
+}
+
+impl HeightmapProjection {
+    /// The images making up the projection. `resolution` is the height of equirectangular maps, which
+    /// are twice as wide, and the size of each cubemap face.
+    pub fn images(&self, resolution: u32) -> Vec<ProjectionImage> {
+        let resolution = resolution.max(1);
+        match self {
+            HeightmapProjection::Equirectangular => vec![
+                ProjectionImage { suffix: "", width: resolution * 2, height: resolution, face: None },
+            ],
+            HeightmapProjection::Cubemap => CUBEMAP_FACE_NAMES.iter().enumerate()
+                .map(|(face, suffix)| ProjectionImage { suffix, width: resolution, height: resolution, face: Some(face) })
+                .collect(),
+        }
+    }
+}
+
+pub struct Heightmap {
+    pub width: u32,
+    pub height: u32,
+    /// Rows from top to bottom.
+    pub elevations: Vec<f32>,
+}
+
+impl Heightmap {
+    pub fn sample(image: &ProjectionImage, shape_gen: &ShapeGenerator) -> Self {
+        Self {
+            width: image.width,
+            height: image.height,
+            elevations: image.sample(|_, direction| shape_gen.get_elevation(direction)),
+        }
+    }
+
+    pub fn min_max(&self) -> (f32, f32) {
+        self.elevations.iter().fold((f32::MAX, f32::MIN), |(min, max), x| (min.min(*x), max.max(*x)))
+    }
+
+    /// Writes the heightmap to `path`. 16-bit formats store elevation normalized between `min_elevation` and `max_elevation`.
+    pub fn write(&self, path: impl AsRef<Path>, format: HeightmapFormat, min_elevation: f32, max_elevation: f32) -> io::Result<()> {
+        match format {
+            HeightmapFormat::Png16 => {
+                let range = (max_elevation - min_elevation).max(0.000001);
+                let pixels: Vec<u16> = self.elevations.iter()
+                    .map(|x| (((x - min_elevation) / range).clamp(0.0, 1.0) * u16::MAX as f32).round() as u16)
+                    .collect();
+                let image: ImageBuffer<Luma<u16>, _> = ImageBuffer::from_raw(self.width, self.height, pixels).unwrap();
+                image.save_with_format(path, ImageFormat::Png).map_err(io::Error::other)
+            }
+            HeightmapFormat::Exr32 => {
+                let channel = AnyChannel::new("Y", FlatSamples::F32(self.elevations.clone()));
+                let layer = Layer::new(
+                    (self.width as usize, self.height as usize),
+                    LayerAttributes::named("elevation"),
+                    Encoding::FAST_LOSSLESS,
+                    AnyChannels::sort(SmallVec::from_vec(vec![channel])),
+                );
+                ExrImage::from_layer(layer).write().to_file(path).map_err(io::Error::other)
+            }
+            HeightmapFormat::Tiff32 => {
+                let mut encoder = TiffEncoder::new(BufWriter::new(File::create(path)?)).map_err(io::Error::other)?;
+                encoder.write_image::<Gray32Float>(self.width, self.height, &self.elevations).map_err(io::Error::other)
+            }
+        }
+    }
+}
+
+
+/// Written next to the heightmap images so other tools can turn pixel values back into elevations.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct HeightmapMetadata {
+    pub projection: HeightmapProjection,
+    pub format: HeightmapFormat,
+    pub width: u32,
+    pub height: u32,
+    /// Image suffixes of the cubemap faces, empty for equirectangular maps.
+    pub faces: Vec<String>,
+    /// Whether pixel values are normalized between `min_elevation` and `max_elevation` rather than absolute.
+    pub normalized: bool,
+    pub min_elevation: f32,
+    pub max_elevation: f32,
+    pub radius: f32,
+    pub sea_level: f32,
+}
+
+/// Samples the planet's elevation and writes it to `{base_path}.{ext}`, or `{base_path}_{face}.{ext}` for
+/// each cubemap face, along with a `{base_path}.json` metadata sidecar. See `HeightmapProjection::images`
+/// for the meaning of `resolution`.
+pub fn export_heightmap(
+    base_path: impl AsRef<Path>,
+    projection: HeightmapProjection,
+    format: HeightmapFormat,
+    resolution: u32,
+    shape_gen: &ShapeGenerator,
+) -> io::Result<HeightmapMetadata> {
+    let base_path = base_path.as_ref();
+    let images = projection.images(resolution);
+    let heightmaps: Vec<Heightmap> = images.iter().map(|image| Heightmap::sample(image, shape_gen)).collect();
+
+    let (min_elevation, max_elevation) = heightmaps.iter()
+        .map(|heightmap| heightmap.min_max())
+        .fold((f32::MAX, f32::MIN), |(min, max), (x_min, x_max)| (min.min(x_min), max.max(x_max)));
+
+    for (image, heightmap) in images.iter().zip(heightmaps.iter()) {
+        heightmap.write(suffixed_path(base_path, &[image.suffix], format.extension()), format, min_elevation, max_elevation)?;
+    }
+
+    let metadata = HeightmapMetadata {
+        projection,
+        format,
+        width: images[0].width,
+        height: images[0].height,
+        faces: images.iter().map(|image| image.suffix.to_string()).filter(|x| !x.is_empty()).collect(),
+        normalized: format == HeightmapFormat::Png16,
+        min_elevation,
+        max_elevation,
+        radius: shape_gen.radius,
+        sea_level: shape_gen.sea_level,
+    };
+    std::fs::write(suffixed_path(base_path, &[], "json"), serde_json::to_string_pretty(&metadata)?)?;
+
+    Ok(metadata)
+}
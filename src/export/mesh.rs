@@ -0,0 +1,383 @@
+use std::{collections::HashMap, fs::File, io::{self, BufWriter, Write}, ops::Range, path::Path};
+
+use bevy::{prelude::*, render::mesh::{Indices, VertexAttributeValues}};
+use serde::{Serialize, Deserialize};
+use serde_json::json;
+
+use crate::{gen::{shape::ShapeGenerator, heightmap::{CUBEMAP_FACE_NAMES, cubemap_uv, cubemap_face_uv}}, render::{planet::{TerrainFace, CubeSphereMapping, PlanetTopology, generate_chunk_mesh}, icosphere::{IcospherePatch, generate_patch_mesh}, planet_mat::normalized_latitude}, ui::color::UiColorSettings};
+
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum MeshFormat {
+    #[default]
+    Glb,
+    Obj,
+    Ply,
+}
+
+impl MeshFormat {
+    pub const ALL: [MeshFormat; 3] = [MeshFormat::Glb, MeshFormat::Obj, MeshFormat::Ply];
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            MeshFormat::Glb => "glb",
+            MeshFormat::Obj => "obj",
+            MeshFormat::Ply => "ply",
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            MeshFormat::Glb => "glTF (Binary)",
+            MeshFormat::Obj => "Wavefront OBJ",
+            MeshFormat::Ply => "PLY",
+        }
+    }
+}
+
+pub struct MeshExportOptions {
+    /// Vertices along each edge of a cube face or icosahedron triangle.
+    pub resolution: u32,
+    pub topology: PlanetTopology,
+    pub mapping: CubeSphereMapping,
+    /// Radius and color of the ocean sphere merged into the mesh, if any.
+    pub ocean: Option<(f32, [f32; 3])>,
+}
+
+/// A single welded triangle mesh of the whole planet, with colors in linear space. It's uv mapped
+/// onto the cubemap faces of a baked cubemap, with image rows going down.
+pub struct ExportMesh {
+    pub positions: Vec<Vec3>,
+    pub normals: Vec<Vec3>,
+    pub colors: Vec<[f32; 3]>,
+    pub uvs: Vec<Vec2>,
+    pub indices: Vec<u32>,
+    /// The indices of the triangles mapped onto each cubemap face.
+    pub face_ranges: [Range<usize>; 6],
+}
+
+impl ExportMesh {
+    fn new() -> Self {
+        Self {
+            positions: Vec::new(),
+            normals: Vec::new(),
+            colors: Vec::new(),
+            uvs: Vec::new(),
+            indices: Vec::new(),
+            face_ranges: Default::default(),
+        }
+    }
+
+    /// Maps every triangle onto the cubemap face its centre lies on, grouping triangles by face. Vertices
+    /// where faces meet are split so each face has its own uv, and those of triangles reaching over a
+    /// face's edge project onto the face's plane just outside 0 to 1.
+    fn map_cube_faces(&mut self) {
+        let mut split: HashMap<(u32, usize), u32> = HashMap::new();
+        let mut face_indices: [Vec<u32>; 6] = Default::default();
+        let (mut positions, mut normals, mut colors, mut uvs) = (Vec::new(), Vec::new(), Vec::new(), Vec::new());
+
+        for triangle in self.indices.chunks(3) {
+            let centre: Vec3 = triangle.iter().map(|i| self.positions[*i as usize]).sum();
+            let (face, _) = cubemap_uv(centre);
+            for &i in triangle {
+                let index = *split.entry((i, face)).or_insert_with(|| {
+                    let position = self.positions[i as usize];
+                    positions.push(position);
+                    normals.push(self.normals[i as usize]);
+                    colors.push(self.colors[i as usize]);
+                    uvs.push(cubemap_face_uv(face, position).unwrap_or(Vec2::splat(0.5)));
+                    positions.len() as u32 - 1
+                });
+                face_indices[face].push(index);
+            }
+        }
+
+        self.indices.clear();
+        for (face, indices) in face_indices.iter().enumerate() {
+            self.face_ranges[face] = self.indices.len()..self.indices.len() + indices.len();
+            self.indices.extend(indices);
+        }
+        self.positions = positions;
+        self.normals = normals;
+        self.colors = colors;
+        self.uvs = uvs;
+    }
+
+    /// Appends the first `num_vertices` vertices and `num_indices` indices of `mesh`, merging
+    /// vertices that lie in the same direction from the centre as ones already added.
+    fn append_welded(&mut self, mesh: &Mesh, num_vertices: usize, num_indices: usize, welded: &mut WeldGrid) {
+        let Some(VertexAttributeValues::Float32x3(positions)) = mesh.attribute(Mesh::ATTRIBUTE_POSITION) else { return };
+        let Some(VertexAttributeValues::Float32x3(normals)) = mesh.attribute(Mesh::ATTRIBUTE_NORMAL) else { return };
+        let Some(Indices::U32(indices)) = mesh.indices() else { return };
+
+        let remap: Vec<u32> = positions[..num_vertices].iter().zip(normals.iter()).map(|(position, normal)| {
+            let direction = Vec3::from(*position).normalize();
+            welded.find(direction).unwrap_or_else(|| {
+                self.positions.push(Vec3::from(*position));
+                self.normals.push(Vec3::from(*normal));
+                let index = self.positions.len() as u32 - 1;
+                welded.insert(direction, index);
+                index
+            })
+        }).collect();
+
+        self.indices.extend(indices[..num_indices].iter().map(|i| remap[*i as usize]));
+    }
+
+    fn append(&mut self, mesh: &Mesh, color: [f32; 3]) {
+        let Some(VertexAttributeValues::Float32x3(positions)) = mesh.attribute(Mesh::ATTRIBUTE_POSITION) else { return };
+        let Some(VertexAttributeValues::Float32x3(normals)) = mesh.attribute(Mesh::ATTRIBUTE_NORMAL) else { return };
+        let Some(Indices::U32(indices)) = mesh.indices() else { return };
+
+        let offset = self.positions.len() as u32;
+        self.positions.extend(positions.iter().map(|x| Vec3::from(*x)));
+        self.normals.extend(normals.iter().map(|x| Vec3::from(*x)));
+        self.colors.resize(self.colors.len() + positions.len(), color);
+        self.indices.extend(indices.iter().map(|i| i + offset));
+    }
+}
+
+
+/// The directions of the vertices added so far, bucketed into cells no wider than the distance
+/// vertices are welded over. Two directions that should weld can still round into neighbouring
+/// cells, so lookups search every cell around the one a direction falls in.
+#[derive(Default)]
+struct WeldGrid {
+    cells: HashMap<IVec3, Vec<(Vec3, u32)>>,
+}
+
+impl WeldGrid {
+    /// Far less than the spacing of vertices at any export resolution, but far more than the
+    /// rounding error between chunks computing the same edge vertex.
+    const WELD_DISTANCE: f32 = 1e-5;
+
+    fn cell(direction: Vec3) -> IVec3 {
+        (direction / Self::WELD_DISTANCE).floor().as_ivec3()
+    }
+
+    fn find(&self, direction: Vec3) -> Option<u32> {
+        let cell = Self::cell(direction);
+        (-1..=1).flat_map(|x| (-1..=1).flat_map(move |y| (-1..=1).map(move |z| cell + IVec3::new(x, y, z))))
+            .filter_map(|cell| self.cells.get(&cell))
+            .flatten()
+            .find(|(welded, _)| welded.distance_squared(direction) <= Self::WELD_DISTANCE * Self::WELD_DISTANCE)
+            .map(|(_, index)| *index)
+    }
+
+    fn insert(&mut self, direction: Vec3, index: u32) {
+        self.cells.entry(Self::cell(direction)).or_default().push((direction, index));
+    }
+}
+
+
+/// Generates the full planet at a single resolution, independent of the chunks currently on screen.
+pub fn build_export_mesh(
+    shape_gen: &ShapeGenerator,
+    colors: &UiColorSettings,
+    options: &MeshExportOptions,
+) -> ExportMesh {
+    let mut export_mesh = ExportMesh::new();
+    let mut welded = WeldGrid::default();
+    let resolution = options.resolution.max(2);
+
+    match options.topology {
+        PlanetTopology::CubeSphere => {
+            // skirts are only there to hide cracks between lod levels, so leave them out
+            let num_vertices = (resolution * resolution) as usize;
+            let num_indices = ((resolution - 1) * (resolution - 1) * 6) as usize;
+            for face in TerrainFace::all() {
+                let chunk_mesh = generate_chunk_mesh(face, Vec2::ZERO, 1.0, resolution, options.mapping, shape_gen);
+                export_mesh.append_welded(&chunk_mesh.mesh, num_vertices, num_indices, &mut welded);
+            }
+        }
+        PlanetTopology::Icosphere => {
+            let num_vertices = (resolution * (resolution + 1) / 2) as usize;
+            let num_indices = ((resolution - 1) * (resolution - 1) * 3) as usize;
+            for index in 0..IcospherePatch::COUNT {
+                let chunk_mesh = generate_patch_mesh(IcospherePatch { index }.corners(), resolution, shape_gen);
+                export_mesh.append_welded(&chunk_mesh.mesh, num_vertices, num_indices, &mut welded);
+            }
+        }
+    }
+
+    let (min_elevation, max_elevation) = export_mesh.positions.iter()
+        .map(|x| x.length())
+        .fold((f32::MAX, f32::MIN), |(min, max), x| (min.min(x), max.max(x)));
+
+    export_mesh.colors = export_mesh.positions.iter().zip(export_mesh.normals.iter()).map(|(position, normal)| {
+        let elevation = position.length();
+        let steepness = 1.0 - normal.dot(*position / elevation);
+        colors.sample(elevation, (min_elevation, max_elevation), shape_gen.sea_level, steepness, normalized_latitude(*position))
+    }).collect();
+
+    if let Some((radius, color)) = options.ocean {
+        let ocean = Mesh::try_from(shape::Icosphere { radius, subdivisions: 6 }).unwrap();
+        export_mesh.append(&ocean, color);
+    }
+
+    export_mesh.map_cube_faces();
+    export_mesh
+}
+
+/// Builds the planet mesh and writes it to `path` in the given format.
+pub fn export_planet_mesh(
+    path: impl AsRef<Path>,
+    format: MeshFormat,
+    shape_gen: &ShapeGenerator,
+    colors: &UiColorSettings,
+    options: &MeshExportOptions,
+) -> io::Result<()> {
+    let export_mesh = build_export_mesh(shape_gen, colors, options);
+    let mut writer = BufWriter::new(File::create(path)?);
+
+    match format {
+        MeshFormat::Glb => write_glb(&export_mesh, &mut writer)?,
+        MeshFormat::Obj => write_obj(&export_mesh, &mut writer)?,
+        MeshFormat::Ply => write_ply(&export_mesh, &mut writer)?,
+    }
+    writer.flush()
+}
+
+
+fn to_srgb(color: [f32; 3]) -> [f32; 3] {
+    let [r, g, b, _] = Color::rgb_linear(color[0], color[1], color[2]).as_rgba_f32();
+    [r, g, b]
+}
+
+pub fn write_glb(mesh: &ExportMesh, writer: &mut impl Write) -> io::Result<()> {
+    let mut bin = Vec::new();
+    let mut views = Vec::new();
+    let mut push_view = |bin: &mut Vec<u8>, data: &[u8], target: u32| {
+        views.push(json!({ "buffer": 0, "byteOffset": bin.len(), "byteLength": data.len(), "target": target }));
+        bin.extend_from_slice(data);
+    };
+
+    let positions: Vec<[f32; 3]> = mesh.positions.iter().map(|x| x.to_array()).collect();
+    let normals: Vec<[f32; 3]> = mesh.normals.iter().map(|x| x.to_array()).collect();
+    let uvs: Vec<[f32; 2]> = mesh.uvs.iter().map(|x| x.to_array()).collect();
+    push_view(&mut bin, bytemuck::cast_slice(&positions), 34962);
+    push_view(&mut bin, bytemuck::cast_slice(&normals), 34962);
+    push_view(&mut bin, bytemuck::cast_slice(&mesh.colors), 34962);
+    push_view(&mut bin, bytemuck::cast_slice(&uvs), 34962);
+    push_view(&mut bin, bytemuck::cast_slice(&mesh.indices), 34963);
+
+    let (min, max) = mesh.positions.iter().fold((Vec3::MAX, Vec3::MIN), |(min, max), x| (min.min(*x), max.max(*x)));
+    let count = mesh.positions.len();
+
+    // a primitive and material per cubemap face, to give each its own baked textures
+    let mut accessors = vec![
+        json!({ "bufferView": 0, "componentType": 5126, "count": count, "type": "VEC3", "min": min.to_array(), "max": max.to_array() }),
+        json!({ "bufferView": 1, "componentType": 5126, "count": count, "type": "VEC3" }),
+        json!({ "bufferView": 2, "componentType": 5126, "count": count, "type": "VEC3" }),
+        json!({ "bufferView": 3, "componentType": 5126, "count": count, "type": "VEC2" }),
+    ];
+    let mut primitives = Vec::new();
+    let mut materials = Vec::new();
+    for (face, range) in mesh.face_ranges.iter().enumerate() {
+        if range.is_empty() { continue };
+        primitives.push(json!({
+            "attributes": { "POSITION": 0, "NORMAL": 1, "COLOR_0": 2, "TEXCOORD_0": 3 },
+            "indices": accessors.len(),
+            "material": materials.len(),
+        }));
+        accessors.push(json!({ "bufferView": 4, "byteOffset": range.start * 4, "componentType": 5125, "count": range.len(), "type": "SCALAR" }));
+        materials.push(json!({
+            "name": format!("Planet_{}", CUBEMAP_FACE_NAMES[face]),
+            "pbrMetallicRoughness": { "metallicFactor": 0.0, "roughnessFactor": 1.0 },
+        }));
+    }
+
+    let document = json!({
+        "asset": { "version": "2.0", "generator": "procedural-planets" },
+        "scene": 0,
+        "scenes": [{ "nodes": [0] }],
+        "nodes": [{ "mesh": 0, "name": "Planet" }],
+        "meshes": [{ "name": "Planet", "primitives": primitives }],
+        "materials": materials,
+        "accessors": accessors,
+        "bufferViews": views,
+        "buffers": [{ "byteLength": bin.len() }],
+    });
+
+    // both chunks have to be 4 byte aligned, json is padded with spaces and binary with zeros
+    let mut json = serde_json::to_vec(&document)?;
+    json.resize((json.len() + 3) & !3, b' ');
+    bin.resize((bin.len() + 3) & !3, 0);
+
+    let total_length = 12 + 8 + json.len() + 8 + bin.len();
+    writer.write_all(b"glTF")?;
+    writer.write_all(&2u32.to_le_bytes())?;
+    writer.write_all(&(total_length as u32).to_le_bytes())?;
+
+    writer.write_all(&(json.len() as u32).to_le_bytes())?;
+    writer.write_all(b"JSON")?;
+    writer.write_all(&json)?;
+
+    writer.write_all(&(bin.len() as u32).to_le_bytes())?;
+    writer.write_all(b"BIN\0")?;
+    writer.write_all(&bin)
+}
+
+/// Writes vertex colors as the non-standard but widely read `v x y z r g b` extension, and a group
+/// and material per cubemap face for its baked textures.
+pub fn write_obj(mesh: &ExportMesh, writer: &mut impl Write) -> io::Result<()> {
+    writeln!(writer, "# procedural-planets")?;
+    writeln!(writer, "o Planet")?;
+    for (position, color) in mesh.positions.iter().zip(mesh.colors.iter()) {
+        let [r, g, b] = to_srgb(*color);
+        writeln!(writer, "v {} {} {} {} {} {}", position.x, position.y, position.z, r, g, b)?;
+    }
+    for normal in mesh.normals.iter() {
+        writeln!(writer, "vn {} {} {}", normal.x, normal.y, normal.z)?;
+    }
+    // texture coordinates go up from the bottom of the image
+    for uv in mesh.uvs.iter() {
+        writeln!(writer, "vt {} {}", uv.x, 1.0 - uv.y)?;
+    }
+    for (face, range) in mesh.face_ranges.iter().enumerate() {
+        if range.is_empty() { continue };
+        writeln!(writer, "g Planet_{}", CUBEMAP_FACE_NAMES[face])?;
+        writeln!(writer, "usemtl Planet_{}", CUBEMAP_FACE_NAMES[face])?;
+        for triangle in mesh.indices[range.clone()].chunks(3) {
+            let [a, b, c] = [triangle[0] + 1, triangle[1] + 1, triangle[2] + 1];
+            writeln!(writer, "f {a}/{a}/{a} {b}/{b}/{b} {c}/{c}/{c}")?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes texture coordinates going up from the bottom of the image, with each face's cubemap face
+/// as its `texnumber`.
+pub fn write_ply(mesh: &ExportMesh, writer: &mut impl Write) -> io::Result<()> {
+    writeln!(writer, "ply")?;
+    writeln!(writer, "format binary_little_endian 1.0")?;
+    writeln!(writer, "comment procedural-planets")?;
+    writeln!(writer, "element vertex {}", mesh.positions.len())?;
+    for property in ["x", "y", "z", "nx", "ny", "nz", "s", "t"] {
+        writeln!(writer, "property float {}", property)?;
+    }
+    for property in ["red", "green", "blue"] {
+        writeln!(writer, "property uchar {}", property)?;
+    }
+    writeln!(writer, "element face {}", mesh.indices.len() / 3)?;
+    writeln!(writer, "property list uchar uint vertex_indices")?;
+    writeln!(writer, "property uchar texnumber")?;
+    writeln!(writer, "end_header")?;
+
+    for (((position, normal), uv), color) in mesh.positions.iter().zip(mesh.normals.iter()).zip(mesh.uvs.iter()).zip(mesh.colors.iter()) {
+        for x in position.to_array().into_iter().chain(normal.to_array()).chain([uv.x, 1.0 - uv.y]) {
+            writer.write_all(&x.to_le_bytes())?;
+        }
+        writer.write_all(&to_srgb(*color).map(|x| (x.clamp(0.0, 1.0) * 255.0).round() as u8))?;
+    }
+    for (face, range) in mesh.face_ranges.iter().enumerate() {
+        for triangle in mesh.indices[range.clone()].chunks(3) {
+            writer.write_all(&[3])?;
+            for i in triangle {
+                writer.write_all(&i.to_le_bytes())?;
+            }
+            writer.write_all(&[face as u8])?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,4 @@
+pub mod mesh;
+pub mod heightmap;
+pub mod bake;
+pub mod gradient;
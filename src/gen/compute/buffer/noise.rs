@@ -1,7 +1,7 @@
-use bevy::{prelude::*, render::{render_resource::{ShaderType, StorageBuffer}, renderer::{RenderDevice, RenderQueue}}};
+use bevy::{prelude::*, render::{render_resource::{ShaderType, StorageBuffer}, renderer::{RenderDevice, RenderQueue}}, utils::HashMap};
 use bytemuck::{Pod, Zeroable};
 
-use crate::gen::{shape::ShapeGenerator, compute::MAX_NOISE_LAYERS, noise::NoiseSimplex3d};
+use crate::gen::{shape::ShapeGenerator, noise::NoiseSimplex3d};
 
 
 
@@ -48,22 +48,41 @@ impl Default for NoiseLayerStorage {
 }
 
 
-#[derive(Resource, Default)]
+#[derive(Default)]
 pub struct NoiseLayersBuffer {
-    pub buffer: StorageBuffer<[NoiseLayerStorage; MAX_NOISE_LAYERS as usize]>,
+    pub buffer: StorageBuffer<Vec<NoiseLayerStorage>>,
     pub size: u64,
 }
 
 
+/// The noise layers of each planet, by its entity.
+#[derive(Resource, Default)]
+pub struct NoiseLayersBuffers(pub HashMap<Entity, NoiseLayersBuffer>);
+
 pub fn prepare_noise_layers_buffer(
     device: Res<RenderDevice>,
     queue: Res<RenderQueue>,
-    mut noise_layers_buffer: ResMut<NoiseLayersBuffer>,
-    shape_gen: Res<ShapeGenerator>,
+    mut noise_layers_buffers: ResMut<NoiseLayersBuffers>,
+    planets: Query<(Entity, &ShapeGenerator)>,
+) {
+    noise_layers_buffers.0.retain(|entity, _| planets.contains(*entity));
+
+    for (entity, shape_gen) in planets.iter() {
+        let noise_layers_buffer = noise_layers_buffers.0.entry(entity).or_default();
+        write_noise_layers(&device, &queue, noise_layers_buffer, shape_gen);
+    }
+}
+
+fn write_noise_layers(
+    device: &RenderDevice,
+    queue: &RenderQueue,
+    noise_layers_buffer: &mut NoiseLayersBuffer,
+    shape_gen: &ShapeGenerator,
 ) {
     let buf = noise_layers_buffer.buffer.get_mut();
-    
-    for i in 0..(shape_gen.num_layers as usize) {
+    buf.resize(shape_gen.noise_layers.len().max(1), NoiseLayerStorage::default());
+
+    for i in 0..shape_gen.noise_layers.len() {
         let layer = &mut buf[i];
         let shape_gen_layer = &shape_gen.noise_layers[i];
 
@@ -83,5 +102,7 @@ pub fn prepare_noise_layers_buffer(
         layer.first_layer_mask = if shape_gen_layer.first_layer_mask { 1 } else { 0 };
     }
 
-    noise_layers_buffer.buffer.write_buffer(&device, &queue);
+    let size = buf.len() as u64;
+    noise_layers_buffer.size = size;
+    noise_layers_buffer.buffer.write_buffer(device, queue);
 }
@@ -1,4 +1,4 @@
-use bevy::{prelude::*, render::{extract_resource::ExtractResource, render_resource::{ShaderType, UniformBuffer}, renderer::{RenderDevice, RenderQueue}}};
+use bevy::{prelude::*, render::{extract_resource::ExtractResource, render_resource::{ShaderType, UniformBuffer}, renderer::{RenderDevice, RenderQueue}}, utils::HashMap};
 
 use crate::gen::compute::texture::PlanetHeightMapImages;
 
@@ -10,19 +10,29 @@ pub struct SettingsUniform {
     texture_size: IVec2,
 }
 
-#[derive(Resource, Default)]
+#[derive(Default)]
 pub struct SettingsBuffer {
     pub buffer: UniformBuffer<SettingsUniform>,
 }
 
+/// The compute settings of each planet, by its entity.
+#[derive(Resource, Default)]
+pub struct SettingsBuffers(pub HashMap<Entity, SettingsBuffer>);
+
 pub fn prepare_settings_buffer(
     device: Res<RenderDevice>,
     queue: Res<RenderQueue>,
-    mut settings_buffer: ResMut<SettingsBuffer>,
-    height_map_handles: Res<PlanetHeightMapImages>,
+    mut settings_buffers: ResMut<SettingsBuffers>,
+    planets: Query<(Entity, &PlanetHeightMapImages)>,
 ) {
-    let buffer = settings_buffer.buffer.get_mut();
-    buffer.texture_size = IVec2::new(height_map_handles.1.0 as i32, height_map_handles.1.1 as i32);
+    settings_buffers.0.retain(|entity, _| planets.contains(*entity));
+
+    for (entity, height_map_handles) in planets.iter() {
+        let settings_buffer = settings_buffers.0.entry(entity).or_default();
+
+        let buffer = settings_buffer.buffer.get_mut();
+        buffer.texture_size = IVec2::new(height_map_handles.1.0 as i32, height_map_handles.1.1 as i32);
 
-    settings_buffer.buffer.write_buffer(&device, &queue);
+        settings_buffer.buffer.write_buffer(&device, &queue);
+    }
 }
\ No newline at end of file
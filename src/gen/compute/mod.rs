@@ -1,4 +1,4 @@
-use bevy::{prelude::*, render::{extract_resource::{ExtractResourcePlugin, ExtractResource}, RenderApp, Render, render_graph::RenderGraph, RenderSet}};
+use bevy::{prelude::*, render::{extract_resource::{ExtractResourcePlugin, ExtractResource}, extract_component::ExtractComponentPlugin, RenderApp, Render, render_graph::RenderGraph, RenderSet}};
 
 
 use self::texture::PlanetHeightMapImages;
@@ -18,7 +18,6 @@ use super::shape::ShapeGenerator;
 
 pub const INIT_HEIGHTMAP_TEXTURE_SIZE: (u32, u32) = (512, 512);
 pub const WORKGROUP_SIZE: u32 = 8;
-pub const MAX_NOISE_LAYERS: u32 = 16;
 
 
 #[derive(ExtractResource, Resource, Default, Clone, PartialEq)]
@@ -30,10 +29,10 @@ pub struct PlanetComputePlugin;
 impl Plugin for PlanetComputePlugin {
     fn build(&self, app: &mut App) {
         app.init_resource::<PlanetComputeState>();
-        app.add_systems(Startup, setup_height_map_images);
+        app.add_systems(Update, setup_height_map_images);
         app.add_plugins(ExtractResourcePlugin::<PlanetComputeState>::default());
-        app.add_plugins(ExtractResourcePlugin::<PlanetHeightMapImages>::default());
-        app.add_plugins(ExtractResourcePlugin::<ShapeGenerator>::default());
+        app.add_plugins(ExtractComponentPlugin::<PlanetHeightMapImages>::default());
+        app.add_plugins(ExtractComponentPlugin::<ShapeGenerator>::default());
 
         let render_app = app.sub_app_mut(RenderApp);
         render_app
@@ -55,8 +54,9 @@ impl Plugin for PlanetComputePlugin {
     
     fn finish(&self, app: &mut App) {
         let render_app = app.sub_app_mut(RenderApp);
-        render_app.init_resource::<SettingsBuffer>();
-        render_app.init_resource::<NoiseLayersBuffer>();
+        render_app.init_resource::<SettingsBuffers>();
+        render_app.init_resource::<NoiseLayersBuffers>();
+        render_app.init_resource::<PlanetComputeBindGroups>();
         render_app.init_resource::<PlanetComputePipeline>();
     }
 }
\ No newline at end of file
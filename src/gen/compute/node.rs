@@ -1,6 +1,6 @@
 use bevy::{prelude::*, render::{render_resource::{PipelineCache, ComputePassDescriptor}, render_graph, renderer::RenderContext}};
 
-use super::{pipeline::{PlanetComputePipeline, PlanetComputeBindGroup}, texture::PlanetHeightMapImages, WORKGROUP_SIZE, PlanetComputeState};
+use super::{pipeline::{PlanetComputePipeline, PlanetComputeBindGroups}, texture::PlanetHeightMapImages, WORKGROUP_SIZE, PlanetComputeState};
 
 enum ShaderState {
     Waiting,
@@ -49,13 +49,14 @@ impl render_graph::Node for PlanetComputeNode {
         render_context: &mut RenderContext,
         world: &World,
     ) -> Result<(), render_graph::NodeRunError> {
-        let bind_group = &world.resource::<PlanetComputeBindGroup>().0;
+        let bind_groups = &world.resource::<PlanetComputeBindGroups>().0;
         let pipeline_cache = world.resource::<PipelineCache>();
         let pipeline = world.resource::<PlanetComputePipeline>();
-        let height_map_dims = world.resource::<PlanetHeightMapImages>().1;
 
         let encoder = render_context.command_encoder();
-        {
+        for (entity, bind_group) in bind_groups.iter() {
+            let Some(height_map_images) = world.get::<PlanetHeightMapImages>(*entity) else { continue };
+            let height_map_dims = height_map_images.1;
             let mut pass = encoder.begin_compute_pass(&ComputePassDescriptor::default());
 
             pass.set_bind_group(0, bind_group, &[]);
@@ -1,36 +1,43 @@
 use std::borrow::Cow;
 
-use bevy::{prelude::*, render::{render_resource::{BindGroup, BindGroupDescriptor, BindGroupEntry, BindingResource, BindGroupLayout, CachedComputePipelineId, BindGroupLayoutDescriptor, BindGroupLayoutEntry, ShaderStages, BindingType, StorageTextureAccess, TextureFormat, TextureViewDimension, PipelineCache, ComputePipelineDescriptor, BufferBindingType}, render_asset::RenderAssets, renderer::RenderDevice}};
+use bevy::{prelude::*, render::{render_resource::{BindGroup, BindGroupDescriptor, BindGroupEntry, BindingResource, BindGroupLayout, CachedComputePipelineId, BindGroupLayoutDescriptor, BindGroupLayoutEntry, ShaderStages, BindingType, StorageTextureAccess, TextureFormat, TextureViewDimension, PipelineCache, ComputePipelineDescriptor, BufferBindingType}, render_asset::RenderAssets, renderer::RenderDevice}, utils::HashMap};
 
-use super::{texture::PlanetHeightMapImages, buffer::SettingsBuffer};
+use super::{texture::PlanetHeightMapImages, buffer::SettingsBuffers};
 
 
 
-#[derive(Resource)]
-pub struct PlanetComputeBindGroup(pub BindGroup);
+/// The bind group of each planet, by its entity.
+#[derive(Resource, Default)]
+pub struct PlanetComputeBindGroups(pub HashMap<Entity, BindGroup>);
 
 pub fn queue_bind_group(
-    mut commands: Commands,
+    mut bind_groups: ResMut<PlanetComputeBindGroups>,
     pipeline: Res<PlanetComputePipeline>,
     gpu_images: Res<RenderAssets<Image>>,
-    height_images: Res<PlanetHeightMapImages>,
+    planets: Query<(Entity, &PlanetHeightMapImages)>,
     render_device: Res<RenderDevice>,
 
-    settings_buf: Res<SettingsBuffer>,
+    settings_bufs: Res<SettingsBuffers>,
 ) {
-    let view_height_map_img = &gpu_images[&height_images.0];
-    let bind_group = render_device.create_bind_group(&BindGroupDescriptor {
-        label: None,
-        layout: &pipeline.bind_group_layout,
-        entries: &[BindGroupEntry {
-            binding: 0,
-            resource: BindingResource::TextureView(&view_height_map_img.texture_view),
-        }, BindGroupEntry {
-            binding: 1,
-            resource: settings_buf.buffer.binding().unwrap(),
-        }],
-    });
-    commands.insert_resource(PlanetComputeBindGroup(bind_group));
+    bind_groups.0.clear();
+
+    for (entity, height_images) in planets.iter() {
+        let Some(view_height_map_img) = gpu_images.get(&height_images.0) else { continue };
+        let Some(settings_buf) = settings_bufs.0.get(&entity) else { continue };
+
+        let bind_group = render_device.create_bind_group(&BindGroupDescriptor {
+            label: None,
+            layout: &pipeline.bind_group_layout,
+            entries: &[BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::TextureView(&view_height_map_img.texture_view),
+            }, BindGroupEntry {
+                binding: 1,
+                resource: settings_buf.buffer.binding().unwrap(),
+            }],
+        });
+        bind_groups.0.insert(entity, bind_group);
+    }
 }
 
 #[derive(Resource)]
@@ -1,16 +1,26 @@
 use bevy::prelude::*;
 
 
-use bevy::{window::PrimaryWindow, render::{render_resource::{Extent3d, TextureDimension, TextureFormat, TextureUsages}, extract_resource::ExtractResource}};
+use bevy::{window::PrimaryWindow, render::{render_resource::{Extent3d, TextureDimension, TextureFormat, TextureUsages}, extract_component::ExtractComponent}};
+
+use crate::render::planet::Planet;
 
 use super::INIT_HEIGHTMAP_TEXTURE_SIZE;
 
 
+/// Gives every new planet a heightmap of its own to compute into.
 pub fn setup_height_map_images(
     mut commands: Commands, 
     window_query: Query<&Window, With<PrimaryWindow>>, 
-    mut images: ResMut<Assets<Image>>
+    mut images: ResMut<Assets<Image>>,
+    planets: Query<Entity, (With<Planet>, Without<PlanetHeightMapImages>)>,
 ) {
+    for planet in planets.iter() {
+        commands.entity(planet).insert(PlanetHeightMapImages(create_height_map_image(&mut images), INIT_HEIGHTMAP_TEXTURE_SIZE));
+    }
+}
+
+fn create_height_map_image(images: &mut Assets<Image>) -> Handle<Image> {
     let mut heightmap = Image::new_fill(
         Extent3d {
             width: INIT_HEIGHTMAP_TEXTURE_SIZE.0,
@@ -24,13 +34,11 @@ pub fn setup_height_map_images(
 
     heightmap.texture_descriptor.usage =
         TextureUsages::COPY_DST | TextureUsages::STORAGE_BINDING | TextureUsages::TEXTURE_BINDING;
-    let heightmap = images.add(heightmap);
-
-    commands.insert_resource(PlanetHeightMapImages(heightmap, INIT_HEIGHTMAP_TEXTURE_SIZE));
+    images.add(heightmap)
 }
 
 
-#[derive(Resource, Clone, ExtractResource)]
+#[derive(Component, Clone, ExtractComponent)]
 pub struct PlanetHeightMapImages(pub Handle<Image>, pub (u32, u32));
 
 impl PlanetHeightMapImages {
@@ -44,4 +52,4 @@ impl PlanetHeightMapImages {
     //     let end = start + 4;
     //     f32::from_ne_bytes(im.data[start..end].try_into().unwrap())
     // }
-}
\ No newline at end of file
+}
@@ -0,0 +1,241 @@
+use std::{fs::File, io::{self, BufReader}, path::{Path, PathBuf}};
+
+use bevy::prelude::*;
+use serde::{Serialize, Deserialize};
+use tiff::decoder::{Decoder as TiffDecoder, DecodingResult};
+
+use super::shape::{lat_long_to_point, point_to_lat_long};
+
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum HeightmapProjection {
+    #[default]
+    Equirectangular,
+    Cubemap,
+}
+
+impl HeightmapProjection {
+    pub const ALL: [HeightmapProjection; 2] = [HeightmapProjection::Equirectangular, HeightmapProjection::Cubemap];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            HeightmapProjection::Equirectangular => "Equirectangular",
+            HeightmapProjection::Cubemap => "Cubemap",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum HeightmapFiltering {
+    #[default]
+    Bilinear,
+    Bicubic,
+}
+
+impl HeightmapFiltering {
+    pub const ALL: [HeightmapFiltering; 2] = [HeightmapFiltering::Bilinear, HeightmapFiltering::Bicubic];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            HeightmapFiltering::Bilinear => "Bilinear",
+            HeightmapFiltering::Bicubic => "Bicubic",
+        }
+    }
+}
+
+
+/// Suffixes of the cubemap face images, in the order of `cubemap_direction`.
+pub const CUBEMAP_FACE_NAMES: [&str; 6] = ["px", "nx", "py", "ny", "pz", "nz"];
+
+/// Direction through `uv` on a cubemap face, using the usual OpenGL face orientations so the
+/// images load as a cubemap in other engines without flipping.
+pub fn cubemap_direction(face: usize, uv: Vec2) -> Vec3 {
+    let s = uv.x * 2.0 - 1.0;
+    let t = uv.y * 2.0 - 1.0;
+    let direction = match face {
+        0 => Vec3::new(1.0, -t, -s),
+        1 => Vec3::new(-1.0, -t, s),
+        2 => Vec3::new(s, 1.0, t),
+        3 => Vec3::new(s, -1.0, -t),
+        4 => Vec3::new(s, -t, 1.0),
+        _ => Vec3::new(-s, -t, -1.0),
+    };
+    direction.normalize()
+}
+
+/// The cubemap face and uv that `direction` passes through, the inverse of `cubemap_direction`.
+pub fn cubemap_uv(direction: Vec3) -> (usize, Vec2) {
+    let abs = direction.abs();
+    let (face, s, t) = if abs.x >= abs.y && abs.x >= abs.z {
+        if direction.x > 0.0 { (0, -direction.z, -direction.y) } else { (1, direction.z, -direction.y) }
+    } else if abs.y >= abs.z {
+        if direction.y > 0.0 { (2, direction.x, direction.z) } else { (3, direction.x, -direction.z) }
+    } else if direction.z > 0.0 {
+        (4, direction.x, -direction.y)
+    } else {
+        (5, -direction.x, -direction.y)
+    };
+    let major = abs.max_element();
+    (face, (Vec2::new(s, t) / major + 1.0) * 0.5)
+}
+
+/// The uv where `direction` crosses the plane of one cubemap `face`, which lies outside 0..1 when
+/// it passes through another face, or nothing when it points away from the face.
+pub fn cubemap_face_uv(face: usize, direction: Vec3) -> Option<Vec2> {
+    let (major, s, t) = match face {
+        0 => (direction.x, -direction.z, -direction.y),
+        1 => (-direction.x, direction.z, -direction.y),
+        2 => (direction.y, direction.x, direction.z),
+        3 => (-direction.y, direction.x, -direction.z),
+        4 => (direction.z, direction.x, -direction.y),
+        _ => (-direction.z, -direction.x, -direction.y),
+    };
+    (major > 0.001).then(|| (Vec2::new(s, t) / major + 1.0) * 0.5)
+}
+
+/// Direction through `uv` on an equirectangular map, with north at the top and longitude zero in the middle.
+pub fn equirectangular_direction(uv: Vec2) -> Vec3 {
+    let latitude = (0.5 - uv.y) * std::f32::consts::PI;
+    let longitude = (uv.x - 0.5) * std::f32::consts::TAU;
+    lat_long_to_point(latitude, longitude)
+}
+
+/// The equirectangular uv that `direction` passes through, the inverse of `equirectangular_direction`.
+pub fn equirectangular_uv(direction: Vec3) -> Vec2 {
+    let (latitude, longitude) = point_to_lat_long(direction);
+    Vec2::new(longitude / std::f32::consts::TAU + 0.5, 0.5 - latitude / std::f32::consts::PI)
+}
+
+/// `base_path` with the non-empty `suffixes` appended to the file name, separated by underscores. Only
+/// `extension` is taken off the end of the file name first, so other dots in it are kept.
+pub fn suffixed_path(base_path: &Path, suffixes: &[&str], extension: &str) -> PathBuf {
+    let file_name = base_path.file_name().and_then(|x| x.to_str()).unwrap_or_default();
+    let mut name = match file_name.rsplit_once('.') {
+        Some((name, x)) if !name.is_empty() && x.eq_ignore_ascii_case(extension) => name.to_string(),
+        _ => file_name.to_string(),
+    };
+    for suffix in suffixes.iter().filter(|x| !x.is_empty()) {
+        name = format!("{}_{}", name, suffix);
+    }
+    base_path.with_file_name(format!("{}.{}", name, extension))
+}
+
+
+/// A single channel image with integer formats normalized to `0..=1` and float formats kept as they are.
+pub struct HeightmapImage {
+    pub width: u32,
+    pub height: u32,
+    pub values: Vec<f32>,
+}
+
+impl HeightmapImage {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let is_tiff = path.extension().and_then(|x| x.to_str()).is_some_and(|x| x.eq_ignore_ascii_case("tif") || x.eq_ignore_ascii_case("tiff"));
+        if !is_tiff {
+            let image = image::open(path).map_err(io::Error::other)?.to_luma32f();
+            return Ok(Self {
+                width: image.width(),
+                height: image.height(),
+                values: image.into_raw(),
+            });
+        }
+
+        // the image crate can't decode floating point tiffs, so those go through the tiff crate directly
+        let mut decoder = TiffDecoder::new(BufReader::new(File::open(path)?)).map_err(io::Error::other)?;
+        let (width, height) = decoder.dimensions().map_err(io::Error::other)?;
+        if width == 0 || height == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "empty tiff image"));
+        }
+        let values: Vec<f32> = match decoder.read_image().map_err(io::Error::other)? {
+            DecodingResult::U8(data) => data.iter().map(|x| *x as f32 / u8::MAX as f32).collect(),
+            DecodingResult::U16(data) => data.iter().map(|x| *x as f32 / u16::MAX as f32).collect(),
+            DecodingResult::U32(data) => data.iter().map(|x| *x as f32 / u32::MAX as f32).collect(),
+            DecodingResult::F32(data) => data,
+            DecodingResult::F64(data) => data.iter().map(|x| *x as f32).collect(),
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "unsupported tiff sample format")),
+        };
+
+        // keep the first channel of color images
+        let channels = values.len() / (width * height) as usize;
+        Ok(Self {
+            width,
+            height,
+            values: values.into_iter().step_by(channels.max(1)).collect(),
+        })
+    }
+
+    fn fetch(&self, x: i32, y: i32, wrap_x: bool) -> f32 {
+        let x = if wrap_x { x.rem_euclid(self.width as i32) } else { x.clamp(0, self.width as i32 - 1) };
+        let y = y.clamp(0, self.height as i32 - 1);
+        self.values[(y as u32 * self.width + x as u32) as usize]
+    }
+
+    /// Samples at `uv` between the outer edges of the image, wrapping horizontally if `wrap_x` is set.
+    pub fn sample(&self, uv: Vec2, filtering: HeightmapFiltering, wrap_x: bool) -> f32 {
+        let p = uv * Vec2::new(self.width as f32, self.height as f32) - 0.5;
+        let base = p.floor();
+        let t = p - base;
+        let (x, y) = (base.x as i32, base.y as i32);
+
+        match filtering {
+            HeightmapFiltering::Bilinear => {
+                let top = self.fetch(x, y, wrap_x) * (1.0 - t.x) + self.fetch(x + 1, y, wrap_x) * t.x;
+                let bottom = self.fetch(x, y + 1, wrap_x) * (1.0 - t.x) + self.fetch(x + 1, y + 1, wrap_x) * t.x;
+                top * (1.0 - t.y) + bottom * t.y
+            }
+            HeightmapFiltering::Bicubic => {
+                let rows: [f32; 4] = std::array::from_fn(|j| {
+                    let row = y - 1 + j as i32;
+                    catmull_rom(std::array::from_fn(|i| self.fetch(x - 1 + i as i32, row, wrap_x)), t.x)
+                });
+                catmull_rom(rows, t.y)
+            }
+        }
+    }
+}
+
+fn catmull_rom(p: [f32; 4], t: f32) -> f32 {
+    0.5 * (2.0 * p[1]
+        + (p[2] - p[0]) * t
+        + (2.0 * p[0] - 5.0 * p[1] + 4.0 * p[2] - p[3]) * t * t
+        + (3.0 * p[1] - p[0] - 3.0 * p[2] + p[3]) * t * t * t)
+}
+
+
+/// Elevation data covering the whole sphere, either one equirectangular image or six cubemap faces.
+pub struct HeightmapData {
+    pub projection: HeightmapProjection,
+    pub images: Vec<HeightmapImage>,
+}
+
+impl HeightmapData {
+    /// Loads `path` relative to `assets/`. Cubemaps are read from `{name}_{face}.{ext}` next to it,
+    /// the same layout the heightmap export writes.
+    pub fn load(path: &str, projection: HeightmapProjection) -> io::Result<Self> {
+        let path = Path::new("assets").join(path);
+        let images = match projection {
+            HeightmapProjection::Equirectangular => vec![HeightmapImage::load(&path)?],
+            HeightmapProjection::Cubemap => {
+                let extension = path.extension().and_then(|x| x.to_str()).unwrap_or("png");
+                CUBEMAP_FACE_NAMES.iter()
+                    .map(|face| HeightmapImage::load(&suffixed_path(&path, &[face], extension)))
+                    .collect::<io::Result<Vec<_>>>()?
+            }
+        };
+
+        Ok(Self {
+            projection,
+            images,
+        })
+    }
+
+    pub fn sample(&self, direction: Vec3, filtering: HeightmapFiltering) -> f32 {
+        match self.projection {
+            HeightmapProjection::Equirectangular => self.images[0].sample(equirectangular_uv(direction), filtering, true),
+            HeightmapProjection::Cubemap => {
+                let (face, uv) = cubemap_uv(direction);
+                self.images[face].sample(uv, filtering, false)
+            }
+        }
+    }
+}
@@ -1,18 +1,14 @@
 pub mod noise;
 pub mod shape;
 pub mod noise_filter;
+pub mod heightmap;
+pub mod sculpt;
 
 use bevy::prelude::*;
 
-use shape::*;
-
 
 pub struct GeneratorPlugin;
 
 impl Plugin for GeneratorPlugin {
-    fn build(&self, app: &mut App) {
-        app
-            .init_resource::<ShapeGenerator>()
-        ;
-    }
+    fn build(&self, _app: &mut App) {}
 }
\ No newline at end of file
@@ -1,13 +1,16 @@
+use std::{io, sync::Arc};
+
 use bevy::prelude::*;
 use serde::{Serialize, Deserialize};
 
-use super::noise::NoiseSimplex3d;
+use super::{noise::NoiseSimplex3d, heightmap::{HeightmapData, HeightmapProjection, HeightmapFiltering}};
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum NoiseFilterType {
     Standard,
     Rigid,
     Warp,
+    Heightmap,
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -26,6 +29,16 @@ pub struct NoiseFilter {
     pub floor: f32,
     pub center: Vec3,
     pub warp_offset: Vec3,
+
+    /// Image relative to `assets/` sampled by heightmap filters, scaled by `strength` and shifted by `offset`.
+    #[serde(default)]
+    pub heightmap_path: String,
+    #[serde(default)]
+    pub heightmap_projection: HeightmapProjection,
+    #[serde(default)]
+    pub heightmap_filtering: HeightmapFiltering,
+    #[serde(skip)]
+    pub heightmap: Option<Arc<HeightmapData>>,
 }
 
 impl NoiseFilter {
@@ -44,6 +57,11 @@ impl NoiseFilter {
             floor: 0.0,
             center: Vec3::ZERO,
             warp_offset: Vec3::new(0.0, 100.0, -100.0),
+
+            heightmap_path: String::new(),
+            heightmap_projection: HeightmapProjection::default(),
+            heightmap_filtering: HeightmapFiltering::default(),
+            heightmap: None,
         }
     }
 
@@ -52,9 +70,24 @@ impl NoiseFilter {
             NoiseFilterType::Standard => self.eval_standard(p),
             NoiseFilterType::Rigid => self.eval_rigid(p),
             NoiseFilterType::Warp => self.eval_standard(p),
+            NoiseFilterType::Heightmap => self.eval_heightmap(p),
         }
     }
 
+    /// Reads `heightmap_path` from disk, keeping the previously loaded image if it fails.
+    pub fn load_heightmap(&mut self) -> io::Result<()> {
+        self.heightmap = Some(Arc::new(HeightmapData::load(&self.heightmap_path, self.heightmap_projection)?));
+        Ok(())
+    }
+
+    pub fn eval_heightmap(&self, p: Vec3) -> f32 {
+        let height = match &self.heightmap {
+            Some(heightmap) => heightmap.sample(p.normalize(), self.heightmap_filtering),
+            None => 0.0,
+        };
+        height * self.strength - self.offset
+    }
+
     pub fn eval_standard(&self, p: Vec3) -> f32 {
         let mut noise_val = 0.0;
         let mut f = self.roughness;
@@ -0,0 +1,265 @@
+use std::{ops::Range, sync::Arc};
+
+use bevy::{prelude::*, utils::HashMap};
+use serde::{Serialize, Deserialize};
+
+use super::{heightmap::{cubemap_direction, cubemap_uv, cubemap_face_uv}, noise::NoiseSimplex3d, shape::ShapeGenerator};
+
+
+/// Hand painted heights added on top of the noise, in units of the planet radius. Stored as one
+/// `resolution` square grid per cubemap face, in the order and orientation of `cubemap_direction`,
+/// and left unallocated until something is painted.
+///
+/// Each face's grid is shared between clones, since every mesh task holds a copy of the
+/// `ShapeGenerator`. Painting copies only the faces it touches, and only while someone
+/// else still holds the old ones.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SculptLayer {
+    pub resolution: u32,
+    faces: Vec<Arc<Vec<f32>>>,
+}
+
+impl Default for SculptLayer {
+    fn default() -> Self {
+        Self {
+            resolution: 256,
+            faces: Vec::new(),
+        }
+    }
+}
+
+impl SculptLayer {
+    pub fn is_empty(&self) -> bool {
+        self.faces.len() != 6
+    }
+
+    pub fn clear(&mut self) {
+        self.faces = Vec::new();
+    }
+
+    /// Resamples the painting onto faces of a different resolution.
+    pub fn set_resolution(&mut self, resolution: u32) {
+        let resolution = resolution.max(2);
+        if resolution == self.resolution { return };
+        if self.is_empty() {
+            self.resolution = resolution;
+            return;
+        }
+
+        let faces = (0..6).map(|face| {
+            Arc::new((0..resolution * resolution).map(|i| {
+                let uv = (Vec2::new((i % resolution) as f32, (i / resolution) as f32) + 0.5) / resolution as f32;
+                self.sample(cubemap_direction(face, uv))
+            }).collect())
+        }).collect();
+        self.resolution = resolution;
+        self.faces = faces;
+    }
+
+    /// Bilinearly filtered height at `point_on_sphere`, carried on across the face edges.
+    pub fn sample(&self, point_on_sphere: Vec3) -> f32 {
+        if self.is_empty() { return 0.0 };
+
+        let (face, uv) = cubemap_uv(point_on_sphere);
+        let texel = uv * self.resolution as f32 - 0.5;
+        let (x, y) = (texel.x.floor() as i32, texel.y.floor() as i32);
+        let f = texel - texel.floor();
+
+        let top = self.fetch(face, x, y) * (1.0 - f.x) + self.fetch(face, x + 1, y) * f.x;
+        let bottom = self.fetch(face, x, y + 1) * (1.0 - f.x) + self.fetch(face, x + 1, y + 1) * f.x;
+        top * (1.0 - f.y) + bottom * f.y
+    }
+
+    /// The texel at `x`, `y` on `face`, or the nearest one on the face next to it when that's off the edge.
+    fn fetch(&self, face: usize, x: i32, y: i32) -> f32 {
+        let resolution = self.resolution as i32;
+        let (face, x, y) = if (0..resolution).contains(&x) && (0..resolution).contains(&y) {
+            (face, x, y)
+        } else {
+            let uv = (Vec2::new(x as f32, y as f32) + 0.5) / resolution as f32;
+            let (face, uv) = cubemap_uv(cubemap_direction(face, uv));
+            let texel = (uv * resolution as f32).as_ivec2().clamp(IVec2::ZERO, IVec2::splat(resolution - 1));
+            (face, texel.x, texel.y)
+        };
+        self.faces[face][(y * resolution + x) as usize]
+    }
+
+    fn face_mut(&mut self, face: usize) -> &mut Vec<f32> {
+        let texels = (self.resolution * self.resolution) as usize;
+        if self.is_empty() || self.faces.iter().any(|face| face.len() != texels) {
+            self.faces = (0..6).map(|_| Arc::new(vec![0.0; texels])).collect();
+        }
+        Arc::make_mut(&mut self.faces[face])
+    }
+}
+
+
+/// Marks a planet with a stroke being painted on it, so work that can wait for the stroke to end does.
+#[derive(Component)]
+pub struct Sculpting;
+
+
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum SculptTool {
+    #[default]
+    Raise,
+    Lower,
+    Smooth,
+    Flatten,
+    NoiseStamp,
+}
+
+impl SculptTool {
+    pub const ALL: [SculptTool; 5] = [SculptTool::Raise, SculptTool::Lower, SculptTool::Smooth, SculptTool::Flatten, SculptTool::NoiseStamp];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SculptTool::Raise => "Raise",
+            SculptTool::Lower => "Lower",
+            SculptTool::Smooth => "Smooth",
+            SculptTool::Flatten => "Flatten",
+            SculptTool::NoiseStamp => "Noise Stamp",
+        }
+    }
+}
+
+/// A brush painting onto a planet's `SculptLayer`. The radius is an angle in radians, so a brush
+/// covers the same part of the planet whatever its size.
+#[derive(Clone, Debug)]
+pub struct SculptBrush {
+    pub tool: SculptTool,
+    pub radius: f32,
+    /// How quickly the brush works, in planet radii per second at its centre.
+    pub strength: f32,
+    /// The fraction of the radius, from the edge inwards, over which the brush fades out.
+    pub falloff: f32,
+    pub noise_scale: f32,
+    pub noise_seed: u32,
+}
+
+impl Default for SculptBrush {
+    fn default() -> Self {
+        Self {
+            tool: SculptTool::Raise,
+            radius: 0.1,
+            strength: 0.05,
+            falloff: 0.5,
+            noise_scale: 20.0,
+            noise_seed: 0,
+        }
+    }
+}
+
+impl SculptBrush {
+    /// The largest radius a stroke may have, which keeps a brush from covering a whole face.
+    pub const MAX_RADIUS: f32 = 0.5;
+
+    /// How strongly the brush acts `angle` radians from its centre.
+    pub fn weight(&self, angle: f32) -> f32 {
+        let t = angle / self.radius;
+        if t >= 1.0 { return 0.0 };
+        let inner = 1.0 - self.falloff.clamp(0.0, 1.0);
+        if t <= inner { return 1.0 };
+        let x = (t - inner) / (1.0 - inner);
+        1.0 - x * x * (3.0 - 2.0 * x)
+    }
+
+    /// Paints one step of a stroke centred on `center`, a point on the unit sphere, lasting `dt`
+    /// seconds. Flattening pulls towards `flatten_elevation`, usually the elevation where the stroke began.
+    pub fn apply(&self, shape_gen: &mut ShapeGenerator, center: Vec3, dt: f32, flatten_elevation: f32) {
+        let radius = self.radius.clamp(0.0001, Self::MAX_RADIUS);
+        let resolution = shape_gen.sculpt.resolution as usize;
+        let min_dot = radius.cos();
+        let amount = self.strength * dt;
+
+        // a face is at most ~54.7° from its centre to its corners, so faces further than that plus the
+        // radius away can't be touched
+        let face_reach = (1.0 / 3f32.sqrt()).acos() + radius;
+
+        let mut touched = Vec::new();
+        for face in 0..6 {
+            let face_center = cubemap_direction(face, Vec2::splat(0.5));
+            if face_center.angle_between(center) > face_reach { continue };
+            let Some((xs, ys)) = Self::texel_bounds(face, center, radius, resolution) else { continue };
+
+            for y in ys {
+                for x in xs.clone() {
+                    let uv = (Vec2::new(x as f32, y as f32) + 0.5) / resolution as f32;
+                    let direction = cubemap_direction(face, uv);
+                    if direction.dot(center) < min_dot { continue };
+                    let weight = self.weight(direction.angle_between(center));
+                    if weight > 0.0 {
+                        touched.push((face, x, y, direction, weight));
+                    }
+                }
+            }
+        }
+        if touched.is_empty() { return };
+
+        // smoothing and flattening work on the whole surface, noise and all, so they need the
+        // elevation before this step. neighbours are shared between texels, so remember them.
+        // texels past the edge of a face project onto the face next to it, so smoothing carries on
+        // across the seams
+        let mut elevations: HashMap<(usize, i32, i32), f32> = HashMap::new();
+        let mut elevation_at = |face: usize, x: i32, y: i32| *elevations.entry((face, x, y)).or_insert_with(|| {
+            let uv = (Vec2::new(x as f32, y as f32) + 0.5) / resolution as f32;
+            shape_gen.get_elevation(cubemap_direction(face, uv))
+        });
+
+        let noise = (self.tool == SculptTool::NoiseStamp).then(|| NoiseSimplex3d::new(self.noise_seed));
+        let changes: Vec<(usize, usize, f32)> = touched.iter().map(|&(face, x, y, direction, weight)| {
+            let change = match self.tool {
+                SculptTool::Raise => amount,
+                SculptTool::Lower => -amount,
+                SculptTool::Smooth => {
+                    let (x, y) = (x as i32, y as i32);
+                    let elevation = elevation_at(face, x, y);
+                    let neighbours = [
+                        elevation_at(face, x - 1, y),
+                        elevation_at(face, x + 1, y),
+                        elevation_at(face, x, y - 1),
+                        elevation_at(face, x, y + 1),
+                    ];
+                    let average = neighbours.iter().sum::<f32>() / 4.0;
+                    (average - elevation) / shape_gen.radius * (amount * 20.0).min(1.0)
+                },
+                SculptTool::Flatten => {
+                    let elevation = elevation_at(face, x as i32, y as i32);
+                    (flatten_elevation - elevation) / shape_gen.radius * (amount * 20.0).min(1.0)
+                },
+                SculptTool::NoiseStamp => noise.as_ref().unwrap().evaluate(direction * self.noise_scale) * amount,
+            };
+            (face, y * resolution + x, change * weight)
+        }).collect();
+
+        for (face, i, change) in changes {
+            shape_gen.sculpt.face_mut(face)[i] += change;
+        }
+    }
+
+    /// The columns and rows of `face` that a brush of `radius` centred on `center` can reach, or
+    /// nothing if it misses the face. The brush's outline is convex on the face plane, so bounding
+    /// points around it is enough, unless it reaches round the side of the face.
+    fn texel_bounds(face: usize, center: Vec3, radius: f32, resolution: usize) -> Option<(Range<usize>, Range<usize>)> {
+        const OUTLINE_POINTS: usize = 64;
+
+        let (tangent, bitangent) = center.any_orthonormal_pair();
+        let mut min = Vec2::splat(f32::MAX);
+        let mut max = Vec2::splat(f32::MIN);
+        for i in 0..OUTLINE_POINTS {
+            let angle = i as f32 / OUTLINE_POINTS as f32 * std::f32::consts::TAU;
+            let edge = center * radius.cos() + (tangent * angle.cos() + bitangent * angle.sin()) * radius.sin();
+            let Some(uv) = cubemap_face_uv(face, edge) else { return Some((0..resolution, 0..resolution)) };
+            min = min.min(uv);
+            max = max.max(uv);
+        }
+
+        // texel centres sit half a texel in, and a texel either side covers the points between samples
+        let size = resolution as f32;
+        let first = ((min * size - 1.5).floor()).max(Vec2::ZERO);
+        let last = ((max * size + 0.5).ceil()).min(Vec2::splat(size));
+        if first.x >= last.x || first.y >= last.y { return None };
+        Some((first.x as usize..last.x as usize, first.y as usize..last.y as usize))
+    }
+}
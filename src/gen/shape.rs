@@ -1,15 +1,17 @@
-use bevy::{prelude::*, render::extract_resource::ExtractResource};
+use bevy::{prelude::*, render::extract_component::ExtractComponent};
 use serde::{Serialize, Deserialize};
 
-use super::noise_filter::NoiseLayer;
+use super::{noise_filter::NoiseLayer, sculpt::SculptLayer};
 
 
-#[derive(Resource, ExtractResource, Clone, Serialize, Deserialize)]
+#[derive(Component, ExtractComponent, Clone, Serialize, Deserialize)]
 pub struct ShapeGenerator {
     pub radius: f32,
     pub sea_level: f32,
     pub num_layers: u32,
     pub noise_layers: Vec<NoiseLayer>,
+    #[serde(default)]
+    pub sculpt: SculptLayer,
 }
 
 impl Default for ShapeGenerator {
@@ -19,11 +21,15 @@ impl Default for ShapeGenerator {
             sea_level: 1.0,
             num_layers: 1,
             noise_layers: vec![NoiseLayer::new(0, true)],
+            sculpt: SculptLayer::default(),
         }
     }
 }
 
 impl ShapeGenerator {
+    /// Every layer is evaluated for every vertex, so more than this makes rebuilding the mesh crawl.
+    pub const MAX_NOISE_LAYERS: u32 = 32;
+
     pub fn get_point_and_elevation(&self, point_on_sphere: Vec3) -> (Vec3, f32) {
         let elevation = self.get_elevation(point_on_sphere);
         (point_on_sphere * elevation, elevation)
@@ -34,8 +40,38 @@ impl ShapeGenerator {
         point_on_sphere * elevation
     }
 
+    /// Surface normal from central differences of the elevation around `point_on_sphere`, taken
+    /// `epsilon` apart on the unit sphere. It only depends on the point itself, so meshes sharing a
+    /// vertex shade it identically regardless of which triangles surround it.
+    pub fn get_normal(&self, point_on_sphere: Vec3, epsilon: f32) -> Vec3 {
+        let (tangent, bitangent) = point_on_sphere.any_orthonormal_pair();
+
+        let dt = self.get_point((point_on_sphere + tangent * epsilon).normalize()) - self.get_point((point_on_sphere - tangent * epsilon).normalize());
+        let db = self.get_point((point_on_sphere + bitangent * epsilon).normalize()) - self.get_point((point_on_sphere - bitangent * epsilon).normalize());
+
+        let normal = dt.cross(db).normalize();
+        if normal.dot(point_on_sphere) < 0.0 { -normal } else { normal }
+    }
+
     pub fn get_elevation(&self, point_on_sphere: Vec3) -> f32 {
         let mut elevation = 0.0;
+        self.for_each_layer_contribution(point_on_sphere, |_, v| elevation += v);
+        elevation += self.sculpt.sample(point_on_sphere);
+
+        elevation = self.radius * (1.0 + elevation);
+        elevation
+    }
+
+    /// What each noise layer adds to the elevation at `point_on_sphere`, in the same units as the
+    /// radius. Disabled and warp layers contribute nothing.
+    pub fn get_layer_contributions(&self, point_on_sphere: Vec3) -> Vec<f32> {
+        let mut contributions = vec![0.0; self.noise_layers.len()];
+        self.for_each_layer_contribution(point_on_sphere, |i, v| contributions[i] = v * self.radius);
+        contributions
+    }
+
+    /// Calls `f` with the index and unscaled contribution of every layer that adds to the elevation.
+    fn for_each_layer_contribution(&self, point_on_sphere: Vec3, mut f: impl FnMut(usize, f32)) {
         let warp_targets: Vec<u32> = self.noise_layers.iter().map(|x| if x.is_warp && x.enabled { x.warp_target - 1 } else { self.num_layers }).collect();
 
         let first_layer = if warp_targets.contains(&0) {
@@ -45,7 +81,7 @@ impl ShapeGenerator {
             self.noise_layers[0].filter.evaluate(point_on_sphere)
         };
         if self.noise_layers[0].enabled {
-            elevation = first_layer;
+            f(0, first_layer);
         }
 
         for i in 1..self.num_layers {
@@ -58,12 +94,9 @@ impl ShapeGenerator {
                 } else {
                     layer.filter.evaluate(point_on_sphere)
                 };
-                elevation += v * mask;
+                f(i as usize, v * mask);
             }
         }
-
-        elevation = self.radius * (1.0 + elevation);
-        elevation
     }
 
     pub fn get_warped_pos(&self, p: Vec3, warp_source: &NoiseLayer) -> Vec3 {
@@ -72,4 +105,15 @@ impl ShapeGenerator {
         let z = warp_source.filter.evaluate(p + warp_source.filter.warp_offset.z);
         Vec3::new(x, y, z)
     }
-}
\ No newline at end of file
+}
+
+/// Direction from the planet centre for a latitude and longitude in radians. +Y is north and
+/// longitude zero faces +Z, increasing towards +X.
+pub fn lat_long_to_point(latitude: f32, longitude: f32) -> Vec3 {
+    Vec3::new(latitude.cos() * longitude.sin(), latitude.sin(), latitude.cos() * longitude.cos())
+}
+
+/// Latitude and longitude in radians of a point on the unit sphere, see `lat_long_to_point`.
+pub fn point_to_lat_long(point_on_sphere: Vec3) -> (f32, f32) {
+    (point_on_sphere.y.clamp(-1.0, 1.0).asin(), point_on_sphere.x.atan2(point_on_sphere.z))
+}
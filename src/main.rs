@@ -3,6 +3,7 @@ use bevy::{prelude::*, pbr::wireframe::WireframePlugin, core_pipeline::experimen
 pub mod gen;
 pub mod render;
 pub mod ui;
+pub mod export;
 
 use bevy_egui::EguiPlugin;
 use gen::*;
@@ -2,7 +2,9 @@ use bevy::{
     core_pipeline::{fullscreen_vertex_shader::fullscreen_shader_vertex_state, prepass::ViewPrepassTextures},
     prelude::*,
     render::{
-        extract_component::{ComponentUniforms, ExtractComponent},
+        camera::ExtractedCamera,
+        extract_component::{ComponentUniforms, DynamicUniformIndex, ExtractComponent},
+        render_asset::RenderAssets,
         render_graph::{NodeRunError, RenderGraphContext, ViewNode},
         render_resource::{
             BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
@@ -14,13 +16,15 @@ use bevy::{
         },
         renderer::{RenderContext, RenderDevice},
         texture::BevyDefault,
-        view::{ViewTarget, ViewUniforms, ViewUniform, ViewUniformOffset}
+        view::{ExtractedView, ViewTarget, ViewUniforms, ViewUniform, ViewUniformOffset}
     },
     ecs::query::QueryItem, pbr::{GpuLights, LightMeta, ViewLightsUniformOffset},
 };
 
 use crate::ui::render::UiRenderSettings;
 
+use super::{planet::{Planet, nearest_planet}, atmosphere_lut::AtmosphereLuts, clouds::draw_clouds, light::PlanetSunlight};
+
 
 #[derive(Default)]
 pub struct AtmospherePassPostProcessNode;
@@ -33,7 +37,8 @@ impl ViewNode for AtmospherePassPostProcessNode {
         &'static ViewTarget, 
         &'static ViewPrepassTextures,
         bevy::ecs::system::lifetimeless::Read<ViewUniformOffset>,
-        bevy::ecs::system::lifetimeless::Read<ViewLightsUniformOffset>
+        bevy::ecs::system::lifetimeless::Read<ViewLightsUniformOffset>,
+        &'static ViewAtmospheres,
     );
     
     fn run(
@@ -69,54 +74,80 @@ impl ViewNode for AtmospherePassPostProcessNode {
             return Ok(());
         };
 
-        let post_process = view_target.0.post_process_write();
-
-        let bind_group = render_context
-            .render_device()
-            .create_bind_group(&BindGroupDescriptor {
-                label: Some("atmosphere_pass_post_process_bind_group"),
-                layout: &post_process_pipeline.layout,
-                entries: &[
-                    BindGroupEntry {
-                        binding: 0,
-                        resource: BindingResource::TextureView(post_process.source),
-                    },
-                    BindGroupEntry {
-                        binding: 1,
-                        resource: BindingResource::TextureView(&depth_view),
-                    },
-                    BindGroupEntry {
-                        binding: 2,
-                        resource: BindingResource::Sampler(&post_process_pipeline.sampler),
-                    },
-                    BindGroupEntry {
-                        binding: 3,
-                        resource: settings_binding.clone(),
-                    },
-                    BindGroupEntry {
-                        binding: 4,
-                        resource: view_binding.clone(),
-                    },
-                    BindGroupEntry {
-                        binding: 5,
-                        resource: lights_binding.clone(),
-                    },
-                ],
-            });
+        let gpu_images = world.resource::<RenderAssets<Image>>();
 
-        let mut render_pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
-            label: Some("atmosphere_pass_post_process_pass"),
-            color_attachments: &[Some(RenderPassColorAttachment {
-                view: post_process.destination,
-                resolve_target: None,
-                ops: Operations::default(),
-            })],
-            depth_stencil_attachment: None,
-        });
+        // each planet's clouds and then its atmosphere are drawn over what's behind them, farthest first
+        for &planet in view_target.4.0.iter() {
+            draw_clouds(render_context, world, view_target.0, &depth_view, planet, view_target.2.offset, view_target.3.offset);
+
+            let (Some(settings_index), Some(luts)) = (
+                world.get::<DynamicUniformIndex<AtmosphereSettings>>(planet),
+                world.get::<AtmosphereLuts>(planet),
+            ) else { continue };
+
+            // the lookups exist as soon as their images are uploaded, even if they haven't been baked yet
+            let (Some(optical_depth_lut), Some(multi_scattering_lut)) = (
+                gpu_images.get(&luts.optical_depth),
+                gpu_images.get(&luts.multi_scattering),
+            ) else { continue };
+
+            let post_process = view_target.0.post_process_write();
+
+            let bind_group = render_context
+                .render_device()
+                .create_bind_group(&BindGroupDescriptor {
+                    label: Some("atmosphere_pass_post_process_bind_group"),
+                    layout: &post_process_pipeline.layout,
+                    entries: &[
+                        BindGroupEntry {
+                            binding: 0,
+                            resource: BindingResource::TextureView(post_process.source),
+                        },
+                        BindGroupEntry {
+                            binding: 1,
+                            resource: BindingResource::TextureView(&depth_view),
+                        },
+                        BindGroupEntry {
+                            binding: 2,
+                            resource: BindingResource::Sampler(&post_process_pipeline.sampler),
+                        },
+                        BindGroupEntry {
+                            binding: 3,
+                            resource: settings_binding.clone(),
+                        },
+                        BindGroupEntry {
+                            binding: 4,
+                            resource: view_binding.clone(),
+                        },
+                        BindGroupEntry {
+                            binding: 5,
+                            resource: lights_binding.clone(),
+                        },
+                        BindGroupEntry {
+                            binding: 6,
+                            resource: BindingResource::TextureView(&optical_depth_lut.texture_view),
+                        },
+                        BindGroupEntry {
+                            binding: 7,
+                            resource: BindingResource::TextureView(&multi_scattering_lut.texture_view),
+                        },
+                    ],
+                });
 
-        render_pass.set_render_pipeline(pipeline);
-        render_pass.set_bind_group(0, &bind_group, &[view_target.2.offset, view_target.3.offset]);
-        render_pass.draw(0..3, 0..1);
+            let mut render_pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
+                label: Some("atmosphere_pass_post_process_pass"),
+                color_attachments: &[Some(RenderPassColorAttachment {
+                    view: post_process.destination,
+                    resolve_target: None,
+                    ops: Operations::default(),
+                })],
+                depth_stencil_attachment: None,
+            });
+
+            render_pass.set_render_pipeline(pipeline);
+            render_pass.set_bind_group(0, &bind_group, &[settings_index.index(), view_target.2.offset, view_target.3.offset]);
+            render_pass.draw(0..3, 0..1);
+        }
 
         Ok(())
     }
@@ -167,7 +198,7 @@ impl FromWorld for AtmospherePassPostProcessPipeline {
                     visibility: ShaderStages::FRAGMENT,
                     ty: BindingType::Buffer {
                         ty: bevy::render::render_resource::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
+                        has_dynamic_offset: true,
                         min_binding_size: Some(AtmosphereSettings::min_size()),
                     },
                     count: None,
@@ -192,6 +223,26 @@ impl FromWorld for AtmospherePassPostProcessPipeline {
                     },
                     count: None,
                 },
+                BindGroupLayoutEntry {
+                    binding: 6,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Float { filterable: false },
+                        view_dimension: TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 7,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Float { filterable: false },
+                        view_dimension: TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
             ],
         });
 
@@ -232,7 +283,7 @@ impl FromWorld for AtmospherePassPostProcessPipeline {
 }
 
 
-#[derive(Component, Debug, Clone, Copy, ExtractComponent, ShaderType, Reflect)]
+#[derive(Component, Debug, Clone, Copy, PartialEq, ExtractComponent, ShaderType, Reflect)]
 #[reflect(Debug, Default)]
 pub struct AtmosphereSettings {
     pub radius: f32,
@@ -241,6 +292,22 @@ pub struct AtmosphereSettings {
     pub num_optical_depth_points: u32,
     pub density_falloff: f32,
     pub scattering_coeffs: Vec3,
+    pub center: Vec3,
+    pub mie_coeffs: Vec3,
+    pub mie_absorption: f32,
+    pub mie_falloff: f32,
+    pub mie_anisotropy: f32,
+    pub ozone_coeffs: Vec3,
+    pub ozone_height: f32,
+    pub ozone_width: f32,
+    /// Angular radius of the sun, in radians.
+    pub sun_disk_angle: f32,
+    pub sun_disk_intensity: f32,
+    pub sun_limb_darkening: f32,
+    /// The sunlight along the planet's own orbit, swapped in for the directional light at `sun_index`.
+    pub sun_direction: Vec3,
+    pub sun_scale: f32,
+    pub sun_index: u32,
 }
 
 impl Default for AtmosphereSettings {
@@ -252,15 +319,39 @@ impl Default for AtmosphereSettings {
             num_optical_depth_points: 10,
             density_falloff: 1.0,
             scattering_coeffs: Vec3::new(700.0, 530.0, 440.0),
+            center: Vec3::ZERO,
+            mie_coeffs: Vec3::ZERO,
+            mie_absorption: 0.0,
+            mie_falloff: 1.0,
+            mie_anisotropy: 0.0,
+            ozone_coeffs: Vec3::ZERO,
+            ozone_height: 0.0,
+            ozone_width: 1.0,
+            sun_disk_angle: 0.0,
+            sun_disk_intensity: 0.0,
+            sun_limb_darkening: 0.0,
+            sun_direction: Vec3::Z,
+            sun_scale: 1.0,
+            sun_index: u32::MAX,
         }
     }
 }
 
+/// Keeps each planet's atmosphere in step with its settings and position. Only the planet closest
+/// to the camera draws the sun's disk, so it isn't drawn once per atmosphere.
 pub fn update_atmosphere(
-    mut atmospheres: Query<&mut AtmosphereSettings>,
-    render_settings: Res<UiRenderSettings>,
+    mut planets: Query<(Entity, &mut AtmosphereSettings, &UiRenderSettings, &PlanetSunlight, &GlobalTransform), With<Planet>>,
+    cameras: Query<(&GlobalTransform, &Camera)>,
 ) {
-    for mut atmo in atmospheres.iter_mut() {
+    let nearest = cameras.iter()
+        .find(|(_, camera)| camera.is_active)
+        .and_then(|(camera_transform, _)| nearest_planet(
+            camera_transform.translation(),
+            planets.iter().map(|(entity, _, settings, _, transform)| (entity, transform.translation(), settings.atmosphere_radius)),
+        ));
+
+    for (entity, mut atmo, render_settings, sunlight, planet_transform) in planets.iter_mut() {
+        atmo.center = planet_transform.translation();
         atmo.radius = render_settings.atmosphere_radius;
         atmo.ocean_radius = render_settings.ocean_radius;
         atmo.num_sample_points = render_settings.atmosphere_sample_points;
@@ -272,5 +363,105 @@ pub fn update_atmosphere(
         scatter_coeffs = scatter_coeffs * scatter_coeffs * scatter_coeffs * scatter_coeffs;
         scatter_coeffs = scatter_coeffs * render_settings.atmosphere_scatter_strength;
         atmo.scattering_coeffs = scatter_coeffs;
+
+        atmo.mie_coeffs = Vec3::from(render_settings.atmosphere_mie_color) * render_settings.atmosphere_mie_strength;
+        atmo.mie_absorption = render_settings.atmosphere_mie_absorption * render_settings.atmosphere_mie_strength;
+        atmo.mie_falloff = render_settings.atmosphere_mie_falloff;
+        atmo.mie_anisotropy = render_settings.atmosphere_mie_anisotropy;
+        atmo.ozone_coeffs = Vec3::from(render_settings.atmosphere_ozone_absorption);
+        atmo.ozone_height = render_settings.atmosphere_ozone_height;
+        atmo.ozone_width = render_settings.atmosphere_ozone_width;
+        atmo.sun_disk_angle = (render_settings.sun_disk_size * 0.5).to_radians();
+        atmo.sun_disk_intensity = if nearest == Some(entity) { render_settings.sun_disk_intensity } else { 0.0 };
+        atmo.sun_limb_darkening = render_settings.sun_limb_darkening;
+        atmo.sun_direction = sunlight.direction;
+        atmo.sun_scale = sunlight.scale;
+        atmo.sun_index = sunlight.index;
+    }
+}
+
+/// The planets a view draws the clouds and atmospheres of, farthest first so nearer ones are drawn
+/// over them.
+#[derive(Component, Default)]
+pub struct ViewAtmospheres(pub Vec<Entity>);
+
+pub fn prepare_view_atmospheres(
+    mut commands: Commands,
+    views: Query<(Entity, &ExtractedView), With<ExtractedCamera>>,
+    atmospheres: Query<(Entity, &AtmosphereSettings)>,
+) {
+    for (view_entity, view) in views.iter() {
+        let position = view.transform.translation();
+        // an atmosphere with no thickness has nothing to draw
+        let mut sorted: Vec<(Entity, f32)> = atmospheres.iter()
+            .filter(|(_, settings)| settings.radius > settings.ocean_radius)
+            .map(|(entity, settings)| (entity, position.distance(settings.center) - settings.radius))
+            .collect();
+        sorted.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+
+        commands.entity(view_entity).insert(ViewAtmospheres(sorted.into_iter().map(|(entity, _)| entity).collect()));
     }
-}
\ No newline at end of file
+}
+
+/// Rough likenesses of real atmospheres, setting what the air is made of but leaving its size and
+/// the quality settings alone.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AtmospherePreset {
+    Earth,
+    Mars,
+    Titan,
+}
+
+impl AtmospherePreset {
+    pub const ALL: [AtmospherePreset; 3] = [AtmospherePreset::Earth, AtmospherePreset::Mars, AtmospherePreset::Titan];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            AtmospherePreset::Earth => "Earth",
+            AtmospherePreset::Mars => "Mars",
+            AtmospherePreset::Titan => "Titan",
+        }
+    }
+
+    pub fn apply(&self, settings: &mut UiRenderSettings) {
+        settings.atmosphere_scatter_coeffs = [700.0, 530.0, 440.0];
+        match self {
+            // a blue sky from the air itself, a thin haze near the ground and an ozone layer that
+            // keeps twilight blue
+            AtmospherePreset::Earth => {
+                settings.atmosphere_density_falloff = 4.0;
+                settings.atmosphere_scatter_strength = 20.0;
+                settings.atmosphere_mie_strength = 2.0;
+                settings.atmosphere_mie_color = [1.0; 3];
+                settings.atmosphere_mie_falloff = 20.0;
+                settings.atmosphere_mie_anisotropy = 0.76;
+                settings.atmosphere_mie_absorption = 0.1;
+                settings.atmosphere_ozone_absorption = [0.3, 0.8, 0.04];
+                settings.atmosphere_ozone_height = 0.25;
+                settings.atmosphere_ozone_width = 0.15;
+            }
+            // thin air full of reddish dust, which scatters red away from the sun and leaves blue sunsets
+            AtmospherePreset::Mars => {
+                settings.atmosphere_density_falloff = 3.0;
+                settings.atmosphere_scatter_strength = 2.0;
+                settings.atmosphere_mie_strength = 8.0;
+                settings.atmosphere_mie_color = [1.0, 0.6, 0.35];
+                settings.atmosphere_mie_falloff = 5.0;
+                settings.atmosphere_mie_anisotropy = 0.65;
+                settings.atmosphere_mie_absorption = 0.4;
+                settings.atmosphere_ozone_absorption = [0.0; 3];
+            }
+            // a deep orange haze reaching high above the ground, which swallows most of the blue
+            AtmospherePreset::Titan => {
+                settings.atmosphere_density_falloff = 1.5;
+                settings.atmosphere_scatter_strength = 6.0;
+                settings.atmosphere_mie_strength = 12.0;
+                settings.atmosphere_mie_color = [1.0, 0.7, 0.35];
+                settings.atmosphere_mie_falloff = 2.5;
+                settings.atmosphere_mie_anisotropy = 0.6;
+                settings.atmosphere_mie_absorption = 0.8;
+                settings.atmosphere_ozone_absorption = [0.0; 3];
+            }
+        }
+    }
+}
@@ -0,0 +1,309 @@
+use std::borrow::Cow;
+
+use bevy::{
+    prelude::*,
+    render::{
+        extract_component::{ComponentUniforms, DynamicUniformIndex, ExtractComponent},
+        render_asset::RenderAssets,
+        render_graph,
+        render_resource::{
+            BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
+            BindGroupLayoutEntry, BindingResource, BindingType, BufferBindingType, CachedComputePipelineId,
+            ComputePassDescriptor, ComputePipelineDescriptor, Extent3d, PipelineCache, ShaderStages, ShaderType,
+            StorageTextureAccess, TextureDimension, TextureFormat, TextureSampleType, TextureUsages, TextureViewDimension,
+        },
+        renderer::{RenderContext, RenderDevice},
+    },
+    utils::HashMap,
+};
+
+use super::atmosphere::AtmosphereSettings;
+
+
+/// Texels along the view angle and height axes of the optical depth lookup.
+pub const OPTICAL_DEPTH_LUT_SIZE: UVec2 = UVec2::new(256, 64);
+/// Texels along the sun angle and height axes of the multiple scattering lookup.
+pub const MULTI_SCATTERING_LUT_SIZE: UVec2 = UVec2::new(32, 32);
+const WORKGROUP_SIZE: u32 = 8;
+
+/// The lookups a planet's atmosphere is drawn from, baked by a compute pass whenever the settings
+/// they depend on change.
+#[derive(Component, Clone, ExtractComponent)]
+pub struct AtmosphereLuts {
+    /// Optical depth of the air, haze and ozone from each height out of the atmosphere, by the cosine
+    /// of the angle from straight up.
+    pub optical_depth: Handle<Image>,
+    /// Light scattered more than once, by height and the cosine of the sun's angle from straight up.
+    pub multi_scattering: Handle<Image>,
+    /// Bumped whenever the lookups need baking again.
+    pub generation: u32,
+    baked_from: Option<AtmosphereSettings>,
+}
+
+fn lut_image(size: UVec2, images: &mut Assets<Image>) -> Handle<Image> {
+    let mut image = Image::new_fill(
+        Extent3d {
+            width: size.x,
+            height: size.y,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        &[0; 16],
+        TextureFormat::Rgba32Float,
+    );
+
+    image.texture_descriptor.usage =
+        TextureUsages::COPY_DST | TextureUsages::STORAGE_BINDING | TextureUsages::TEXTURE_BINDING;
+    images.add(image)
+}
+
+/// Gives every atmosphere lookups of its own to bake into.
+pub fn setup_atmosphere_luts(
+    mut commands: Commands,
+    mut images: ResMut<Assets<Image>>,
+    atmospheres: Query<Entity, (With<AtmosphereSettings>, Without<AtmosphereLuts>)>,
+) {
+    for atmosphere in atmospheres.iter() {
+        commands.entity(atmosphere).insert(AtmosphereLuts {
+            optical_depth: lut_image(OPTICAL_DEPTH_LUT_SIZE, &mut images),
+            multi_scattering: lut_image(MULTI_SCATTERING_LUT_SIZE, &mut images),
+            generation: 0,
+            baked_from: None,
+        });
+    }
+}
+
+/// Asks for the lookups to be baked again when the atmosphere they were baked from changes shape or color.
+pub fn update_atmosphere_luts(
+    mut atmospheres: Query<(&AtmosphereSettings, &mut AtmosphereLuts)>,
+) {
+    for (settings, mut luts) in atmospheres.iter_mut() {
+        // the lookups are in the planet's frame, and don't depend on how finely the view ray is
+        // sampled, which way the haze scatters or how and where the sun shines
+        let baked_from = AtmosphereSettings {
+            center: Vec3::ZERO,
+            num_sample_points: 0,
+            mie_anisotropy: 0.0,
+            sun_disk_angle: 0.0,
+            sun_disk_intensity: 0.0,
+            sun_limb_darkening: 0.0,
+            sun_direction: Vec3::ZERO,
+            sun_scale: 0.0,
+            sun_index: 0,
+            ..*settings
+        };
+        if luts.baked_from == Some(baked_from) { continue };
+
+        luts.baked_from = Some(baked_from);
+        luts.generation = luts.generation.wrapping_add(1);
+    }
+}
+
+
+/// The bind groups of the lookups to bake this frame.
+#[derive(Resource, Default)]
+pub struct AtmosphereLutBindGroups(pub Vec<AtmosphereLutBindGroup>);
+
+pub struct AtmosphereLutBindGroup {
+    optical_depth: BindGroup,
+    multi_scattering: BindGroup,
+    settings_offset: u32,
+}
+
+/// The generation each atmosphere's lookups were last baked at.
+#[derive(Resource, Default)]
+pub struct BakedAtmosphereLuts(pub HashMap<Entity, u32>);
+
+#[allow(clippy::too_many_arguments)]
+pub fn queue_atmosphere_lut_bind_groups(
+    mut bind_groups: ResMut<AtmosphereLutBindGroups>,
+    mut baked: ResMut<BakedAtmosphereLuts>,
+    pipeline: Res<AtmosphereLutPipeline>,
+    pipeline_cache: Res<PipelineCache>,
+    gpu_images: Res<RenderAssets<Image>>,
+    settings_uniforms: Res<ComponentUniforms<AtmosphereSettings>>,
+    atmospheres: Query<(Entity, &AtmosphereLuts, &DynamicUniformIndex<AtmosphereSettings>)>,
+    render_device: Res<RenderDevice>,
+) {
+    bind_groups.0.clear();
+    baked.0.retain(|entity, _| atmospheres.contains(*entity));
+
+    // nothing counts as baked until the pipelines are there to bake it
+    if pipeline_cache.get_compute_pipeline(pipeline.optical_depth_pipeline).is_none()
+        || pipeline_cache.get_compute_pipeline(pipeline.multi_scattering_pipeline).is_none() {
+        return;
+    }
+    let Some(settings_binding) = settings_uniforms.uniforms().binding() else { return };
+
+    for (entity, luts, settings_index) in atmospheres.iter() {
+        if baked.0.get(&entity) == Some(&luts.generation) { continue };
+        let Some(optical_depth) = gpu_images.get(&luts.optical_depth) else { continue };
+        let Some(multi_scattering) = gpu_images.get(&luts.multi_scattering) else { continue };
+
+        let optical_depth_bind_group = render_device.create_bind_group(&BindGroupDescriptor {
+            label: Some("atmosphere_optical_depth_lut_bind_group"),
+            layout: &pipeline.optical_depth_layout,
+            entries: &[BindGroupEntry {
+                binding: 0,
+                resource: settings_binding.clone(),
+            }, BindGroupEntry {
+                binding: 1,
+                resource: BindingResource::TextureView(&optical_depth.texture_view),
+            }],
+        });
+        let multi_scattering_bind_group = render_device.create_bind_group(&BindGroupDescriptor {
+            label: Some("atmosphere_multi_scattering_lut_bind_group"),
+            layout: &pipeline.multi_scattering_layout,
+            entries: &[BindGroupEntry {
+                binding: 0,
+                resource: settings_binding.clone(),
+            }, BindGroupEntry {
+                binding: 2,
+                resource: BindingResource::TextureView(&optical_depth.texture_view),
+            }, BindGroupEntry {
+                binding: 3,
+                resource: BindingResource::TextureView(&multi_scattering.texture_view),
+            }],
+        });
+
+        bind_groups.0.push(AtmosphereLutBindGroup {
+            optical_depth: optical_depth_bind_group,
+            multi_scattering: multi_scattering_bind_group,
+            settings_offset: settings_index.index(),
+        });
+        baked.0.insert(entity, luts.generation);
+    }
+}
+
+#[derive(Resource)]
+pub struct AtmosphereLutPipeline {
+    optical_depth_layout: BindGroupLayout,
+    multi_scattering_layout: BindGroupLayout,
+    optical_depth_pipeline: CachedComputePipelineId,
+    multi_scattering_pipeline: CachedComputePipelineId,
+}
+
+impl FromWorld for AtmosphereLutPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let render_device = world.resource::<RenderDevice>();
+
+        let settings_entry = BindGroupLayoutEntry {
+            binding: 0,
+            visibility: ShaderStages::COMPUTE,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Uniform,
+                has_dynamic_offset: true,
+                min_binding_size: Some(AtmosphereSettings::min_size()),
+            },
+            count: None,
+        };
+        let storage_entry = |binding| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::COMPUTE,
+            ty: BindingType::StorageTexture {
+                access: StorageTextureAccess::WriteOnly,
+                format: TextureFormat::Rgba32Float,
+                view_dimension: TextureViewDimension::D2,
+            },
+            count: None,
+        };
+
+        let optical_depth_layout = render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("atmosphere_optical_depth_lut_bind_group_layout"),
+            entries: &[settings_entry, storage_entry(1)],
+        });
+        let multi_scattering_layout = render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("atmosphere_multi_scattering_lut_bind_group_layout"),
+            entries: &[settings_entry, BindGroupLayoutEntry {
+                binding: 2,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Texture {
+                    sample_type: TextureSampleType::Float { filterable: false },
+                    view_dimension: TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            }, storage_entry(3)],
+        });
+
+        let shader = world
+            .resource::<AssetServer>()
+            .load("shaders/atmosphere_lut.wgsl");
+        let pipeline_cache = world.resource::<PipelineCache>();
+        let optical_depth_pipeline = pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
+            label: Some("atmosphere_optical_depth_lut_pipeline".into()),
+            layout: vec![optical_depth_layout.clone()],
+            push_constant_ranges: Vec::new(),
+            shader: shader.clone(),
+            shader_defs: vec![],
+            entry_point: Cow::from("transmittance"),
+        });
+        let multi_scattering_pipeline = pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
+            label: Some("atmosphere_multi_scattering_lut_pipeline".into()),
+            layout: vec![multi_scattering_layout.clone()],
+            push_constant_ranges: Vec::new(),
+            shader,
+            shader_defs: vec![],
+            entry_point: Cow::from("multi_scattering"),
+        });
+
+        Self {
+            optical_depth_layout,
+            multi_scattering_layout,
+            optical_depth_pipeline,
+            multi_scattering_pipeline,
+        }
+    }
+}
+
+/// Bakes the optical depth lookup, then the multiple scattering lookup from it, for each atmosphere
+/// that changed.
+#[derive(Default)]
+pub struct AtmosphereLutNode;
+
+impl AtmosphereLutNode {
+    pub const NODE_NAME: &'static str = "atmosphere_lut";
+}
+
+impl render_graph::Node for AtmosphereLutNode {
+    fn run(
+        &self,
+        _graph: &mut render_graph::RenderGraphContext,
+        render_context: &mut RenderContext,
+        world: &World,
+    ) -> Result<(), render_graph::NodeRunError> {
+        let bind_groups = &world.resource::<AtmosphereLutBindGroups>().0;
+        let pipeline_cache = world.resource::<PipelineCache>();
+        let pipeline = world.resource::<AtmosphereLutPipeline>();
+
+        let (Some(optical_depth_pipeline), Some(multi_scattering_pipeline)) = (
+            pipeline_cache.get_compute_pipeline(pipeline.optical_depth_pipeline),
+            pipeline_cache.get_compute_pipeline(pipeline.multi_scattering_pipeline),
+        ) else { return Ok(()) };
+
+        let encoder = render_context.command_encoder();
+        for bind_group in bind_groups.iter() {
+            {
+                let mut pass = encoder.begin_compute_pass(&ComputePassDescriptor::default());
+                pass.set_pipeline(optical_depth_pipeline);
+                pass.set_bind_group(0, &bind_group.optical_depth, &[bind_group.settings_offset]);
+                pass.dispatch_workgroups(
+                    OPTICAL_DEPTH_LUT_SIZE.x.div_ceil(WORKGROUP_SIZE),
+                    OPTICAL_DEPTH_LUT_SIZE.y.div_ceil(WORKGROUP_SIZE),
+                    1,
+                );
+            }
+
+            let mut pass = encoder.begin_compute_pass(&ComputePassDescriptor::default());
+            pass.set_pipeline(multi_scattering_pipeline);
+            pass.set_bind_group(0, &bind_group.multi_scattering, &[bind_group.settings_offset]);
+            pass.dispatch_workgroups(
+                MULTI_SCATTERING_LUT_SIZE.x.div_ceil(WORKGROUP_SIZE),
+                MULTI_SCATTERING_LUT_SIZE.y.div_ceil(WORKGROUP_SIZE),
+                1,
+            );
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,372 @@
+use bevy::{
+    core_pipeline::fullscreen_vertex_shader::fullscreen_shader_vertex_state,
+    prelude::*,
+    render::{
+        extract_component::{ComponentUniforms, DynamicUniformIndex, ExtractComponent},
+        render_resource::{
+            BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
+            BindGroupLayoutEntry, BindingResource, BindingType, CachedRenderPipelineId,
+            ColorTargetState, ColorWrites, FragmentState, MultisampleState, Operations,
+            PipelineCache, PrimitiveState, RenderPassColorAttachment, RenderPassDescriptor,
+            RenderPipelineDescriptor, Sampler, SamplerBindingType, SamplerDescriptor, ShaderStages,
+            ShaderType, TextureFormat, TextureSampleType, TextureView, TextureViewDimension, BufferBindingType,
+        },
+        renderer::{RenderContext, RenderDevice},
+        texture::BevyDefault,
+        view::{ViewTarget, ViewUniforms, ViewUniform}
+    },
+    pbr::{GpuLights, LightMeta},
+};
+use serde::{Serialize, Deserialize};
+
+use crate::ui::render::UiRenderSettings;
+
+use super::{planet::Planet, orbit::SimClock, light::PlanetSunlight};
+
+
+/// Draws one planet's clouds over everything behind them. The atmosphere pass calls this for each
+/// planet in turn, farthest first, just before drawing the planet's atmosphere over its clouds.
+pub fn draw_clouds(
+    render_context: &mut RenderContext,
+    world: &World,
+    view_target: &ViewTarget,
+    depth_view: &TextureView,
+    planet: Entity,
+    view_offset: u32,
+    lights_offset: u32,
+) {
+    let (Some(settings), Some(settings_index)) = (
+        world.get::<CloudSettings>(planet),
+        world.get::<DynamicUniformIndex<CloudSettings>>(planet),
+    ) else { return };
+    if settings.enabled == 0 { return };
+
+    let post_process_pipeline = world.resource::<CloudPassPostProcessPipeline>();
+
+    let pipeline_cache = world.resource::<PipelineCache>();
+
+    let Some(pipeline) = pipeline_cache.get_render_pipeline(post_process_pipeline.pipeline_id) else {
+        return;
+    };
+
+    let settings_uniforms = world.resource::<ComponentUniforms<CloudSettings>>();
+    let Some(settings_binding) = settings_uniforms.uniforms().binding() else {
+        return;
+    };
+
+    let Some(view_binding) = world.resource::<ViewUniforms>().uniforms.binding() else {
+        return;
+    };
+
+    let Some(lights_binding) = world.resource::<LightMeta>().view_gpu_lights.binding() else {
+        return;
+    };
+
+    let post_process = view_target.post_process_write();
+
+    let bind_group = render_context
+        .render_device()
+        .create_bind_group(&BindGroupDescriptor {
+            label: Some("cloud_pass_post_process_bind_group"),
+            layout: &post_process_pipeline.layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(post_process.source),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::TextureView(depth_view),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: BindingResource::Sampler(&post_process_pipeline.sampler),
+                },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: settings_binding.clone(),
+                },
+                BindGroupEntry {
+                    binding: 4,
+                    resource: view_binding.clone(),
+                },
+                BindGroupEntry {
+                    binding: 5,
+                    resource: lights_binding.clone(),
+                },
+            ],
+        });
+
+    let mut render_pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
+        label: Some("cloud_pass_post_process_pass"),
+        color_attachments: &[Some(RenderPassColorAttachment {
+            view: post_process.destination,
+            resolve_target: None,
+            ops: Operations::default(),
+        })],
+        depth_stencil_attachment: None,
+    });
+
+    render_pass.set_render_pipeline(pipeline);
+    render_pass.set_bind_group(0, &bind_group, &[settings_index.index(), view_offset, lights_offset]);
+    render_pass.draw(0..3, 0..1);
+}
+
+#[derive(Resource)]
+pub struct CloudPassPostProcessPipeline {
+    layout: BindGroupLayout,
+    sampler: Sampler,
+    pipeline_id: CachedRenderPipelineId,
+}
+
+impl FromWorld for CloudPassPostProcessPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let render_device = world.resource::<RenderDevice>();
+
+        let layout = render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("cloud_pass_post_process_bind_group_layout"),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Float { filterable: true },
+                        view_dimension: TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Depth,
+                        view_dimension: TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: true,
+                        min_binding_size: Some(CloudSettings::min_size()),
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: true,
+                        min_binding_size: Some(ViewUniform::min_size()),
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 5,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: true,
+                        min_binding_size: Some(GpuLights::min_size()),
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let sampler = render_device.create_sampler(&SamplerDescriptor::default());
+
+        let shader = world
+            .resource::<AssetServer>()
+            .load("shaders/clouds.wgsl");
+
+        let pipeline_id = world
+            .resource_mut::<PipelineCache>()
+            .queue_render_pipeline(RenderPipelineDescriptor {
+                label: Some("cloud_pass_post_process_pipeline".into()),
+                layout: vec![layout.clone()],
+                vertex: fullscreen_shader_vertex_state(),
+                fragment: Some(FragmentState {
+                    shader,
+                    shader_defs: vec![],
+                    entry_point: "fragment".into(),
+                    targets: vec![Some(ColorTargetState {
+                        format: TextureFormat::bevy_default(),
+                        blend: None,
+                        write_mask: ColorWrites::ALL,
+                    })],
+                }),
+                primitive: PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: MultisampleState::default(),
+                push_constant_ranges: vec![],
+            });
+
+        Self {
+            layout,
+            sampler,
+            pipeline_id,
+        }
+    }
+}
+
+
+/// A planet's cloud layer, as it's edited and saved.
+#[derive(Component, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PlanetClouds {
+    pub enabled: bool,
+    /// The bottom and top of the layer, as fractions of the way from sea level up to the edge of the atmosphere.
+    pub min_height: f32,
+    pub max_height: f32,
+    /// How much of the sky is overcast, from clear at 0 to fully covered at 1.
+    pub coverage: f32,
+    /// Extinction per unit of distance through the thickest cloud.
+    pub density: f32,
+    /// Frequencies of the noise shaping the clouds and eroding their edges.
+    pub noise_scale: f32,
+    pub detail_scale: f32,
+    pub seed: u32,
+    /// How fast the clouds drift, in planet space units per simulated second.
+    pub wind: [f32; 3],
+    /// How fast the detail noise drifts against the wind, which keeps the cloud shapes changing.
+    pub turbulence: f32,
+    pub color: [f32; 3],
+    /// How dark the shadows the clouds cast onto the ground get.
+    pub shadow_strength: f32,
+    pub num_steps: u32,
+    pub num_light_steps: u32,
+}
+
+impl Default for PlanetClouds {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            min_height: 0.08,
+            max_height: 0.2,
+            coverage: 0.45,
+            density: 40.0,
+            noise_scale: 3.0,
+            detail_scale: 14.0,
+            seed: 0,
+            wind: [0.01, 0.0, 0.005],
+            turbulence: 0.02,
+            color: [1.0; 3],
+            shadow_strength: 0.8,
+            num_steps: 48,
+            num_light_steps: 6,
+        }
+    }
+}
+
+/// A cloud layer as the shaders see it. Each planet carries one for the clouds drawn over the view,
+/// and its material a copy for the shadows they cast.
+#[derive(Component, Debug, Clone, Copy, ExtractComponent, ShaderType, Reflect)]
+#[reflect(Debug, Default)]
+pub struct CloudSettings {
+    pub center: Vec3,
+    /// Rotates planet space into world space, so the clouds turn with the planet.
+    pub rotation: Mat3,
+    pub inner_radius: f32,
+    pub outer_radius: f32,
+    /// The sphere the clouds stop at when nothing nearer is in the depth buffer.
+    pub surface_radius: f32,
+    pub coverage: f32,
+    pub density: f32,
+    pub noise_scale: f32,
+    pub detail_scale: f32,
+    pub shadow_strength: f32,
+    /// Where the base and detail noise have drifted to by now.
+    pub wind_offset: Vec3,
+    pub detail_offset: Vec3,
+    pub color: Vec3,
+    pub num_steps: u32,
+    pub num_light_steps: u32,
+    pub enabled: u32,
+    /// The sunlight along the planet's own orbit, swapped in for the directional light at `sun_index`.
+    pub sun_direction: Vec3,
+    pub sun_scale: f32,
+    pub sun_index: u32,
+}
+
+impl Default for CloudSettings {
+    fn default() -> Self {
+        Self {
+            center: Vec3::ZERO,
+            rotation: Mat3::IDENTITY,
+            inner_radius: 1.0,
+            outer_radius: 1.0,
+            surface_radius: 1.0,
+            coverage: 0.0,
+            density: 0.0,
+            noise_scale: 1.0,
+            detail_scale: 1.0,
+            shadow_strength: 0.0,
+            wind_offset: Vec3::ZERO,
+            detail_offset: Vec3::ZERO,
+            color: Vec3::ONE,
+            num_steps: 0,
+            num_light_steps: 0,
+            enabled: 0,
+            sun_direction: Vec3::Z,
+            sun_scale: 1.0,
+            sun_index: u32::MAX,
+        }
+    }
+}
+
+impl CloudSettings {
+    pub fn new(clouds: &PlanetClouds, render_settings: &UiRenderSettings, sunlight: &PlanetSunlight, transform: &GlobalTransform, time: f64) -> Self {
+        let sea_level = render_settings.ocean_radius;
+        let thickness = (render_settings.atmosphere_radius - sea_level).max(0.0);
+        let min_height = clouds.min_height.min(clouds.max_height);
+
+        // drift in double precision, so the clouds don't start stuttering after a long time
+        let drift = |velocity: Vec3| (velocity.as_dvec3() * time).as_vec3();
+        let wind = Vec3::from(clouds.wind);
+        let seed_offset = Vec3::new(17.13, 31.71, 47.37) * (clouds.seed % 1024) as f32;
+
+        Self {
+            center: transform.translation(),
+            rotation: Mat3::from_quat(transform.to_scale_rotation_translation().1),
+            inner_radius: sea_level + thickness * min_height,
+            outer_radius: sea_level + thickness * clouds.max_height.max(min_height + 0.001),
+            surface_radius: sea_level,
+            coverage: clouds.coverage,
+            density: clouds.density,
+            noise_scale: clouds.noise_scale,
+            detail_scale: clouds.detail_scale,
+            shadow_strength: clouds.shadow_strength,
+            wind_offset: drift(wind * clouds.noise_scale) + seed_offset,
+            detail_offset: drift((wind + Vec3::Y * clouds.turbulence) * clouds.detail_scale) - seed_offset,
+            color: Vec3::from(clouds.color),
+            num_steps: clouds.num_steps,
+            num_light_steps: clouds.num_light_steps,
+            enabled: clouds.enabled as u32,
+            sun_direction: sunlight.direction,
+            sun_scale: sunlight.scale,
+            sun_index: sunlight.index,
+        }
+    }
+}
+
+/// Keeps each planet's clouds in step with its settings, position and the simulated time.
+pub fn update_clouds(
+    mut planets: Query<(&mut CloudSettings, &PlanetClouds, &UiRenderSettings, &PlanetSunlight, &GlobalTransform), With<Planet>>,
+    clock: Res<SimClock>,
+) {
+    for (mut settings, clouds, render_settings, sunlight, planet_transform) in planets.iter_mut() {
+        *settings = CloudSettings::new(clouds, render_settings, sunlight, planet_transform, clock.time);
+    }
+}
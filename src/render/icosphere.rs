@@ -0,0 +1,186 @@
+use bevy::{prelude::*, render::{mesh::Indices, render_resource::PrimitiveTopology}, tasks::AsyncComputeTaskPool};
+
+use crate::gen::shape::ShapeGenerator;
+
+use super::{planet::{Planet, PlanetTopology, ChunkMesh}, lod::{ChunkMeshTask, TerrainChunk, TerrainQuadtree}};
+
+
+const PHI: f32 = 1.618034;
+
+const ICOSAHEDRON_VERTICES: [Vec3; 12] = [
+    Vec3::new(-1.0, PHI, 0.0), Vec3::new(1.0, PHI, 0.0), Vec3::new(-1.0, -PHI, 0.0), Vec3::new(1.0, -PHI, 0.0),
+    Vec3::new(0.0, -1.0, PHI), Vec3::new(0.0, 1.0, PHI), Vec3::new(0.0, -1.0, -PHI), Vec3::new(0.0, 1.0, -PHI),
+    Vec3::new(PHI, 0.0, -1.0), Vec3::new(PHI, 0.0, 1.0), Vec3::new(-PHI, 0.0, -1.0), Vec3::new(-PHI, 0.0, 1.0),
+];
+
+const ICOSAHEDRON_FACES: [[usize; 3]; 20] = [
+    [0, 11, 5], [0, 5, 1], [0, 1, 7], [0, 7, 10], [0, 10, 11],
+    [1, 5, 9], [5, 11, 4], [11, 10, 2], [10, 7, 6], [7, 1, 8],
+    [3, 9, 4], [3, 4, 2], [3, 2, 6], [3, 6, 8], [3, 8, 9],
+    [4, 9, 5], [2, 4, 11], [6, 2, 10], [8, 6, 7], [9, 8, 1],
+];
+
+
+/// One of the twenty base triangles of the icosahedron, subdivided and displaced as its own mesh
+/// so each can be culled separately.
+#[derive(Component)]
+pub struct IcospherePatch {
+    pub index: usize,
+}
+
+impl IcospherePatch {
+    pub const COUNT: usize = ICOSAHEDRON_FACES.len();
+
+    pub fn corners(&self) -> [Vec3; 3] {
+        ICOSAHEDRON_FACES[self.index].map(|i| ICOSAHEDRON_VERTICES[i].normalize())
+    }
+}
+
+pub fn spawn_patch_task(
+    patch: &IcospherePatch,
+    resolution: u32,
+    shape_gen: &ShapeGenerator,
+) -> ChunkMeshTask {
+    let corners = patch.corners();
+    let shape_gen = shape_gen.clone();
+    let task = AsyncComputeTaskPool::get().spawn(async move {
+        generate_patch_mesh(corners, resolution, &shape_gen)
+    });
+    ChunkMeshTask(task)
+}
+
+/// Builds a geodesic patch with `resolution` vertices along each edge of the base triangle.
+pub fn generate_patch_mesh(
+    corners: [Vec3; 3],
+    resolution: u32,
+    shape_gen: &ShapeGenerator,
+) -> ChunkMesh {
+    let mut min_elevation = f32::MAX;
+    let mut max_elevation = f32::MIN;
+
+    let n = resolution - 1;
+    let index = |row: u32, col: u32| row * (row + 1) / 2 + col;
+    let [a, b, c] = corners;
+
+    let num_vertices = (resolution * (resolution + 1) / 2) as usize;
+    let mut positions = Vec::with_capacity(num_vertices);
+    let mut normals = vec![Vec3::ZERO; num_vertices];
+    let mut uvs = Vec::with_capacity(num_vertices);
+    let mut indices = Vec::with_capacity((n * n * 3) as usize);
+
+    for row in 0..=n {
+        for col in 0..=row {
+            let uv = Vec2::new(row as f32, col as f32) / n as f32;
+            let point_on_sphere = (a + (b - a) * uv.x + (c - b) * uv.y).normalize();
+            let (position, elevation) = shape_gen.get_point_and_elevation(point_on_sphere);
+
+            min_elevation = min_elevation.min(elevation);
+            max_elevation = max_elevation.max(elevation);
+
+            positions.push(position);
+            uvs.push(uv);
+        }
+    }
+
+    // the base faces don't all wind the same way, so orient every triangle outwards
+    let flip = (b - a).cross(c - a).dot(a) < 0.0;
+    let mut push_triangle = |i0: u32, i1: u32, i2: u32| {
+        if flip {
+            indices.extend_from_slice(&[i0, i2, i1]);
+        } else {
+            indices.extend_from_slice(&[i0, i1, i2]);
+        }
+    };
+    for row in 0..n {
+        for col in 0..=row {
+            push_triangle(index(row, col), index(row + 1, col), index(row + 1, col + 1));
+            if col < row {
+                push_triangle(index(row, col), index(row + 1, col + 1), index(row, col + 1));
+            }
+        }
+    }
+
+    for triangle in indices.chunks(3) {
+        let (i0, i1, i2) = (triangle[0] as usize, triangle[1] as usize, triangle[2] as usize);
+        let face_normal = (positions[i1] - positions[i0]).cross(positions[i2] - positions[i0]);
+        normals[i0] += face_normal;
+        normals[i1] += face_normal;
+        normals[i2] += face_normal;
+    }
+    normals = normals.iter().map(|x| x.normalize()).collect();
+
+    // border vertices are shared with the neighbouring patches, see `generate_chunk_mesh`
+    let epsilon = a.distance(b) / n as f32;
+    for row in 0..=n {
+        for col in 0..=row {
+            if row == n || col == 0 || col == row {
+                let i = index(row, col) as usize;
+                normals[i] = shape_gen.get_normal(positions[i].normalize(), epsilon);
+            }
+        }
+    }
+
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+    mesh.set_indices(Some(Indices::U32(indices)));
+
+    ChunkMesh {
+        mesh,
+        min_elevation,
+        max_elevation,
+    }
+}
+
+
+/// Swaps a planet's terrain entities over when its base topology changes.
+pub fn update_planet_topology(
+    mut commands: Commands,
+    mut planets: Query<(Entity, &mut Planet, &ShapeGenerator)>,
+    chunks: Query<(Entity, &Parent), With<TerrainChunk>>,
+    patches: Query<(Entity, &Parent), With<IcospherePatch>>,
+    mut quadtrees: Query<&mut TerrainQuadtree>,
+) {
+    for (planet_entity, mut planet, shape_gen) in planets.iter_mut() {
+        if planet.topology == planet.built_topology { continue };
+        planet.built_topology = planet.topology;
+
+        planet.min_elevation = f32::MAX;
+        planet.max_elevation = f32::MIN;
+        planet.regeneration = None;
+
+        match planet.topology {
+            PlanetTopology::CubeSphere => {
+                for (entity, parent) in patches.iter() {
+                    if parent.get() == planet_entity {
+                        commands.entity(entity).despawn_recursive();
+                    }
+                }
+                // the chunks are rebuilt by the lod system from fresh quadtrees
+            }
+            PlanetTopology::Icosphere => {
+                for (entity, parent) in chunks.iter() {
+                    if parent.get() == planet_entity {
+                        commands.entity(entity).despawn_recursive();
+                    }
+                }
+                for face in planet.terrain_faces {
+                    if let Ok(mut quadtree) = quadtrees.get_mut(face) {
+                        *quadtree = TerrainQuadtree::default();
+                    }
+                }
+
+                for index in 0..IcospherePatch::COUNT {
+                    let patch = IcospherePatch { index };
+                    let task = spawn_patch_task(&patch, planet.resolution, shape_gen);
+                    commands.spawn((
+                        patch,
+                        task,
+                        SpatialBundle::default(),
+                    )).set_parent(planet_entity);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,298 @@
+use bevy::{
+    prelude::*,
+    core_pipeline::{core_3d::Opaque3d, tonemapping::{DebandDither, Tonemapping}},
+    ecs::system::{lifetimeless::*, SystemParamItem},
+    pbr::{MeshPipeline, MeshPipelineKey, MeshUniform, SetMeshBindGroup, SetMeshViewBindGroup},
+    render::{
+        mesh::{GpuBufferInfo, MeshVertexBufferLayout},
+        render_asset::RenderAssets,
+        render_phase::{AddRenderCommand, DrawFunctions, PhaseItem, RenderCommand, RenderCommandResult, RenderPhase, SetItemPipeline, TrackedRenderPass},
+        render_resource::*,
+        renderer::{RenderDevice, RenderQueue},
+        view::ExtractedView,
+        Extract, ExtractSchedule, Render, RenderApp, RenderSet,
+    },
+    utils::HashMap,
+};
+use bytemuck::{Pod, Zeroable};
+
+
+/// One copy of an instanced mesh, placed relative to the entity holding it.
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct MeshInstance {
+    pub transform: [[f32; 4]; 4],
+    pub color: [f32; 4],
+}
+
+// SAFETY: only floats, with no padding between them
+unsafe impl Zeroable for MeshInstance {}
+unsafe impl Pod for MeshInstance {}
+
+impl MeshInstance {
+    pub fn new(transform: Mat4, color: Color) -> Self {
+        Self {
+            transform: transform.to_cols_array_2d(),
+            color: color.as_linear_rgba_f32(),
+        }
+    }
+}
+
+/// Draws the entity's mesh once for every instance in a single draw call. The mesh needs positions,
+/// normals and vertex colors, and the entity should have `NoFrustumCulling` since its bounds only
+/// cover a single instance.
+#[derive(Component, Clone, Deref)]
+pub struct MeshInstances(pub Vec<MeshInstance>);
+
+/// Marks an entity with `MeshInstances` in the render world, without copying the instances over.
+#[derive(Component)]
+pub struct ExtractedInstancedMesh;
+
+/// Instances that changed since the last frame, waiting to be uploaded.
+#[derive(Resource, Default)]
+pub struct ChangedMeshInstances(Vec<(Entity, Vec<MeshInstance>)>);
+
+/// Each instanced entity's buffer, kept from frame to frame and only rewritten when its instances change.
+#[derive(Resource, Default)]
+pub struct InstanceBuffers(HashMap<Entity, InstanceBuffer>);
+
+
+pub struct InstancedMeshPlugin;
+
+impl Plugin for InstancedMeshPlugin {
+    fn build(&self, app: &mut App) {
+        let Ok(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+
+        render_app
+            .add_render_command::<Opaque3d, DrawMeshInstanced>()
+            .init_resource::<SpecializedMeshPipelines<InstancedMeshPipeline>>()
+            .init_resource::<ChangedMeshInstances>()
+            .init_resource::<InstanceBuffers>()
+            .add_systems(ExtractSchedule, extract_mesh_instances)
+            .add_systems(Render, (
+                queue_instanced_meshes.in_set(RenderSet::Queue),
+                prepare_instance_buffers.in_set(RenderSet::Prepare),
+            ));
+    }
+
+    fn finish(&self, app: &mut App) {
+        let Ok(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+
+        render_app
+            .init_resource::<InstancedMeshPipeline>();
+    }
+}
+
+
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn queue_instanced_meshes(
+    opaque_3d_draw_functions: Res<DrawFunctions<Opaque3d>>,
+    instanced_pipeline: Res<InstancedMeshPipeline>,
+    msaa: Res<Msaa>,
+    mut pipelines: ResMut<SpecializedMeshPipelines<InstancedMeshPipeline>>,
+    pipeline_cache: Res<PipelineCache>,
+    meshes: Res<RenderAssets<Mesh>>,
+    instanced_meshes: Query<(Entity, &MeshUniform, &Handle<Mesh>), With<ExtractedInstancedMesh>>,
+    mut views: Query<(&ExtractedView, Option<&Tonemapping>, Option<&DebandDither>, &mut RenderPhase<Opaque3d>)>,
+) {
+    let draw_instanced = opaque_3d_draw_functions.read().id::<DrawMeshInstanced>();
+    let msaa_key = MeshPipelineKey::from_msaa_samples(msaa.samples());
+
+    for (view, tonemapping, dither, mut opaque_phase) in views.iter_mut() {
+        let mut view_key = msaa_key | MeshPipelineKey::from_hdr(view.hdr);
+        // tonemapped in the shader like the standard material, so they match the terrain around them
+        if !view.hdr {
+            if let Some(tonemapping) = tonemapping {
+                view_key |= MeshPipelineKey::TONEMAP_IN_SHADER | tonemapping_key(*tonemapping);
+            }
+            if let Some(DebandDither::Enabled) = dither {
+                view_key |= MeshPipelineKey::DEBAND_DITHER;
+            }
+        }
+
+        let rangefinder = view.rangefinder3d();
+        for (entity, mesh_uniform, mesh_handle) in instanced_meshes.iter() {
+            let Some(mesh) = meshes.get(mesh_handle) else { continue };
+            let key = view_key | MeshPipelineKey::from_primitive_topology(mesh.primitive_topology);
+            let pipeline = match pipelines.specialize(&pipeline_cache, &instanced_pipeline, key, &mesh.layout) {
+                Ok(pipeline) => pipeline,
+                Err(err) => {
+                    error!("{}", err);
+                    continue;
+                }
+            };
+            opaque_phase.add(Opaque3d {
+                entity,
+                pipeline,
+                draw_function: draw_instanced,
+                distance: rangefinder.distance(&mesh_uniform.transform),
+            });
+        }
+    }
+}
+
+fn tonemapping_key(tonemapping: Tonemapping) -> MeshPipelineKey {
+    match tonemapping {
+        Tonemapping::None => MeshPipelineKey::TONEMAP_METHOD_NONE,
+        Tonemapping::Reinhard => MeshPipelineKey::TONEMAP_METHOD_REINHARD,
+        Tonemapping::ReinhardLuminance => MeshPipelineKey::TONEMAP_METHOD_REINHARD_LUMINANCE,
+        Tonemapping::AcesFitted => MeshPipelineKey::TONEMAP_METHOD_ACES_FITTED,
+        Tonemapping::AgX => MeshPipelineKey::TONEMAP_METHOD_AGX,
+        Tonemapping::SomewhatBoringDisplayTransform => MeshPipelineKey::TONEMAP_METHOD_SOMEWHAT_BORING_DISPLAY_TRANSFORM,
+        Tonemapping::TonyMcMapface => MeshPipelineKey::TONEMAP_METHOD_TONY_MC_MAPFACE,
+        Tonemapping::BlenderFilmic => MeshPipelineKey::TONEMAP_METHOD_BLENDER_FILMIC,
+    }
+}
+
+pub struct InstanceBuffer {
+    buffer: Buffer,
+    length: usize,
+}
+
+fn extract_mesh_instances(
+    mut commands: Commands,
+    mut changed: ResMut<ChangedMeshInstances>,
+    instanced_meshes: Extract<Query<(Entity, Ref<MeshInstances>)>>,
+) {
+    let mut markers = Vec::new();
+    for (entity, instances) in instanced_meshes.iter() {
+        markers.push((entity, ExtractedInstancedMesh));
+        if instances.is_changed() {
+            changed.0.push((entity, instances.0.clone()));
+        }
+    }
+    commands.insert_or_spawn_batch(markers);
+}
+
+fn prepare_instance_buffers(
+    mut buffers: ResMut<InstanceBuffers>,
+    mut changed: ResMut<ChangedMeshInstances>,
+    instanced_meshes: Query<Entity, With<ExtractedInstancedMesh>>,
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+) {
+    buffers.0.retain(|entity, _| instanced_meshes.contains(*entity));
+
+    for (entity, instances) in changed.0.drain(..) {
+        let contents: &[u8] = bytemuck::cast_slice(instances.as_slice());
+        match buffers.0.get_mut(&entity) {
+            // write over the old instances while the new ones still fit
+            Some(instance_buffer) if instance_buffer.buffer.size() >= contents.len() as u64 => {
+                render_queue.write_buffer(&instance_buffer.buffer, 0, contents);
+                instance_buffer.length = instances.len();
+            }
+            _ => {
+                let buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
+                    label: Some("mesh instance buffer"),
+                    contents,
+                    usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+                });
+                buffers.0.insert(entity, InstanceBuffer {
+                    buffer,
+                    length: instances.len(),
+                });
+            }
+        }
+    }
+}
+
+
+#[derive(Resource)]
+pub struct InstancedMeshPipeline {
+    shader: Handle<Shader>,
+    mesh_pipeline: MeshPipeline,
+}
+
+impl FromWorld for InstancedMeshPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let shader = world.resource::<AssetServer>().load("shaders/scatter.wgsl");
+        let mesh_pipeline = world.resource::<MeshPipeline>().clone();
+
+        Self {
+            shader,
+            mesh_pipeline,
+        }
+    }
+}
+
+impl SpecializedMeshPipeline for InstancedMeshPipeline {
+    type Key = MeshPipelineKey;
+
+    fn specialize(
+        &self,
+        key: Self::Key,
+        layout: &MeshVertexBufferLayout,
+    ) -> Result<RenderPipelineDescriptor, SpecializedMeshPipelineError> {
+        let mut descriptor = self.mesh_pipeline.specialize(key, layout)?;
+
+        // the mesh bind group is bound at 1 rather than after a material's
+        descriptor.vertex.shader_defs.push("MESH_BINDGROUP_1".into());
+        descriptor.vertex.shader = self.shader.clone();
+
+        // the instance transform's columns, then its color, after every location a mesh attribute can take
+        let vec4_size = VertexFormat::Float32x4.size();
+        descriptor.vertex.buffers.push(VertexBufferLayout {
+            array_stride: std::mem::size_of::<MeshInstance>() as u64,
+            step_mode: VertexStepMode::Instance,
+            attributes: (0..5).map(|i| VertexAttribute {
+                format: VertexFormat::Float32x4,
+                offset: vec4_size * i as u64,
+                shader_location: 8 + i,
+            }).collect(),
+        });
+
+        let fragment = descriptor.fragment.as_mut().unwrap();
+        fragment.shader = self.shader.clone();
+        fragment.shader_defs.push("MESH_BINDGROUP_1".into());
+        Ok(descriptor)
+    }
+}
+
+type DrawMeshInstanced = (
+    SetItemPipeline,
+    SetMeshViewBindGroup<0>,
+    SetMeshBindGroup<1>,
+    DrawInstances,
+);
+
+pub struct DrawInstances;
+
+impl<P: PhaseItem> RenderCommand<P> for DrawInstances {
+    type Param = (SRes<RenderAssets<Mesh>>, SRes<InstanceBuffers>);
+    type ViewWorldQuery = ();
+    type ItemWorldQuery = Read<Handle<Mesh>>;
+
+    #[inline]
+    fn render<'w>(
+        item: &P,
+        _view: (),
+        mesh_handle: &'w Handle<Mesh>,
+        (meshes, instance_buffers): SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        let Some(gpu_mesh) = meshes.into_inner().get(mesh_handle) else {
+            return RenderCommandResult::Failure;
+        };
+        let Some(instance_buffer) = instance_buffers.into_inner().0.get(&item.entity()) else {
+            return RenderCommandResult::Failure;
+        };
+
+        pass.set_vertex_buffer(0, gpu_mesh.vertex_buffer.slice(..));
+        pass.set_vertex_buffer(1, instance_buffer.buffer.slice(..));
+
+        match &gpu_mesh.buffer_info {
+            GpuBufferInfo::Indexed { buffer, index_format, count } => {
+                pass.set_index_buffer(buffer.slice(..), 0, *index_format);
+                pass.draw_indexed(0..*count, 0, 0..instance_buffer.length as u32);
+            }
+            GpuBufferInfo::NonIndexed => {
+                pass.draw(0..gpu_mesh.vertex_count, 0..instance_buffer.length as u32);
+            }
+        }
+        RenderCommandResult::Success
+    }
+}
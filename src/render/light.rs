@@ -1,29 +1,112 @@
-use bevy::prelude::*;
+use bevy::{prelude::*, pbr::MAX_DIRECTIONAL_LIGHTS};
 
-use crate::ui::render::UiRenderSettings;
+use crate::gen::shape::ShapeGenerator;
 
+use super::{orbit::{PlanetOrbit, SimClock}, planet::{Planet, nearest_planet}};
+
+
+/// Illuminance of the sun at a distance of one semi-major axis.
+const SUN_ILLUMINANCE: f32 = 10000.0;
+
+/// Marks the directional light standing in for the sun. Any other directional lights are left
+/// where they were put.
+#[derive(Component)]
+pub struct Sun;
+
+/// The sunlight arriving at one planet, which its shaders use in place of the `Sun` light. The
+/// planet the `Sun` follows is left to light itself with it as it is, shadows and all.
+#[derive(Component, Clone, Copy, Debug)]
+pub struct PlanetSunlight {
+    /// Where the `Sun` light sits among the directional lights handed to shaders, or `u32::MAX`
+    /// when there's nothing to swap.
+    pub index: u32,
+    /// Towards the sun, in world space.
+    pub direction: Vec3,
+    /// This planet's illuminance over the `Sun` light's, which scales the light's color.
+    pub scale: f32,
+}
+
+impl Default for PlanetSunlight {
+    fn default() -> Self {
+        Self {
+            index: u32::MAX,
+            direction: Vec3::Z,
+            scale: 1.0,
+        }
+    }
+}
 
 pub fn spawn_directional_light(
     mut commands: Commands,
 ) {
-    commands.spawn(DirectionalLightBundle {
-        directional_light: DirectionalLight {
-            color: Color::rgb(1.0, 1.0, 1.0),
-            illuminance: 10000.0,
+    commands.spawn((
+        DirectionalLightBundle {
+            directional_light: DirectionalLight {
+                color: Color::rgb(1.0, 1.0, 1.0),
+                illuminance: SUN_ILLUMINANCE,
+                ..default()
+            },
+            transform: Transform::from_rotation(Quat::from_euler(EulerRot::YXZ, 0.0, 0.0, 0.0)),
             ..default()
         },
-        transform: Transform::from_rotation(Quat::from_euler(EulerRot::YXZ, 0.0, 0.0, 0.0)),
-        ..default()
-    });
+        Sun,
+    ));
 }
 
+/// The direction towards the sun and the illuminance of sunlight at a planet on `orbit`.
+fn sunlight(orbit: &PlanetOrbit, time: f64) -> Option<(Vec3, f32)> {
+    let sun_to_planet = orbit.position(time);
+    let distance = sun_to_planet.length();
+    if distance <= 0.0 { return None };
+    Some((-sun_to_planet / distance, SUN_ILLUMINANCE * (orbit.semi_major_axis / distance).powi(2)))
+}
+
+/// Points the sunlight along the orbit of the planet closest to the camera, so whoever is looking
+/// at a planet sees its day, night and seasons. Every planet also gets the sunlight along its own
+/// orbit, for its shaders to use instead.
 pub fn update_directional_light(
-    mut directional_light_transforms: Query<&mut Transform, With<DirectionalLight>>,
-    render_settings: Res<UiRenderSettings>,
+    mut suns: Query<(Entity, &mut Transform, &mut DirectionalLight, &ComputedVisibility), With<Sun>>,
+    lights: Query<(Entity, &DirectionalLight, &ComputedVisibility), Without<Sun>>,
+    cameras: Query<(&GlobalTransform, &Camera)>,
+    mut planets: Query<(Entity, &PlanetOrbit, &ShapeGenerator, &GlobalTransform, &mut PlanetSunlight), With<Planet>>,
+    clock: Res<SimClock>,
 ) {
-    if !render_settings.is_changed() { return; }
-    for mut transform in directional_light_transforms.iter_mut() {
-        let rot = render_settings.light_euler_rot * std::f32::consts::PI / 180.0;
-        transform.rotation = Quat::from_euler(EulerRot::YXZ, rot.x, rot.y, rot.z);
+    let Ok((sun_entity, mut sun_transform, mut sun_light, sun_visibility)) = suns.get_single_mut() else { return };
+
+    let nearest = cameras.iter()
+        .find(|(_, camera)| camera.is_active)
+        .and_then(|(camera_transform, _)| nearest_planet(
+            camera_transform.translation(),
+            planets.iter().map(|(entity, orbit, shape_gen, transform, _)| ((entity, orbit), transform.translation(), shape_gen.radius)),
+        ))
+        .and_then(|(entity, orbit)| Some((entity, sunlight(orbit, clock.time)?)));
+
+    if let Some((_, (direction, illuminance))) = nearest {
+        let up = if direction.abs_diff_eq(Vec3::Y, 0.001) || direction.abs_diff_eq(Vec3::NEG_Y, 0.001) { Vec3::Z } else { Vec3::Y };
+        *sun_transform = Transform::IDENTITY.looking_to(-direction, up);
+        sun_light.illuminance = illuminance;
     }
-}
\ No newline at end of file
+
+    // bevy hands visible directional lights to shaders shadow casters first and then by entity
+    let mut visible: Vec<(Entity, bool)> = lights.iter()
+        .filter(|(_, _, visibility)| visibility.is_visible())
+        .map(|(entity, light, _)| (entity, light.shadows_enabled))
+        .chain(sun_visibility.is_visible().then_some((sun_entity, sun_light.shadows_enabled)))
+        .collect();
+    visible.sort_by(|(entity_a, shadows_a), (entity_b, shadows_b)| shadows_b.cmp(shadows_a).then_with(|| entity_a.cmp(entity_b)));
+    let sun_index = visible.iter()
+        .take(MAX_DIRECTIONAL_LIGHTS)
+        .position(|(entity, _)| *entity == sun_entity);
+
+    for (entity, orbit, _, _, mut planet_sunlight) in planets.iter_mut() {
+        let followed = nearest.is_some_and(|(nearest, _)| nearest == entity);
+        *planet_sunlight = match (sun_index, sunlight(orbit, clock.time)) {
+            (Some(index), Some((direction, illuminance))) if !followed => PlanetSunlight {
+                index: index as u32,
+                direction,
+                scale: illuminance / sun_light.illuminance.max(f32::EPSILON),
+            },
+            _ => PlanetSunlight::default(),
+        };
+    }
+}
@@ -0,0 +1,291 @@
+use bevy::{prelude::*, render::primitives::Aabb, tasks::{AsyncComputeTaskPool, Task}, utils::HashMap};
+use futures_lite::future;
+
+use crate::gen::shape::ShapeGenerator;
+
+use super::{planet::{Planet, PlanetTopology, TerrainFace, ChunkMesh, CubeSphereMapping, generate_chunk_mesh}, planet_mat::PlanetMaterial};
+
+
+#[derive(Component)]
+pub struct TerrainChunk {
+    pub face: usize,
+    pub min: Vec2,
+    pub size: f32,
+    pub depth: u32,
+}
+
+#[derive(Component)]
+pub struct ChunkMeshTask(pub Task<ChunkMesh>);
+
+/// A rebuilt mesh held back until every other chunk being rebuilt has finished too.
+#[derive(Component)]
+pub struct PendingChunkMesh(pub Handle<Mesh>);
+
+pub fn spawn_chunk_task(
+    face: TerrainFace,
+    min: Vec2,
+    size: f32,
+    resolution: u32,
+    mapping: CubeSphereMapping,
+    shape_gen: &ShapeGenerator,
+) -> ChunkMeshTask {
+    let shape_gen = shape_gen.clone();
+    let task = AsyncComputeTaskPool::get().spawn(async move {
+        generate_chunk_mesh(face, min, size, resolution, mapping, &shape_gen)
+    });
+    ChunkMeshTask(task)
+}
+
+
+pub struct QuadtreeNode {
+    pub min: Vec2,
+    pub size: f32,
+    pub depth: u32,
+    pub chunk: Option<Entity>,
+    pub children: Option<Box<[QuadtreeNode; 4]>>,
+}
+
+impl QuadtreeNode {
+    pub fn new(min: Vec2, size: f32, depth: u32) -> Self {
+        Self {
+            min,
+            size,
+            depth,
+            chunk: None,
+            children: None,
+        }
+    }
+
+    fn subdivide(&self) -> Box<[QuadtreeNode; 4]> {
+        let half = self.size * 0.5;
+        Box::new([
+            QuadtreeNode::new(self.min, half, self.depth + 1),
+            QuadtreeNode::new(self.min + Vec2::new(half, 0.0), half, self.depth + 1),
+            QuadtreeNode::new(self.min + Vec2::new(0.0, half), half, self.depth + 1),
+            QuadtreeNode::new(self.min + Vec2::new(half, half), half, self.depth + 1),
+        ])
+    }
+
+    /// Whether this node's area is fully drawn, either by its own chunk or by its descendants.
+    fn is_covered(&self, ready_chunks: &Query<(), (With<TerrainChunk>, With<Handle<Mesh>>)>) -> bool {
+        if self.chunk.is_some_and(|chunk| ready_chunks.contains(chunk)) {
+            return true;
+        }
+        match &self.children {
+            Some(children) => children.iter().all(|child| child.is_covered(ready_chunks)),
+            None => false,
+        }
+    }
+
+    fn despawn_descendants(&mut self, commands: &mut Commands) {
+        if let Some(children) = self.children.as_mut() {
+            for child in children.iter_mut() {
+                if let Some(chunk) = child.chunk.take() {
+                    commands.entity(chunk).despawn_recursive();
+                }
+                child.despawn_descendants(commands);
+            }
+        }
+        self.children = None;
+    }
+
+    /// Projected size in pixels of the spacing between this node's vertices.
+    fn screen_error(&self, face: &TerrainFace, lod: &LodContext) -> f32 {
+        let center = face.point_on_unit_sphere(self.min + Vec2::splat(self.size * 0.5), lod.planet.mapping) * lod.radius;
+        // a face spans roughly a quarter of a great circle
+        let extent = self.size * std::f32::consts::FRAC_PI_2 * lod.radius;
+        let spacing = extent / (lod.resolution as f32 - 1.0);
+        let distance = (center.distance(lod.camera_position) - extent * std::f32::consts::FRAC_1_SQRT_2).max(0.0001);
+        spacing / distance * lod.screen_factor
+    }
+}
+
+#[derive(Component)]
+pub struct TerrainQuadtree(pub QuadtreeNode);
+
+impl Default for TerrainQuadtree {
+    fn default() -> Self {
+        Self(QuadtreeNode::new(Vec2::ZERO, 1.0, 0))
+    }
+}
+
+
+struct LodContext<'a> {
+    /// In the planet's local space.
+    camera_position: Vec3,
+    screen_factor: f32,
+    radius: f32,
+    resolution: u32,
+    max_depth: u32,
+    threshold: f32,
+    planet_entity: Entity,
+    planet: &'a Planet,
+    shape_gen: &'a ShapeGenerator,
+}
+
+fn update_node(
+    node: &mut QuadtreeNode,
+    face: &TerrainFace,
+    lod: &LodContext,
+    commands: &mut Commands,
+    ready_chunks: &Query<(), (With<TerrainChunk>, With<Handle<Mesh>>)>,
+) {
+    // merge at a lower error than we split at so nodes don't flicker on the boundary
+    let threshold = if node.children.is_some() { lod.threshold * 0.75 } else { lod.threshold };
+    let wants_split = node.depth < lod.max_depth && node.screen_error(face, lod) > threshold;
+
+    if wants_split {
+        if node.children.is_none() {
+            node.children = Some(node.subdivide());
+        }
+        for child in node.children.as_mut().unwrap().iter_mut() {
+            update_node(child, face, lod, commands, ready_chunks);
+        }
+
+        // keep drawing the coarse chunk until every child has something to draw in its place
+        if node.chunk.is_some() && node.children.as_ref().unwrap().iter().all(|child| child.is_covered(ready_chunks)) {
+            commands.entity(node.chunk.take().unwrap()).despawn_recursive();
+        }
+    } else {
+        if node.chunk.is_none() {
+            let task = spawn_chunk_task(*face, node.min, node.size, lod.resolution, lod.planet.mapping, lod.shape_gen);
+            node.chunk = Some(commands.spawn((
+                TerrainChunk {
+                    face: face.index(),
+                    min: node.min,
+                    size: node.size,
+                    depth: node.depth,
+                },
+                task,
+                SpatialBundle::default(),
+            )).set_parent(lod.planet_entity).id());
+        }
+
+        if node.children.is_some() && node.chunk.is_some_and(|chunk| ready_chunks.contains(chunk)) {
+            node.despawn_descendants(commands);
+        }
+    }
+}
+
+pub fn update_terrain_lod(
+    mut commands: Commands,
+    mut faces: Query<(&TerrainFace, &mut TerrainQuadtree, &Parent)>,
+    ready_chunks: Query<(), (With<TerrainChunk>, With<Handle<Mesh>>)>,
+    cameras: Query<(&GlobalTransform, &Camera, &Projection)>,
+    planets: Query<(&Planet, &ShapeGenerator, &GlobalTransform)>,
+) {
+    let Some((camera_transform, camera, projection)) = cameras.iter().find(|(_, camera, _)| camera.is_active) else { return };
+    let Projection::Perspective(perspective) = projection else { return };
+    let Some(viewport_size) = camera.logical_viewport_size() else { return };
+    let screen_factor = viewport_size.y / (2.0 * (perspective.fov * 0.5).tan());
+
+    for (face, mut quadtree, parent) in faces.iter_mut() {
+        let Ok((planet, shape_gen, planet_transform)) = planets.get(parent.get()) else { continue };
+        if planet.topology != PlanetTopology::CubeSphere { continue };
+
+        let lod = LodContext {
+            camera_position: planet_transform.affine().inverse().transform_point3(camera_transform.translation()),
+            screen_factor,
+            radius: shape_gen.radius,
+            resolution: planet.resolution,
+            max_depth: planet.max_lod_depth,
+            threshold: planet.lod_error_threshold,
+            planet_entity: parent.get(),
+            planet,
+            shape_gen,
+        };
+
+        update_node(&mut quadtree.0, face, &lod, &mut commands, &ready_chunks);
+    }
+}
+
+/// How a planet's chunk tasks got on this frame.
+#[derive(Default)]
+struct ChunkTaskProgress {
+    remaining: usize,
+    finished_rebuilds: usize,
+    bounds_changed: bool,
+}
+
+#[allow(clippy::type_complexity)]
+pub fn poll_chunk_tasks(
+    mut commands: Commands,
+    mut tasks: Query<(Entity, &mut ChunkMeshTask, Option<&Handle<Mesh>>, &Parent)>,
+    pending_meshes: Query<(Entity, &PendingChunkMesh, &Parent)>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<PlanetMaterial>>,
+    mut planets: Query<(Entity, &mut Planet)>,
+) {
+    let mut progress: HashMap<Entity, ChunkTaskProgress> = HashMap::default();
+
+    for (entity, mut task, mesh_handle, parent) in tasks.iter_mut() {
+        let Ok((_, mut planet)) = planets.get_mut(parent.get()) else { continue };
+        let progress = progress.entry(parent.get()).or_default();
+
+        let Some(chunk_mesh) = future::block_on(future::poll_once(&mut task.0)) else {
+            if mesh_handle.is_some() {
+                progress.remaining += 1;
+            }
+            continue;
+        };
+
+        match planet.regeneration.as_mut() {
+            Some(regeneration) => {
+                regeneration.min_elevation = regeneration.min_elevation.min(chunk_mesh.min_elevation);
+                regeneration.max_elevation = regeneration.max_elevation.max(chunk_mesh.max_elevation);
+            }
+            None => {
+                planet.min_elevation = planet.min_elevation.min(chunk_mesh.min_elevation);
+                planet.max_elevation = planet.max_elevation.max(chunk_mesh.max_elevation);
+                progress.bounds_changed = true;
+            }
+        }
+
+        let mut chunk_commands = commands.entity(entity);
+        chunk_commands.remove::<ChunkMeshTask>();
+        match mesh_handle {
+            Some(_) => {
+                chunk_commands.insert(PendingChunkMesh(meshes.add(chunk_mesh.mesh)));
+                progress.finished_rebuilds += 1;
+            }
+            None => {
+                chunk_commands.insert((meshes.add(chunk_mesh.mesh), planet.material.clone()));
+            }
+        }
+    }
+
+    for (planet_entity, mut planet) in planets.iter_mut() {
+        let progress = progress.remove(&planet_entity).unwrap_or_default();
+        let mut bounds_changed = progress.bounds_changed;
+
+        if let Some(regeneration) = planet.regeneration.as_mut() {
+            regeneration.remaining = progress.remaining;
+
+            // swap every rebuilt mesh in on the same frame, so the planet never shows a mix of old and new shapes
+            if progress.remaining == 0 && progress.finished_rebuilds == 0 {
+                for (entity, pending_mesh, _) in pending_meshes.iter().filter(|(_, _, parent)| parent.get() == planet_entity) {
+                    commands.entity(entity)
+                        .insert(pending_mesh.0.clone())
+                        .remove::<PendingChunkMesh>()
+                        // bounds are only computed for entities without one, so clear the stale box
+                        .remove::<Aabb>();
+                }
+
+                // with nothing to rebuild there are no new bounds, so keep the ones the material has
+                let regeneration = planet.regeneration.take().unwrap();
+                if regeneration.total > 0 {
+                    planet.min_elevation = regeneration.min_elevation;
+                    planet.max_elevation = regeneration.max_elevation;
+                    bounds_changed = true;
+                }
+            }
+        }
+
+        // a planet that hasn't finished a chunk yet has no bounds to give its material
+        if bounds_changed && planet.min_elevation <= planet.max_elevation {
+            let mat = materials.get_mut(&planet.material).unwrap();
+            mat.min_elevation = planet.min_elevation;
+            mat.max_elevation = planet.max_elevation;
+        }
+    }
+}
@@ -1,21 +1,36 @@
 pub mod planet;
+pub mod lod;
+pub mod icosphere;
 pub mod light;
+pub mod orbit;
 pub mod planet_mat;
 // pub mod ocean_mat;
 pub mod ocean;
 pub mod utils;
 pub mod atmosphere;
+pub mod atmosphere_lut;
 pub mod post;
+pub mod instancing;
+pub mod scatter;
+pub mod splat;
+pub mod clouds;
 
 use bevy::prelude::*;
 
 use planet::*;
+use lod::*;
+use icosphere::*;
 use light::*;
+use orbit::*;
 use planet_mat::*;
 // use ocean_mat::*;
 use ocean::*;
 use atmosphere::*;
 use post::*;
+use instancing::*;
+use scatter::*;
+use splat::*;
+use clouds::*;
 
 
 pub struct RenderPlugin;
@@ -23,28 +38,46 @@ pub struct RenderPlugin;
 impl Plugin for RenderPlugin {
     fn build(&self, app: &mut App) {
         app
-            .init_resource::<Planet>()
+            .init_resource::<SimClock>()
             .add_event::<UpdatePlanetMesh>()
             .add_event::<UpdatePlanetMaterials>()
             .add_plugins(PostProcessPlugin)
+            .add_plugins(InstancedMeshPlugin)
             .add_plugins(MaterialPlugin::<PlanetMaterial>::default())
             .add_plugins(MaterialPlugin::<OceanMaterial> {
                 prepass_enabled: false,
                 ..default()
             })
             .add_systems(Startup, (
-                spawn_planet,
+                spawn_initial_planet,
                 spawn_directional_light,
-                spawn_ocean,
+                setup_scatter_meshes,
             ))
             .add_systems(Update, (
+                update_planet_topology,
                 generate_mesh,
-                generate_materials,
+                apply_deferred,
+                poll_chunk_tasks,
+                update_terrain_lod,
+            ).chain())
+            .add_systems(Update, (
+                advance_sim_clock,
+                update_planet_rotation,
                 update_directional_light,
+            ).chain())
+            .add_systems(Update, (
+                generate_materials,
+                generate_terrain_layers,
                 update_ocean,
                 update_atmosphere,
+                update_clouds,
                 update_planet_material,
             ))
+            .add_systems(Update, (
+                update_scatter,
+                apply_deferred,
+                poll_scatter_tasks,
+            ).chain())
         ;
     }
 }
\ No newline at end of file
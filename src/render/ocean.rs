@@ -2,6 +2,8 @@ use bevy::{prelude::*, reflect::{TypeUuid, TypePath}, render::render_resource::A
 
 use crate::ui::render::UiRenderSettings;
 
+use super::{planet::Planet, light::PlanetSunlight};
+
 
 
 #[derive(AsBindGroup, Debug, Clone, TypeUuid, TypePath)]
@@ -27,6 +29,15 @@ pub struct OceanMaterial {
     pub wave_speed: f32,
     #[uniform(0)]
     pub wave_scale: f32,
+    #[uniform(0)]
+    pub center: Vec3,
+    /// The sunlight along the planet's own orbit, swapped in for the directional light at `sun_index`.
+    #[uniform(0)]
+    pub sun_direction: Vec3,
+    #[uniform(0)]
+    pub sun_scale: f32,
+    #[uniform(0)]
+    pub sun_index: u32,
 
     #[texture(1)]
     #[sampler(2)]
@@ -60,6 +71,10 @@ impl Default for OceanMaterial {
             time: 0.0,
             wave_speed: 1.0,
             wave_scale: 1.0,
+            center: Vec3::ZERO,
+            sun_direction: Vec3::Z,
+            sun_scale: 1.0,
+            sun_index: u32::MAX,
             wave_normals_1: None,
             wave_normals_2: None,
             selected_normal_map_1: 1,
@@ -69,33 +84,39 @@ impl Default for OceanMaterial {
 }
 
 
+/// Spawns an ocean sphere, to be parented to its planet.
 pub fn spawn_ocean(
-    mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut ocean_materials: ResMut<Assets<OceanMaterial>>,
-) {
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    ocean_materials: &mut Assets<OceanMaterial>,
+) -> Entity {
     commands.spawn(MaterialMeshBundle {
         mesh: meshes.add(Mesh::try_from(shape::Icosphere { radius: 1.0, subdivisions: 6 }).unwrap()),
         material: ocean_materials.add(OceanMaterial::default()),
-        transform: Transform::from_xyz(0.0, 0.0, 0.0),
         ..default()
-    });
+    }).id()
 }
 
 pub fn update_ocean(
+    planets: Query<(&Planet, &UiRenderSettings, &PlanetSunlight, &GlobalTransform)>,
     mut ocean_transforms: Query<(&mut Transform, &Handle<OceanMaterial>)>,
     mut ocean_materials: ResMut<Assets<OceanMaterial>>,
     asset_server: Res<AssetServer>,
-    render_settings: Res<UiRenderSettings>,
     time: Res<Time>,
 ) {
-    for (mut transform, mat_handle) in ocean_transforms.iter_mut() {
+    for (planet, render_settings, sunlight, planet_transform) in planets.iter() {
+        let Ok((mut transform, mat_handle)) = ocean_transforms.get_mut(planet.ocean) else { continue };
+
         transform.scale.x = render_settings.ocean_radius * 1.0;
         transform.scale.y = render_settings.ocean_radius * 1.0;
         transform.scale.z = render_settings.ocean_radius * 1.0;
 
         let mat = ocean_materials.get_mut(mat_handle).unwrap();
         mat.time = time.elapsed_seconds();
+        mat.center = planet_transform.translation();
+        mat.sun_direction = sunlight.direction;
+        mat.sun_scale = sunlight.scale;
+        mat.sun_index = sunlight.index;
         mat.wave_speed = render_settings.wave_speed;
         mat.wave_scale = render_settings.wave_scale;
         mat.wave_strength = render_settings.wave_strength;
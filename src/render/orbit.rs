@@ -0,0 +1,118 @@
+use std::f32::consts::TAU;
+
+use bevy::prelude::*;
+use serde::{Serialize, Deserialize};
+
+
+/// Simulated time in seconds, which drives planet rotation and orbits.
+#[derive(Resource)]
+pub struct SimClock {
+    pub time: f64,
+    pub speed: f32,
+    pub paused: bool,
+}
+
+impl Default for SimClock {
+    fn default() -> Self {
+        Self {
+            time: 0.0,
+            speed: 1.0,
+            paused: false,
+        }
+    }
+}
+
+/// A planet's spin and its Keplerian orbit around the sun. Angles are in degrees and periods in
+/// simulated seconds, with a period of zero standing still.
+///
+/// The sun isn't part of the scene, so planets stay where they're placed and the orbit only decides
+/// which way, and how strongly, sunlight arrives. The orbital plane is the world XZ plane.
+#[derive(Component, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct PlanetOrbit {
+    pub axial_tilt: f32,
+    pub rotation_period: f32,
+
+    pub orbital_period: f32,
+    pub semi_major_axis: f32,
+    pub eccentricity: f32,
+    pub inclination: f32,
+    pub longitude_of_ascending_node: f32,
+    pub argument_of_periapsis: f32,
+    pub mean_anomaly_at_epoch: f32,
+}
+
+impl Default for PlanetOrbit {
+    fn default() -> Self {
+        Self {
+            axial_tilt: 23.44,
+            rotation_period: 120.0,
+
+            orbital_period: 120.0 * 365.0,
+            semi_major_axis: 1.0,
+            eccentricity: 0.0167,
+            inclination: 0.0,
+            longitude_of_ascending_node: 0.0,
+            argument_of_periapsis: 0.0,
+            mean_anomaly_at_epoch: 0.0,
+        }
+    }
+}
+
+impl PlanetOrbit {
+    /// Fraction of the way through a period at `time`, in `[0, 1)`.
+    fn phase(period: f32, time: f64) -> f32 {
+        if period <= 0.0 { 0.0 } else { (time / period as f64).rem_euclid(1.0) as f32 }
+    }
+
+    /// The fraction of the current day that has passed at `time`.
+    pub fn time_of_day(&self, time: f64) -> f32 {
+        Self::phase(self.rotation_period, time)
+    }
+
+    /// The planet's orientation at `time`, spun about its own axis and then tilted.
+    pub fn rotation(&self, time: f64) -> Quat {
+        let spin = Self::phase(self.rotation_period, time) * TAU;
+        Quat::from_rotation_z(-self.axial_tilt.to_radians()) * Quat::from_rotation_y(spin)
+    }
+
+    /// Position relative to the sun at `time`. At the default orientation, periapsis lies along -Z.
+    pub fn position(&self, time: f64) -> Vec3 {
+        let e = self.eccentricity.clamp(0.0, 0.99);
+        let mean_anomaly = self.mean_anomaly_at_epoch.to_radians() + Self::phase(self.orbital_period, time) * TAU;
+
+        // solve kepler's equation M = E - e sin(E) for the eccentric anomaly by newton's method
+        let mut eccentric_anomaly = if e > 0.8 { std::f32::consts::PI } else { mean_anomaly };
+        for _ in 0..8 {
+            let f = eccentric_anomaly - e * eccentric_anomaly.sin() - mean_anomaly;
+            eccentric_anomaly -= f / (1.0 - e * eccentric_anomaly.cos());
+        }
+
+        let x = self.semi_major_axis * (eccentric_anomaly.cos() - e);
+        let y = self.semi_major_axis * (1.0 - e * e).sqrt() * eccentric_anomaly.sin();
+        let in_plane = Vec3::NEG_Z * x + Vec3::NEG_X * y;
+
+        let orientation = Quat::from_rotation_y(self.longitude_of_ascending_node.to_radians())
+            * Quat::from_rotation_x(self.inclination.to_radians())
+            * Quat::from_rotation_y(self.argument_of_periapsis.to_radians());
+        orientation * in_plane
+    }
+}
+
+
+pub fn advance_sim_clock(
+    mut clock: ResMut<SimClock>,
+    time: Res<Time>,
+) {
+    if clock.paused { return };
+    clock.time += time.delta_seconds_f64() * clock.speed as f64;
+}
+
+pub fn update_planet_rotation(
+    mut planets: Query<(&mut Transform, &PlanetOrbit)>,
+    clock: Res<SimClock>,
+) {
+    for (mut transform, orbit) in planets.iter_mut() {
+        transform.rotation = orbit.rotation(clock.time);
+    }
+}
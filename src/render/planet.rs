@@ -1,30 +1,164 @@
-use bevy::{prelude::*, render::{mesh::Indices, render_resource::PrimitiveTopology}};
+use bevy::{prelude::*, render::{mesh::Indices, render_resource::PrimitiveTopology}, utils::HashSet};
+use serde::{Serialize, Deserialize};
 
-use crate::{ui::color::UiColorSettings, gen::shape::ShapeGenerator};
+use crate::{ui::{color::UiColorSettings, render::UiRenderSettings}, gen::shape::ShapeGenerator};
 
-use super::planet_mat::{PlanetMaterial, ColorEntry};
+use super::{planet_mat::PlanetMaterial, lod::{TerrainQuadtree, TerrainChunk, PendingChunkMesh, spawn_chunk_task}, icosphere::{IcospherePatch, spawn_patch_task}, ocean::{OceanMaterial, spawn_ocean}, orbit::PlanetOrbit, scatter::PlanetScatter, splat::PlanetTerrainLayers, clouds::{PlanetClouds, CloudSettings}, atmosphere::AtmosphereSettings, light::PlanetSunlight};
 
 
-#[derive(Resource)]
+/// The root of a planet. Its terrain faces, chunks and ocean are spawned as children, so they all
+/// follow its transform.
+#[derive(Component)]
 pub struct Planet {
     pub resolution: u32,
-    pub position: Vec3,
-    terrain_faces: [Entity; 6],
+    pub max_lod_depth: u32,
+    pub lod_error_threshold: f32,
+    pub mapping: CubeSphereMapping,
+    pub topology: PlanetTopology,
+    pub min_elevation: f32,
+    pub max_elevation: f32,
+    pub material: Handle<PlanetMaterial>,
+    pub regeneration: Option<MeshRegeneration>,
+    pub ocean: Entity,
+    pub(super) terrain_faces: [Entity; 6],
+    /// The topology the terrain entities currently match, see `update_planet_topology`.
+    pub(super) built_topology: PlanetTopology,
+}
+
+/// Tracks the chunk meshes being rebuilt in the background after the shape changes.
+pub struct MeshRegeneration {
+    pub total: usize,
+    pub remaining: usize,
+    pub min_elevation: f32,
+    pub max_elevation: f32,
+}
+
+impl MeshRegeneration {
+    pub fn progress(&self) -> f32 {
+        if self.total == 0 { 1.0 } else { 1.0 - self.remaining as f32 / self.total as f32 }
+    }
 }
 
 impl Default for Planet {
     fn default() -> Self {
         Self {
             resolution: 10,
-            position: Vec3::ZERO,
+            max_lod_depth: 8,
+            lod_error_threshold: 8.0,
+            mapping: CubeSphereMapping::default(),
+            topology: PlanetTopology::default(),
+            min_elevation: f32::MAX,
+            max_elevation: f32::MIN,
+            material: Handle::default(),
+            regeneration: None,
+            ocean: Entity::PLACEHOLDER,
             terrain_faces: [Entity::PLACEHOLDER; 6],
+            built_topology: PlanetTopology::default(),
         }
     }
 }
 
-#[derive(Component)]
+impl Planet {
+    pub fn apply_settings(&mut self, settings: &UiRenderSettings) {
+        self.resolution = settings.planet_resolution;
+        self.max_lod_depth = settings.planet_max_lod_depth;
+        self.lod_error_threshold = settings.planet_lod_error;
+        self.mapping = settings.planet_mapping;
+        self.topology = settings.planet_topology;
+    }
+}
+
+#[derive(Bundle)]
+pub struct PlanetBundle {
+    pub name: Name,
+    pub planet: Planet,
+    pub shape_gen: ShapeGenerator,
+    pub colors: UiColorSettings,
+    pub settings: UiRenderSettings,
+    pub orbit: PlanetOrbit,
+    pub scatter: PlanetScatter,
+    pub terrain_layers: PlanetTerrainLayers,
+    pub clouds: PlanetClouds,
+    pub atmosphere: AtmosphereSettings,
+    pub cloud_settings: CloudSettings,
+    pub sunlight: PlanetSunlight,
+    pub spatial: SpatialBundle,
+}
+
+impl PlanetBundle {
+    pub fn new(name: impl Into<String>, translation: Vec3) -> Self {
+        Self {
+            name: Name::new(name.into()),
+            planet: Planet::default(),
+            shape_gen: ShapeGenerator::default(),
+            colors: UiColorSettings::default(),
+            settings: UiRenderSettings::default(),
+            orbit: PlanetOrbit::default(),
+            scatter: PlanetScatter::default(),
+            terrain_layers: PlanetTerrainLayers::default(),
+            clouds: PlanetClouds::default(),
+            atmosphere: AtmosphereSettings::default(),
+            cloud_settings: CloudSettings::default(),
+            sunlight: PlanetSunlight::default(),
+            spatial: SpatialBundle::from_transform(Transform::from_translation(translation)),
+        }
+    }
+}
+
+/// The planet whose surface is closest to `position`, given each planet's centre and radius.
+pub fn nearest_planet<T>(position: Vec3, planets: impl IntoIterator<Item = (T, Vec3, f32)>) -> Option<T> {
+    planets.into_iter()
+        .map(|(planet, center, radius)| (planet, position.distance(center) - radius))
+        .min_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map(|(planet, _)| planet)
+}
+
+/// The base mesh the planet surface is built from.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum PlanetTopology {
+    /// Six cube faces, each split into a quadtree of chunks by level of detail.
+    #[default]
+    CubeSphere,
+    /// A subdivided icosahedron with near-uniform triangles, at a single level of detail.
+    Icosphere,
+}
+
+/// How points on the cube faces are projected onto the sphere.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum CubeSphereMapping {
+    /// Plain normalization, which crowds vertices towards the cube corners.
+    #[default]
+    Normalized,
+    /// The analytic spherified cube mapping, which spreads vertices much more evenly.
+    Spherified,
+    /// Warps the face coordinates by `tan` before normalizing, giving close to equal-area cells.
+    TangentAdjusted,
+}
+
+impl CubeSphereMapping {
+    /// Maps a point on the surface of the `[-1, 1]` cube onto the unit sphere.
+    pub fn cube_to_sphere(&self, point_on_cube: Vec3) -> Vec3 {
+        match self {
+            Self::Normalized => point_on_cube.normalize(),
+            Self::Spherified => {
+                let p2 = point_on_cube * point_on_cube;
+                point_on_cube * Vec3::new(
+                    (1.0 - p2.y / 2.0 - p2.z / 2.0 + p2.y * p2.z / 3.0).sqrt(),
+                    (1.0 - p2.z / 2.0 - p2.x / 2.0 + p2.z * p2.x / 3.0).sqrt(),
+                    (1.0 - p2.x / 2.0 - p2.y / 2.0 + p2.x * p2.y / 3.0).sqrt(),
+                )
+            }
+            Self::TangentAdjusted => {
+                // the face's own axis sits at +-1, which tan(x * PI / 4) leaves untouched
+                let warp = |x: f32| (x * std::f32::consts::FRAC_PI_4).tan();
+                Vec3::new(warp(point_on_cube.x), warp(point_on_cube.y), warp(point_on_cube.z)).normalize()
+            }
+        }
+    }
+}
+
+#[derive(Component, Clone, Copy)]
 pub struct TerrainFace {
-    #[allow(dead_code)]
     index: usize,
     local_up: Vec3,
     axis_a: Vec3,
@@ -32,6 +166,13 @@ pub struct TerrainFace {
 }
 
 impl TerrainFace {
+    pub const DIRECTIONS: [Vec3; 6] = [Vec3::Y, Vec3::NEG_Y, Vec3::X, Vec3::NEG_X, Vec3::Z, Vec3::NEG_Z];
+
+    /// The six faces of the cube, in the same order as the planet spawns them.
+    pub fn all() -> [TerrainFace; 6] {
+        std::array::from_fn(|i| TerrainFace::new(i, Self::DIRECTIONS[i]))
+    }
+
     pub fn new(index: usize, local_up: Vec3) -> Self {
         let axis_a = Vec3::new(local_up.y, local_up.z, local_up.x);
         let axis_b = local_up.cross(axis_a);
@@ -43,146 +184,257 @@ impl TerrainFace {
             axis_b,
         }
     }
+
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub fn point_on_cube(&self, uv: Vec2) -> Vec3 {
+        self.local_up + (uv.x - 0.5) * 2.0 * self.axis_a + (uv.y - 0.5) * 2.0 * self.axis_b
+    }
+
+    pub fn point_on_unit_sphere(&self, uv: Vec2, mapping: CubeSphereMapping) -> Vec3 {
+        mapping.cube_to_sphere(self.point_on_cube(uv))
+    }
 }
 
 #[derive(Event)]
-pub struct UpdatePlanetMesh {}
+pub struct UpdatePlanetMesh {
+    pub planet: Entity,
+}
 
 #[derive(Event)]
-pub struct UpdatePlanetMaterials {}
+pub struct UpdatePlanetMaterials {
+    pub planet: Entity,
+}
 
 
+/// Spawns the planet along with its terrain faces and ocean. Chunks are added by the lod system.
 pub fn spawn_planet(
+    commands: &mut Commands,
+    materials: &mut Assets<PlanetMaterial>,
+    meshes: &mut Assets<Mesh>,
+    ocean_materials: &mut Assets<OceanMaterial>,
+    mut bundle: PlanetBundle,
+) -> Entity {
+    let planet = commands.spawn_empty().id();
+
+    bundle.planet.terrain_faces = TerrainFace::all().map(|face| {
+        commands.spawn((face, TerrainQuadtree::default())).set_parent(planet).id()
+    });
+    bundle.planet.ocean = spawn_ocean(commands, meshes, ocean_materials);
+    commands.entity(bundle.planet.ocean).set_parent(planet);
+
+    bundle.planet.material = materials.add(PlanetMaterial::default());
+    bundle.planet.apply_settings(&bundle.settings);
+
+    commands.entity(planet).insert(bundle);
+    planet
+}
+
+pub fn spawn_initial_planet(
     mut commands: Commands,
-    mut planet: ResMut<Planet>,
-    mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<PlanetMaterial>>,
-    mut update_planet_mesh_evw: EventWriter<UpdatePlanetMesh>
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut ocean_materials: ResMut<Assets<OceanMaterial>>,
 ) {
-    let directions = [Vec3::Y, Vec3::NEG_Y, Vec3::X, Vec3::NEG_X, Vec3::Z, Vec3::NEG_Z];
-    for i in 0..6 {
-        let mesh = meshes.add(Mesh::new(PrimitiveTopology::TriangleList));
-        let mat = PlanetMaterial::default();
-        planet.terrain_faces[i] = commands.spawn((MaterialMeshBundle {
-            mesh: mesh.clone(),
-            material: materials.add(mat),
-            transform: Transform::from_xyz(0.0, 0.0, 0.0),
-            ..default()
-        }, TerrainFace::new(
-            i, 
-            directions[i]
-        ))).id();
+    spawn_planet(&mut commands, &mut materials, &mut meshes, &mut ocean_materials, PlanetBundle::new("Planet 1", Vec3::ZERO));
+}
+
+
+pub struct ChunkMesh {
+    pub mesh: Mesh,
+    pub min_elevation: f32,
+    pub max_elevation: f32,
+}
+
+/// Builds the mesh for the square `min..min + size` of a face's uv space, with a skirt
+/// hanging off each edge to hide cracks against neighbours at a different level of detail.
+pub fn generate_chunk_mesh(
+    face: TerrainFace,
+    min: Vec2,
+    size: f32,
+    resolution: u32,
+    mapping: CubeSphereMapping,
+    shape_gen: &ShapeGenerator,
+) -> ChunkMesh {
+    let mut min_elevation = f32::MAX;
+    let mut max_elevation = f32::MIN;
+
+    let num_vertices = (resolution * resolution) as usize;
+    let num_triangles = ((resolution - 1) * (resolution - 1) * 2) as usize;
+    let mut positions = vec![Vec3::ZERO; num_vertices];
+    let mut uvs = vec![Vec2::ZERO; num_vertices];
+    let mut normals = vec![Vec3::ZERO; num_vertices];
+    let mut indices = vec![0u32; num_triangles * 3];
+    let mut tri_index = 0;
+
+    for y in 0u32..resolution {
+        for x in 0u32..resolution {
+            let i = y * resolution + x;
+            let uv = min + Vec2::new(x as f32, y as f32) / (resolution as f32 - 1.0) * size;
+            let point_on_sphere = face.point_on_unit_sphere(uv, mapping);
+
+            let (position, elevation) = shape_gen.get_point_and_elevation(point_on_sphere);
+
+            if elevation > max_elevation {
+                max_elevation = elevation;
+            }
+            if elevation < min_elevation {
+                min_elevation = elevation;
+            }
+
+            positions[i as usize] = position;
+            uvs[i as usize] = uv;
+
+            if x != resolution - 1 && y != resolution - 1 {
+                indices[tri_index] = i;
+                indices[tri_index + 1] = i + resolution + 1;
+                indices[tri_index + 2] = i + resolution;
+
+                indices[tri_index + 3] = i;
+                indices[tri_index + 4] = i + 1;
+                indices[tri_index + 5] = i + resolution + 1;
+
+                tri_index += 6;
+            }
+        }
+    }
+
+    for i in 0..num_triangles {
+        let i0 = indices[i * 3] as usize;
+        let i1 = indices[i * 3 + 1] as usize;
+        let i2 = indices[i * 3 + 2] as usize;
+
+        let p0 = positions[i0];
+        let p1 = positions[i1];
+        let p2 = positions[i2];
+
+        let face_normal = (p1 - p0).cross(p2 - p0);
+        normals[i0] += face_normal;
+        normals[i1] += face_normal;
+        normals[i2] += face_normal;
+    }
+
+    normals = normals.iter().map(|x| x.normalize()).collect();
+
+    // vertices on the chunk border are shared with a neighbouring chunk or face that can't see our
+    // triangles, so take their normals from the surface itself to shade both sides the same
+    let epsilon = size * 2.0 / (resolution as f32 - 1.0);
+    for y in 0u32..resolution {
+        for x in 0u32..resolution {
+            if x == 0 || y == 0 || x == resolution - 1 || y == resolution - 1 {
+                let i = (y * resolution + x) as usize;
+                normals[i] = shape_gen.get_normal(positions[i].normalize(), epsilon);
+            }
+        }
+    }
+
+    // a neighbour one lod level coarser can only leave a gap as deep as the terrain changes over a
+    // couple of our vertices, so the skirt shrinks with the chunk. it needn't reach below the chunk's
+    // own lowest point, and always hangs down a sliver of the radius
+    let vertex_angle = size * std::f32::consts::FRAC_PI_2 / (resolution as f32 - 1.0);
+    let relief = (max_elevation - min_elevation) / max_elevation.max(f32::EPSILON);
+    let skirt_depth = 1.0 - (vertex_angle * 2.0).min(relief).max(0.001);
+    let edges = [
+        (0..resolution).collect::<Vec<u32>>(),
+        (0..resolution).map(|x| (resolution - 1) * resolution + x).collect(),
+        (0..resolution).map(|y| y * resolution).collect(),
+        (0..resolution).map(|y| y * resolution + resolution - 1).collect(),
+    ];
+    for edge in edges.iter() {
+        let skirt_start = positions.len() as u32;
+        for &i in edge.iter() {
+            positions.push(positions[i as usize] * skirt_depth);
+            uvs.push(uvs[i as usize]);
+            normals.push(normals[i as usize]);
+        }
+
+        // skirts are emitted with both windings so they're never culled, whichever side the crack is seen from
+        for j in 0..(resolution - 1) {
+            let (e0, e1) = (edge[j as usize], edge[j as usize + 1]);
+            let (s0, s1) = (skirt_start + j, skirt_start + j + 1);
+            indices.extend_from_slice(&[e0, e1, s1, e0, s1, s0]);
+            indices.extend_from_slice(&[e0, s1, e1, e0, s0, s1]);
+        }
+    }
+
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+    mesh.set_indices(Some(Indices::U32(indices)));
+
+    ChunkMesh {
+        mesh,
+        min_elevation,
+        max_elevation,
     }
-    update_planet_mesh_evw.send(UpdatePlanetMesh {});
 }
 
 
 pub fn generate_mesh(
-    terrain_faces: Query<(&TerrainFace, &Handle<Mesh>)>,
-    face_materials: Query<&Handle<PlanetMaterial>, With<TerrainFace>>,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<PlanetMaterial>>,
-    planet: Res<Planet>,
-    shape_gen: Res<ShapeGenerator>,
+    mut commands: Commands,
+    chunks: Query<(Entity, &TerrainChunk, &Parent)>,
+    patches: Query<(Entity, &IcospherePatch, &Parent)>,
+    faces: Query<&TerrainFace>,
+    mut planets: Query<(&mut Planet, &ShapeGenerator)>,
     mut update_planet_mesh_evr: EventReader<UpdatePlanetMesh>,
 ) {
-    for _update_planet_mesh_ev in update_planet_mesh_evr.iter() {
-        let mut min_elevation = f32::MAX;
-        let mut max_elevation = f32::MIN;
-
-        for (face, mesh_handle) in terrain_faces.iter() {
-            let mesh = meshes.get_mut(&mesh_handle).unwrap();
-            mesh.remove_attribute(Mesh::ATTRIBUTE_POSITION);
-            mesh.remove_attribute(Mesh::ATTRIBUTE_NORMAL);
-            mesh.remove_attribute(Mesh::ATTRIBUTE_UV_0);
-            mesh.set_indices(None);
-
-            let num_triangles = ((planet.resolution - 1) * (planet.resolution - 1) * 2) as usize;
-            let mut positions = vec![Vec3::ZERO; (planet.resolution * planet.resolution) as usize];
-            let mut uvs = vec![Vec2::ZERO; (planet.resolution * planet.resolution) as usize];
-            let mut normals = vec![Vec3::ZERO; (planet.resolution * planet.resolution) as usize];
-            let mut indices = vec![0u32; num_triangles * 3];
-            let mut tri_index = 0;
-
-            for y in 0u32..planet.resolution {
-                for x in 0u32..planet.resolution {
-                    let i = y * planet.resolution + x;
-                    let uv = Vec2::new(x as f32, y as f32) / (planet.resolution as f32 - 1.0);
-
-                    let point_on_cube = face.local_up + (uv.x - 0.5) * 2.0 * face.axis_a + (uv.y - 0.5) * 2.0 * face.axis_b;
-                    let point_on_sphere = point_on_cube.normalize();
-
-                    let (position, elevation) = shape_gen.get_point_and_elevation(point_on_sphere);
-
-                    if elevation > max_elevation {
-                        max_elevation = elevation;
-                    }
-                    if elevation < min_elevation {
-                        min_elevation = elevation;
-                    }
-
-                    positions[i as usize] = position;
-                    uvs[i as usize] = uv;
-    
-                    if x != planet.resolution - 1 && y != planet.resolution - 1 {
-                        indices[tri_index] = i;
-                        indices[tri_index + 1] = i + planet.resolution + 1;
-                        indices[tri_index + 2] = i + planet.resolution;
-    
-                        indices[tri_index + 3] = i;
-                        indices[tri_index + 4] = i + 1;
-                        indices[tri_index + 5] = i + planet.resolution + 1;
-    
-                        tri_index += 6;
-                    }
-                }
-            }
+    let updated_planets: HashSet<Entity> = update_planet_mesh_evr.iter().map(|ev| ev.planet).collect();
 
-            for i in 0..num_triangles {
-                let i0 = indices[i * 3 + 0] as usize;
-                let i1 = indices[i * 3 + 1] as usize;
-                let i2 = indices[i * 3 + 2] as usize;
+    for planet_entity in updated_planets {
+        let Ok((mut planet, shape_gen)) = planets.get_mut(planet_entity) else { continue };
 
-                let p0 = positions[i0];
-                let p1 = positions[i1];
-                let p2 = positions[i2];
+        let planet_chunks: Vec<_> = chunks.iter().filter(|(_, _, parent)| parent.get() == planet_entity).collect();
+        let planet_patches: Vec<_> = patches.iter().filter(|(_, _, parent)| parent.get() == planet_entity).collect();
 
-                let face_normal = (p1 - p0).cross(p2 - p0);
-                normals[i0] += face_normal;
-                normals[i1] += face_normal;
-                normals[i2] += face_normal;
-            }
+        let num_chunks = planet_chunks.len() + planet_patches.len();
+        planet.regeneration = Some(MeshRegeneration {
+            total: num_chunks,
+            remaining: num_chunks,
+            min_elevation: f32::MAX,
+            max_elevation: f32::MIN,
+        });
 
-            normals = normals.iter().map(|x| x.normalize()).collect();
-    
-            mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
-            mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
-            mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
-            mesh.set_indices(Some(Indices::U32(indices)));
+        // existing chunks keep their old mesh until every rebuilt one is ready. inserting a new task
+        // drops (and so cancels) any stale one still queued, and any stale result waiting to be swapped in
+        for (entity, chunk, _) in planet_chunks {
+            let face = *faces.get(planet.terrain_faces[chunk.face]).unwrap();
+            let task = spawn_chunk_task(face, chunk.min, chunk.size, planet.resolution, planet.mapping, shape_gen);
+            commands.entity(entity)
+                .insert(task)
+                .remove::<PendingChunkMesh>();
         }
-
-        for mat_handle in face_materials.iter() {
-            let mat = materials.get_mut(mat_handle).unwrap();
-            mat.min_elevation = min_elevation;
-            mat.max_elevation = max_elevation;
+        for (entity, patch, _) in planet_patches {
+            let task = spawn_patch_task(patch, planet.resolution, shape_gen);
+            commands.entity(entity)
+                .insert(task)
+                .remove::<PendingChunkMesh>();
         }
     }
 }
 
+/// Bakes each planet's color gradients into its material's lookups, whenever it's asked to or the
+/// material doesn't have them yet.
 pub fn generate_materials(
-    terrain_faces: Query<&Handle<PlanetMaterial>, With<TerrainFace>>,
+    planets: Query<(Entity, &Planet, &UiColorSettings)>,
     mut materials: ResMut<Assets<PlanetMaterial>>,
-    color_settings: Res<UiColorSettings>,
+    mut images: ResMut<Assets<Image>>,
     mut update_planet_mats_evr: EventReader<UpdatePlanetMaterials>,
 ) {
-    for _update_planet_mats_ev in update_planet_mats_evr.iter() {
-        for mat_handle in terrain_faces.iter() {
-            let mat = materials.get_mut(mat_handle).unwrap();
-            mat.n_colors = color_settings.colors.count();
+    let updated: HashSet<Entity> = update_planet_mats_evr.iter().map(|ev| ev.planet).collect();
 
-            for (i, col) in color_settings.colors.sorted().iter().enumerate() {
-                mat.colors[i] = ColorEntry::new(col.0, col.1, col.2);
-            }
-        }
+    for (planet_entity, planet, color_settings) in planets.iter() {
+        let Some(mat) = materials.get(&planet.material) else { continue };
+        if mat.color_lut.is_some() && !updated.contains(&planet_entity) { continue };
+
+        let color_lut = images.add(color_settings.colors.bake().to_image());
+        let underwater_lut = color_settings.separate_underwater.then(|| images.add(color_settings.underwater.bake().to_image()));
+
+        let mat = materials.get_mut(&planet.material).unwrap();
+        mat.color_lut = Some(color_lut);
+        mat.underwater_lut = underwater_lut;
+        mat.separate_underwater = color_settings.separate_underwater as u32;
     }
 }
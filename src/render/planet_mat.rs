@@ -1,36 +1,22 @@
-use bevy::{prelude::*, reflect::{TypeUuid, TypePath}, render::render_resource::{AsBindGroup, ShaderType}};
-use serde::{Serialize, Deserialize};
+use std::f32::consts::FRAC_PI_2;
 
-use crate::ui::render::UiRenderSettings;
+use bevy::{prelude::*, reflect::{TypeUuid, TypePath}, render::{render_resource::{AsBindGroup, ShaderType, Extent3d, TextureDimension, TextureFormat, SamplerDescriptor, FilterMode}, texture::ImageSampler}};
+use serde::{Serialize, Deserialize};
 
-#[derive(Debug, Clone, Copy, ShaderType)]
-pub struct ColorEntry {
-    pub color: [f32; 3],
-    pub elevation: f32,
-    _padding: [f32; 3],
-    pub steepness: f32,
-}
+use crate::{ui::render::UiRenderSettings, gen::shape::{ShapeGenerator, point_to_lat_long}};
 
-impl Default for ColorEntry {
-    fn default() -> Self {
-        Self {
-            color: [0.0; 3],
-            elevation: 0.0,
-            _padding: [0.0; 3],
-            steepness: 0.0,
-        }
-    }
-}
+use super::{planet::Planet, clouds::{PlanetClouds, CloudSettings}, orbit::SimClock, light::PlanetSunlight};
 
-impl ColorEntry {
-    pub fn new(elevation: f32, steepness: f32, color: [f32; 3]) -> Self {
-        Self {
-            elevation,
-            color,
-            steepness,
-            _padding: [0.0; 3],
-        }
-    }
+/// One terrain layer as the shader sees it, with its textures at the same index in each array.
+#[derive(Debug, Clone, Copy, Default, ShaderType)]
+pub struct TerrainLayerEntry {
+    pub tint: [f32; 3],
+    pub roughness: f32,
+    pub elevation: Vec2,
+    pub steepness: Vec2,
+    pub blend: f32,
+    pub triplanar_scale: f32,
+    pub normal_strength: f32,
 }
 
 
@@ -42,19 +28,66 @@ pub struct PlanetMaterial {
     #[uniform(0)]
     pub max_elevation: f32,
     #[uniform(0)]
-    pub n_colors: u32,
-    #[uniform(0)]
     pub surface_strength: f32,
     #[uniform(0)]
     pub surface_scale: f32,
-    
-    #[storage(1, read_only)]
-    pub colors: [ColorEntry; ColorGradient::RESOLUTION as usize],
+    #[uniform(0)]
+    pub n_layers: u32,
+    #[uniform(0)]
+    pub sea_level: f32,
+    /// Whether the seabed is colored from `underwater_lut` by depth, rather than sharing `color_lut` with the land.
+    #[uniform(0)]
+    pub separate_underwater: u32,
+    /// Roughness wherever no terrain layer is painted over the gradient.
+    #[uniform(0)]
+    pub roughness: f32,
+    /// The planet's position, which the shader measures elevation and steepness from.
+    #[uniform(0)]
+    pub center: Vec3,
+    /// The planet's north pole, which the color lookup's latitude is measured from.
+    #[uniform(0)]
+    pub pole: Vec3,
+    /// How much of the sunlight the atmosphere scatters back down onto the ground, per channel.
+    #[uniform(0)]
+    pub sky_color: Vec3,
+    /// The planet's rotation, which the shader takes back out so textures stay fixed to the ground.
+    #[uniform(0)]
+    pub rotation: Mat3,
+    /// The sunlight along the planet's own orbit, swapped in for the directional light at `sun_index`.
+    #[uniform(0)]
+    pub sun_direction: Vec3,
+    #[uniform(0)]
+    pub sun_scale: f32,
+    #[uniform(0)]
+    pub sun_index: u32,
+
+    #[texture(1, dimension = "3d")]
+    #[sampler(9)]
+    pub color_lut: Option<Handle<Image>>,
+    #[texture(10, dimension = "3d")]
+    pub underwater_lut: Option<Handle<Image>>,
 
     #[texture(2)]
     #[sampler(3)]
     pub surface_normal_map: Option<Handle<Image>>,
     selected_normal_map: u32,
+
+    #[storage(4, read_only)]
+    pub layers: Vec<TerrainLayerEntry>,
+    #[texture(5, dimension = "2d_array")]
+    #[sampler(6)]
+    pub layer_albedo: Option<Handle<Image>>,
+    #[texture(7, dimension = "2d_array")]
+    pub layer_normals: Option<Handle<Image>>,
+    #[texture(8, dimension = "2d_array")]
+    pub layer_roughness: Option<Handle<Image>>,
+    /// The texture paths and resolution the layer arrays were built from.
+    pub layer_textures: Vec<[String; 3]>,
+    pub layer_texture_resolution: u32,
+
+    /// The planet's own cloud layer, for the shadows it casts on the ground.
+    #[uniform(11)]
+    pub clouds: CloudSettings,
 }
 
 impl Material for PlanetMaterial {
@@ -70,25 +103,53 @@ impl Default for PlanetMaterial {
             max_elevation: 0.0,
             surface_strength: 0.1,
             surface_scale: 1.0,
-            n_colors: 1,
-            colors: [ColorEntry::default(); ColorGradient::RESOLUTION as usize],
+            center: Vec3::ZERO,
+            pole: Vec3::Y,
+            color_lut: None,
+            sea_level: 0.0,
+            separate_underwater: 0,
+            roughness: 0.9,
+            sky_color: Vec3::ZERO,
+            rotation: Mat3::IDENTITY,
+            sun_direction: Vec3::Z,
+            sun_scale: 1.0,
+            sun_index: u32::MAX,
+            underwater_lut: None,
             surface_normal_map: None,
             selected_normal_map: 1,
+            n_layers: 0,
+            layers: vec![TerrainLayerEntry::default()],
+            layer_albedo: None,
+            layer_normals: None,
+            layer_roughness: None,
+            layer_textures: Vec::new(),
+            layer_texture_resolution: 0,
+            clouds: CloudSettings::default(),
         }
     }
 }
 
 pub fn update_planet_material(
-    mut planet_handles: Query<&Handle<PlanetMaterial>>,
+    planets: Query<(&Planet, &UiRenderSettings, &ShapeGenerator, &PlanetClouds, &PlanetSunlight, &GlobalTransform)>,
     mut planet_materials: ResMut<Assets<PlanetMaterial>>,
     asset_server: Res<AssetServer>,
-    render_settings: Res<UiRenderSettings>,
+    clock: Res<SimClock>,
 ) {
-    for mat_handle in planet_handles.iter_mut() {
-        let mat = planet_materials.get_mut(mat_handle).unwrap();
+    for (planet, render_settings, shape_gen, clouds, sunlight, transform) in planets.iter() {
+        let Some(mat) = planet_materials.get_mut(&planet.material) else { continue };
 
+        mat.center = transform.translation();
+        mat.pole = transform.up();
+        mat.rotation = Mat3::from_quat(transform.to_scale_rotation_translation().1);
+        mat.sea_level = shape_gen.sea_level;
         mat.surface_strength = render_settings.surface_strength;
         mat.surface_scale = render_settings.surface_scale;
+        mat.roughness = render_settings.surface_roughness;
+        mat.sky_color = sky_color(render_settings);
+        mat.sun_direction = sunlight.direction;
+        mat.sun_scale = sunlight.scale;
+        mat.sun_index = sunlight.index;
+        mat.clouds = CloudSettings::new(clouds, render_settings, sunlight, transform, clock.time);
 
         if mat.surface_normal_map.is_none() || mat.selected_normal_map != render_settings.surface_normal_map {
             mat.selected_normal_map = render_settings.surface_normal_map;
@@ -97,22 +158,283 @@ pub fn update_planet_material(
     }
 }
 
+/// The fraction of light the atmosphere scatters on its way down through it, as a rough stand in
+/// for the color of the sky seen from the ground.
+fn sky_color(render_settings: &UiRenderSettings) -> Vec3 {
+    let wavelengths = Vec3::from(render_settings.atmosphere_scatter_coeffs).max(Vec3::splat(1.0));
+    let scattering = (400.0 / wavelengths).powf(4.0) * render_settings.atmosphere_scatter_strength;
+    // with an exponential falloff, the optical depth straight up is about the thickness over the falloff
+    let thickness = (render_settings.atmosphere_radius - render_settings.ocean_radius).max(0.0);
+    let optical_depth = thickness / render_settings.atmosphere_density_falloff.max(1.0);
+
+    (1.0 - (-scattering * optical_depth).exp()) * render_settings.sky_ambient_strength
+}
+
+
+/// Texels along the elevation and steepness axes of a baked color lookup.
+pub const COLOR_LUT_RESOLUTION: u32 = 64;
+/// Texels along the latitude axis, when the gradient uses it.
+pub const COLOR_LUT_LATITUDE_RESOLUTION: u32 = 16;
+
+/// How far a direction in planet space is from the equator, from 0 there to 1 at either pole.
+pub fn normalized_latitude(direction: Vec3) -> f32 {
+    point_to_lat_long(direction.normalize()).0.abs() / FRAC_PI_2
+}
+
+/// A gradient baked over normalized elevation × steepness × latitude, with each axis running from
+/// 0 at the first texel to 1 at the last. The planet shader samples this instead of blending every key.
+#[derive(Clone, Default)]
+pub struct ColorLut {
+    pub size: UVec3,
+    pub colors: Vec<[f32; 3]>,
+}
+
+impl ColorLut {
+    /// Where texel `i` of an axis `n` texels long sits along it.
+    pub fn coordinate(i: u32, n: u32) -> f32 {
+        if n > 1 { i as f32 / (n - 1) as f32 } else { 0.0 }
+    }
+
+    /// The elevation × steepness slice at latitude texel `z`, rows running along elevation.
+    pub fn slice(&self, z: u32) -> &[[f32; 3]] {
+        let len = (self.size.x * self.size.y) as usize;
+        &self.colors[z as usize * len..(z as usize + 1) * len]
+    }
+
+    pub fn to_image(&self) -> Image {
+        let data = self.colors.iter()
+            .flat_map(|color| {
+                let [r, g, b] = color.map(|x| (x.clamp(0.0, 1.0) * 255.0).round() as u8);
+                [r, g, b, 255]
+            })
+            .collect();
+
+        let mut image = Image::new(
+            Extent3d {
+                width: self.size.x,
+                height: self.size.y,
+                depth_or_array_layers: self.size.z,
+            },
+            TextureDimension::D3,
+            data,
+            // the colors are written out as they are, so keep them out of srgb
+            TextureFormat::Rgba8Unorm,
+        );
+        image.sampler_descriptor = ImageSampler::Descriptor(SamplerDescriptor {
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            ..default()
+        });
+        image
+    }
+}
+
+
+/// The color space keys are blended in.
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug, Serialize, Deserialize)]
+pub enum ColorInterpolation {
+    /// Straight linear rgb, which tends to go grey between saturated colors.
+    #[default]
+    Rgb,
+    /// Perceptually uniform, keeping lightness and chroma steady between keys.
+    Oklab,
+    /// Around the hue wheel, keeping saturation between keys.
+    Hsv,
+}
+
+impl ColorInterpolation {
+    pub const ALL: [ColorInterpolation; 3] = [ColorInterpolation::Rgb, ColorInterpolation::Oklab, ColorInterpolation::Hsv];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ColorInterpolation::Rgb => "RGB",
+            ColorInterpolation::Oklab => "OKLab",
+            ColorInterpolation::Hsv => "HSV",
+        }
+    }
+
+    /// A linear rgb color in a form where weighted averages blend the way this mode should.
+    fn encode(&self, rgb: Vec3) -> Vec4 {
+        match self {
+            ColorInterpolation::Rgb => rgb.extend(0.0),
+            ColorInterpolation::Oklab => linear_to_oklab(rgb).extend(0.0),
+            ColorInterpolation::Hsv => {
+                // hue as a direction scaled by saturation, so greys don't pull the hue around
+                let [r, g, b, _] = Color::rgb_linear(rgb.x, rgb.y, rgb.z).as_rgba_f32();
+                let [hue, saturation, value] = rgb_to_hsv(Vec3::new(r, g, b)).to_array();
+                let (sin, cos) = hue.to_radians().sin_cos();
+                Vec4::new(cos * saturation, sin * saturation, saturation, value)
+            }
+        }
+    }
+
+    fn decode(&self, encoded: Vec4) -> Vec3 {
+        match self {
+            ColorInterpolation::Rgb => encoded.truncate(),
+            ColorInterpolation::Oklab => oklab_to_linear(encoded.truncate()),
+            ColorInterpolation::Hsv => {
+                let hue = encoded.y.atan2(encoded.x).to_degrees().rem_euclid(360.0);
+                let [r, g, b] = hsv_to_rgb(Vec3::new(hue, encoded.z, encoded.w)).to_array();
+                let [r, g, b, _] = Color::rgb(r, g, b).as_linear_rgba_f32();
+                Vec3::new(r, g, b)
+            }
+        }
+    }
+}
+
+fn linear_to_oklab(rgb: Vec3) -> Vec3 {
+    let lms = Vec3::new(
+        Vec3::new(0.41222147, 0.53633254, 0.051445993).dot(rgb),
+        Vec3::new(0.2119035, 0.6806995, 0.10739696).dot(rgb),
+        Vec3::new(0.08830246, 0.28171884, 0.6299787).dot(rgb),
+    );
+    let lms = Vec3::new(lms.x.cbrt(), lms.y.cbrt(), lms.z.cbrt());
+    Vec3::new(
+        Vec3::new(0.21045426, 0.7936178, -0.004072047).dot(lms),
+        Vec3::new(1.9779985, -2.4285922, 0.4505937).dot(lms),
+        Vec3::new(0.025904037, 0.78277177, -0.80867577).dot(lms),
+    )
+}
+
+fn oklab_to_linear(lab: Vec3) -> Vec3 {
+    let lms = Vec3::new(
+        Vec3::new(1.0, 0.39633778, 0.21580376).dot(lab),
+        Vec3::new(1.0, -0.105561346, -0.06385417).dot(lab),
+        Vec3::new(1.0, -0.08948418, -1.2914855).dot(lab),
+    );
+    let lms = lms * lms * lms;
+    Vec3::new(
+        Vec3::new(4.0767417, -3.3077116, 0.23096993).dot(lms),
+        Vec3::new(-1.268438, 2.6097574, -0.3413194).dot(lms),
+        Vec3::new(-0.0041960863, -0.7034186, 1.7076147).dot(lms),
+    )
+}
+
+/// Hue in degrees, saturation and value, from gamma encoded rgb.
+fn rgb_to_hsv(rgb: Vec3) -> Vec3 {
+    let max = rgb.max_element();
+    let min = rgb.min_element();
+    let delta = max - min;
+
+    let hue = if delta <= 0.0 {
+        0.0
+    } else if max == rgb.x {
+        60.0 * ((rgb.y - rgb.z) / delta).rem_euclid(6.0)
+    } else if max == rgb.y {
+        60.0 * ((rgb.z - rgb.x) / delta + 2.0)
+    } else {
+        60.0 * ((rgb.x - rgb.y) / delta + 4.0)
+    };
+    let saturation = if max > 0.0 { delta / max } else { 0.0 };
+    Vec3::new(hue, saturation, max)
+}
+
+fn hsv_to_rgb(hsv: Vec3) -> Vec3 {
+    let [hue, saturation, value] = hsv.to_array();
+    let chroma = value * saturation;
+    let x = chroma * (1.0 - ((hue / 60.0).rem_euclid(2.0) - 1.0).abs());
+    let rgb = match (hue / 60.0) as u32 {
+        0 => Vec3::new(chroma, x, 0.0),
+        1 => Vec3::new(x, chroma, 0.0),
+        2 => Vec3::new(0.0, chroma, x),
+        3 => Vec3::new(0.0, x, chroma),
+        4 => Vec3::new(x, 0.0, chroma),
+        _ => Vec3::new(chroma, 0.0, x),
+    };
+    rgb + (value - chroma)
+}
+
+/// How a key hands over to the keys around it.
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug, Serialize, Deserialize)]
+pub enum KeyEasing {
+    #[default]
+    Linear,
+    /// Holds on to the neighbouring colors, only taking over close to the key.
+    EaseIn,
+    /// Spreads out from the key before handing over.
+    EaseOut,
+    EaseInOut,
+    /// A hard edge halfway to the next key.
+    Step,
+}
+
+impl KeyEasing {
+    pub const ALL: [KeyEasing; 5] = [KeyEasing::Linear, KeyEasing::EaseIn, KeyEasing::EaseOut, KeyEasing::EaseInOut, KeyEasing::Step];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            KeyEasing::Linear => "Linear",
+            KeyEasing::EaseIn => "Ease In",
+            KeyEasing::EaseOut => "Ease Out",
+            KeyEasing::EaseInOut => "Ease In Out",
+            KeyEasing::Step => "Step",
+        }
+    }
+
+    pub fn apply(&self, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            KeyEasing::Linear => t,
+            KeyEasing::EaseIn => t * t,
+            KeyEasing::EaseOut => 1.0 - (1.0 - t) * (1.0 - t),
+            KeyEasing::EaseInOut => t * t * (3.0 - 2.0 * t),
+            KeyEasing::Step => if t < 0.5 { 0.0 } else { 1.0 },
+        }
+    }
+}
+
+/// An enabled key, with its color already encoded for blending.
+struct BlendKey {
+    position: Vec3,
+    color: Vec4,
+    easing: KeyEasing,
+}
+
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct ColorGradient {
     key_points: Vec<(f32, f32, [f32; 3], bool)>,
+    /// Each key's normalized latitude, only used with `latitude_axis`.
+    #[serde(default)]
+    key_latitudes: Vec<f32>,
+    /// Whether keys are placed by latitude as well as elevation and steepness.
+    #[serde(default)]
+    pub latitude_axis: bool,
+    #[serde(default)]
+    key_easings: Vec<KeyEasing>,
+    #[serde(default)]
+    pub interpolation: ColorInterpolation,
 }
 
 impl ColorGradient {
-    pub const RESOLUTION: u32 = 20;
-
     pub fn new() -> Self {
         Self {
             key_points: vec![(0.0, 0.0, [0.0; 3], true)],
+            key_latitudes: vec![0.0],
+            latitude_axis: false,
+            key_easings: vec![KeyEasing::Linear],
+            interpolation: ColorInterpolation::Rgb,
         }
     }
+    /// A gradient running along elevation through `keys` of position, color and easing.
+    pub fn from_keys(keys: impl IntoIterator<Item = (f32, [f32; 3], KeyEasing)>, interpolation: ColorInterpolation) -> Self {
+        let mut gradient = Self {
+            key_points: Vec::new(),
+            key_latitudes: Vec::new(),
+            latitude_axis: false,
+            key_easings: Vec::new(),
+            interpolation,
+        };
+        for (u, color, easing) in keys {
+            gradient.add(color, u, 0.0);
+            *gradient.get_easing_mut(gradient.len() - 1) = easing;
+        }
+        gradient
+    }
     pub fn add(&mut self, color: [f32; 3], u: f32, v: f32) {
         self.key_points.push((u, v, color, true));
+        self.key_latitudes.resize(self.key_points.len(), 0.0);
+        self.key_easings.resize(self.key_points.len(), KeyEasing::Linear);
     }
     pub fn get_mut(&mut self, id: usize) -> &mut (f32, f32, [f32; 3], bool) {
         &mut self.key_points[id]
@@ -129,6 +451,15 @@ impl ColorGradient {
     pub fn get_col_mut(&mut self, id: usize) -> &mut [f32; 3] {
         &mut self.key_points[id].2
     }
+    pub fn get_latitude_mut(&mut self, id: usize) -> &mut f32 {
+        // saves from before latitudes existed don't have any
+        self.key_latitudes.resize(self.key_points.len(), 0.0);
+        &mut self.key_latitudes[id]
+    }
+    pub fn get_easing_mut(&mut self, id: usize) -> &mut KeyEasing {
+        self.key_easings.resize(self.key_points.len(), KeyEasing::Linear);
+        &mut self.key_easings[id]
+    }
     pub fn get(&self, id: usize) -> &(f32, f32, [f32; 3], bool) {
         &self.key_points[id]
     }
@@ -144,55 +475,132 @@ impl ColorGradient {
     pub fn get_col(&self, id: usize) -> &[f32; 3] {
         &self.key_points[id].2
     }
+    pub fn get_latitude(&self, id: usize) -> f32 {
+        self.key_latitudes.get(id).copied().unwrap_or(0.0)
+    }
+    pub fn get_easing(&self, id: usize) -> KeyEasing {
+        self.key_easings.get(id).copied().unwrap_or_default()
+    }
     pub fn pop(&mut self, id: usize) {
         self.key_points.remove(id);
+        if id < self.key_latitudes.len() {
+            self.key_latitudes.remove(id);
+        }
+        if id < self.key_easings.len() {
+            self.key_easings.remove(id);
+        }
+    }
+    pub fn len(&self) -> usize {
+        self.key_points.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.key_points.is_empty()
     }
     pub fn count(&self) -> u32 {
         let filtered: Vec<_> = self.key_points.iter().filter(|x| x.3).collect();
         return filtered.len() as u32;
     }
-    pub fn sorted(&self) -> Vec<(f32, f32, [f32; 3])> {
-        let mut keys: Vec<_> = self.key_points.iter()
-            .map(|x| *x)
-            .filter(|x| x.3)
-            .map(|x| (x.0, x.1, x.2))
+    /// The enabled keys along elevation, in order, with their colors and easing.
+    pub fn sorted(&self) -> Vec<(f32, [f32; 3], KeyEasing)> {
+        let mut keys: Vec<_> = self.key_points.iter().enumerate()
+            .filter(|(_, x)| x.3)
+            .map(|(i, x)| (x.0, x.2, self.get_easing(i)))
             .collect();
-        keys.sort_by(|(u1, _, _), (u2, _, _)| u1.partial_cmp(u2).unwrap());
+        keys.sort_by(|(u1, _, _), (u2, _, _)| u1.total_cmp(u2));
         keys
     }
-    pub fn interpolated(&self) -> [Color; Self::RESOLUTION as usize] {
-        let mut colors = [Color::BLACK; Self::RESOLUTION as usize];
+    pub fn interpolated(&self, resolution: u32) -> Vec<Color> {
         let keys = self.sorted();
-
-        for i in 0..Self::RESOLUTION {
-            let t = i as f32 / (Self::RESOLUTION as f32 - 1.0);
-            colors[i as usize] = self.evaluate(t, keys.clone());
-        };
-
-        return colors;
+        (0..resolution)
+            .map(|i| {
+                let t = i as f32 / (resolution as f32 - 1.0).max(1.0);
+                let [r, g, b] = self.evaluate(t, &keys);
+                Color::rgb_linear(r, g, b)
+            })
+            .collect()
+    }
+    fn blend_keys(&self) -> Vec<BlendKey> {
+        self.key_points.iter().enumerate()
+            .filter(|(_, x)| x.3)
+            .map(|(i, x)| {
+                let latitude = if self.latitude_axis { self.get_latitude(i) } else { 0.0 };
+                BlendKey {
+                    position: Vec3::new(x.0, x.1, latitude),
+                    color: self.interpolation.encode(Vec3::from(x.2)),
+                    easing: self.get_easing(i),
+                }
+            })
+            .collect()
     }
-    pub fn evaluate(&self, t: f32, keys: Vec<(f32, f32, [f32; 3])>) -> Color {
-        if t <= keys[0].0 {
-            return Color::from(keys[0].2);
+    fn blend(&self, keys: &[BlendKey], position: Vec3) -> [f32; 3] {
+        let weights: Vec<f32> = keys.iter()
+            .map(|key| {
+                let dist = position.distance(key.position) / 1.5 + 0.000001;
+                let gauss_dist = 1.0 - (-dist * dist * 10.0).exp();
+                -gauss_dist.ln().min(0.000001)
+            })
+            .collect();
+        let total: f32 = weights.iter().sum();
+        if total <= 0.0 {
+            return [0.0; 3];
         }
-        if t >= keys[keys.len() - 1].0 {
-            return Color::from(keys[keys.len() - 1].2);
+
+        // each key's easing reshapes its share of the blend
+        let mut eased: Vec<f32> = keys.iter().zip(weights.iter()).map(|(key, weight)| key.easing.apply(weight / total)).collect();
+        if eased.iter().sum::<f32>() <= 0.0 {
+            eased = weights;
         }
+        let eased_total: f32 = eased.iter().sum();
 
-        for i in 0..(keys.len() - 1) {
-            let start = keys[i];
-            let end = keys[i + 1];
+        let color = keys.iter().zip(eased.iter()).fold(Vec4::ZERO, |color, (key, weight)| color + key.color * (weight / eased_total));
+        self.interpolation.decode(color).to_array()
+    }
+    /// The surface color for a normalized elevation, steepness and latitude, as it's baked into the color lookup.
+    pub fn sample(&self, elevation: f32, steepness: f32, latitude: f32) -> [f32; 3] {
+        let latitude = if self.latitude_axis { latitude } else { 0.0 };
+        self.blend(&self.blend_keys(), Vec3::new(elevation, steepness, latitude))
+    }
+    pub fn bake(&self) -> ColorLut {
+        let depth = if self.latitude_axis { COLOR_LUT_LATITUDE_RESOLUTION } else { 1 };
+        let size = UVec3::new(COLOR_LUT_RESOLUTION, COLOR_LUT_RESOLUTION, depth);
+        let keys = self.blend_keys();
 
-            if t < end.0 {
-                let local_t = ((t - start.0) / (end.0 - start.0)).max(0.0).min(1.0);
+        let mut colors = Vec::with_capacity((size.x * size.y * size.z) as usize);
+        for z in 0..size.z {
+            for y in 0..size.y {
+                for x in 0..size.x {
+                    let position = Vec3::new(
+                        ColorLut::coordinate(x, size.x),
+                        ColorLut::coordinate(y, size.y),
+                        ColorLut::coordinate(z, size.z),
+                    );
+                    colors.push(self.blend(&keys, position));
+                }
+            }
+        }
 
-                let r = start.2[0] + (end.2[0] - start.2[0]) * local_t;
-                let g = start.2[1] + (end.2[1] - start.2[1]) * local_t;
-                let b = start.2[2] + (end.2[2] - start.2[2]) * local_t;
+        ColorLut { size, colors }
+    }
+    /// The color at `t` along elevation between the `sorted` keys, ignoring steepness and latitude,
+    /// with each key's easing shaping the stretch after it.
+    pub fn evaluate(&self, t: f32, keys: &[(f32, [f32; 3], KeyEasing)]) -> [f32; 3] {
+        let (Some(first), Some(last)) = (keys.first(), keys.last()) else { return [0.0; 3] };
+        if t <= first.0 {
+            return first.1;
+        }
+        if t >= last.0 {
+            return last.1;
+        }
 
-                return Color::rgb(r, g, b);
+        for window in keys.windows(2) {
+            let (start, end) = (window[0], window[1]);
+            if t < end.0 {
+                let local_t = start.2.apply((t - start.0) / (end.0 - start.0).max(0.000001));
+                let start_color = self.interpolation.encode(Vec3::from(start.1));
+                let end_color = self.interpolation.encode(Vec3::from(end.1));
+                return self.interpolation.decode(start_color.lerp(end_color, local_t)).to_array();
             }
-        };
-        Color::BLACK
+        }
+        last.1
     }
-}
\ No newline at end of file
+}
@@ -1,8 +1,8 @@
 #![allow(dead_code)]
 
-use bevy::{prelude::*, render::{extract_component::{ExtractComponentPlugin, UniformComponentPlugin}, RenderApp, render_graph::{ViewNodeRunner, RenderGraphApp}}, core_pipeline::core_3d};
+use bevy::{prelude::*, render::{extract_component::{ExtractComponentPlugin, UniformComponentPlugin}, RenderApp, Render, RenderSet, render_graph::{ViewNodeRunner, RenderGraphApp, RenderGraph}}, core_pipeline::core_3d};
 
-use super::atmosphere::{AtmospherePassPostProcessPipeline, AtmosphereSettings, AtmospherePassPostProcessNode};
+use super::{atmosphere::{AtmospherePassPostProcessPipeline, AtmosphereSettings, AtmospherePassPostProcessNode, update_atmosphere, prepare_view_atmospheres}, atmosphere_lut::*, clouds::{CloudPassPostProcessPipeline, CloudSettings}};
 
 
 pub struct PostProcessPlugin;
@@ -12,12 +12,17 @@ impl Plugin for PostProcessPlugin {
         app.add_plugins((
             ExtractComponentPlugin::<AtmosphereSettings>::default(),
             UniformComponentPlugin::<AtmosphereSettings>::default(),
+            ExtractComponentPlugin::<CloudSettings>::default(),
+            UniformComponentPlugin::<CloudSettings>::default(),
+            ExtractComponentPlugin::<AtmosphereLuts>::default(),
         ));
+        app.add_systems(Update, (setup_atmosphere_luts, update_atmosphere_luts.after(update_atmosphere)));
 
         let Ok(render_app) = app.get_sub_app_mut(RenderApp) else {
             return;
         };
 
+        // the atmosphere pass draws each planet's clouds too, so they're layered with the atmospheres
         render_app
             .add_render_graph_node::<ViewNodeRunner<AtmospherePassPostProcessNode>>(
                 core_3d::graph::NAME,
@@ -31,16 +36,35 @@ impl Plugin for PostProcessPlugin {
                     core_3d::graph::node::END_MAIN_PASS_POST_PROCESSING,
                 ],
             );
+
+        // the lookups are baked before any camera draws with them
+        render_app
+            .init_resource::<AtmosphereLutBindGroups>()
+            .init_resource::<BakedAtmosphereLuts>()
+            .add_systems(Render, (
+                prepare_view_atmospheres.in_set(RenderSet::Prepare),
+                queue_atmosphere_lut_bind_groups.in_set(RenderSet::Queue),
+            ));
+
+        let mut render_graph = render_app.world.resource_mut::<RenderGraph>();
+        render_graph.add_node(AtmosphereLutNode::NODE_NAME, AtmosphereLutNode);
+        render_graph.add_node_edge(
+            AtmosphereLutNode::NODE_NAME,
+            bevy::render::main_graph::node::CAMERA_DRIVER,
+        );
     }
 
     fn finish(&self, app: &mut App) {
         app.register_type::<AtmosphereSettings>();
+        app.register_type::<CloudSettings>();
 
         let Ok(render_app) = app.get_sub_app_mut(RenderApp) else {
             return;
         };
 
         render_app
-            .init_resource::<AtmospherePassPostProcessPipeline>();
+            .init_resource::<AtmospherePassPostProcessPipeline>()
+            .init_resource::<CloudPassPostProcessPipeline>()
+            .init_resource::<AtmosphereLutPipeline>();
     }
 }
\ No newline at end of file
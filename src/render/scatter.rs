@@ -0,0 +1,434 @@
+use bevy::{prelude::*, render::{mesh::Indices, render_resource::PrimitiveTopology, view::NoFrustumCulling}, tasks::{AsyncComputeTaskPool, Task}, utils::{HashSet, HashMap}};
+use futures_lite::future;
+use rand::{Rng, SeedableRng, rngs::StdRng};
+use serde::{Serialize, Deserialize};
+
+use crate::gen::{shape::{ShapeGenerator, point_to_lat_long}, heightmap::{cubemap_direction, cubemap_uv}, noise::NoiseSimplex3d, sculpt::Sculpting};
+
+use super::{planet::{Planet, UpdatePlanetMesh}, instancing::{MeshInstance, MeshInstances}};
+
+
+/// Each cube face is split into `2^SCATTER_TILE_DEPTH` scatter tiles along each side.
+const SCATTER_TILE_DEPTH: u32 = 7;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Default, Debug, Serialize, Deserialize)]
+pub enum ScatterKind {
+    #[default]
+    Tree,
+    Rock,
+    Grass,
+}
+
+impl ScatterKind {
+    pub const ALL: [ScatterKind; 3] = [ScatterKind::Tree, ScatterKind::Rock, ScatterKind::Grass];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ScatterKind::Tree => "Tree",
+            ScatterKind::Rock => "Rock",
+            ScatterKind::Grass => "Grass",
+        }
+    }
+
+    /// How far below the surface instances are sunk, as a fraction of their size, so they don't
+    /// float on slopes.
+    fn sink(&self) -> f32 {
+        match self {
+            ScatterKind::Tree => 0.05,
+            ScatterKind::Rock => 0.25,
+            ScatterKind::Grass => 0.1,
+        }
+    }
+}
+
+/// One kind of thing scattered over the surface and where it grows.
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ScatterLayer {
+    pub kind: ScatterKind,
+    pub enabled: bool,
+    /// Candidate positions tried in each tile, before the masks thin them out.
+    pub density: u32,
+    /// The band above sea level this grows in, as fractions of the height of the highest peak.
+    pub min_height: f32,
+    pub max_height: f32,
+    /// Steepest slope in degrees this grows on.
+    pub max_slope: f32,
+    /// Furthest from the equator in degrees this grows.
+    pub max_latitude: f32,
+    /// Frequency of the noise clumping instances together, which only grow where it's above the threshold.
+    pub noise_scale: f32,
+    pub noise_threshold: f32,
+    pub min_size: f32,
+    pub max_size: f32,
+    pub color: [f32; 3],
+}
+
+impl Default for ScatterLayer {
+    fn default() -> Self {
+        Self::new(ScatterKind::Tree)
+    }
+}
+
+impl ScatterLayer {
+    pub fn new(kind: ScatterKind) -> Self {
+        match kind {
+            ScatterKind::Tree => Self {
+                kind,
+                enabled: true,
+                density: 16,
+                min_height: 0.0,
+                max_height: 0.5,
+                max_slope: 30.0,
+                max_latitude: 65.0,
+                noise_scale: 8.0,
+                noise_threshold: 0.45,
+                min_size: 0.01,
+                max_size: 0.02,
+                color: [0.13, 0.35, 0.12],
+            },
+            ScatterKind::Rock => Self {
+                kind,
+                enabled: true,
+                density: 8,
+                min_height: 0.0,
+                max_height: 1.0,
+                max_slope: 60.0,
+                max_latitude: 90.0,
+                noise_scale: 30.0,
+                noise_threshold: 0.3,
+                min_size: 0.003,
+                max_size: 0.01,
+                color: [0.45, 0.43, 0.4],
+            },
+            ScatterKind::Grass => Self {
+                kind,
+                enabled: true,
+                density: 48,
+                min_height: 0.0,
+                max_height: 0.35,
+                max_slope: 25.0,
+                max_latitude: 55.0,
+                noise_scale: 15.0,
+                noise_threshold: 0.35,
+                min_size: 0.003,
+                max_size: 0.006,
+                color: [0.35, 0.55, 0.2],
+            },
+        }
+    }
+}
+
+/// What's scattered over a planet. Placement only depends on the seed, the layers and the
+/// planet's shape, so the same spots always get the same instances as they stream in and out.
+#[derive(Component, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PlanetScatter {
+    pub enabled: bool,
+    pub seed: u32,
+    /// How far around the camera instances are streamed in, and how close to the surface it has to be.
+    pub range: f32,
+    pub layers: Vec<ScatterLayer>,
+}
+
+impl Default for PlanetScatter {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            seed: 0,
+            range: 0.1,
+            layers: ScatterKind::ALL.iter().map(|kind| ScatterLayer::new(*kind)).collect(),
+        }
+    }
+}
+
+
+/// The meshes instanced by each `ScatterKind`, standing on the origin with +Y up.
+#[derive(Resource)]
+pub struct ScatterMeshes(HashMap<ScatterKind, Handle<Mesh>>);
+
+#[derive(Default)]
+struct MeshBuilder {
+    positions: Vec<[f32; 3]>,
+    normals: Vec<[f32; 3]>,
+    colors: Vec<[f32; 4]>,
+    indices: Vec<u32>,
+}
+
+impl MeshBuilder {
+    /// A cone around +Y, shaded smoothly around its sides and closed at the bottom.
+    fn cone(&mut self, base: Vec3, radius: f32, height: f32, segments: u32, color: Color) {
+        let color = color.as_linear_rgba_f32();
+        let slant = Vec2::new(height, radius).normalize();
+        let start = self.positions.len() as u32;
+
+        for i in 0..=segments {
+            let angle = i as f32 / segments as f32 * std::f32::consts::TAU;
+            let (sin, cos) = angle.sin_cos();
+            self.positions.push((base + Vec3::new(cos * radius, 0.0, sin * radius)).into());
+            self.normals.push([cos * slant.x, slant.y, sin * slant.x]);
+            self.colors.push(color);
+            self.positions.push((base + Vec3::Y * height).into());
+            self.normals.push([cos * slant.x, slant.y, sin * slant.x]);
+            self.colors.push(color);
+        }
+        for i in 0..segments {
+            let a = start + i * 2;
+            self.indices.extend([a, a + 1, a + 2]);
+        }
+
+        let center = self.positions.len() as u32;
+        self.positions.push(base.into());
+        self.normals.push([0.0, -1.0, 0.0]);
+        self.colors.push(color);
+        for i in 0..segments {
+            let a = start + i * 2;
+            self.indices.extend([center, a, a + 2]);
+        }
+    }
+
+    /// Bends a unit sphere's vertices through `shape`, shading it flat.
+    fn lumpy_sphere(&mut self, shape: impl Fn(Vec3) -> Vec3, color: Color) {
+        let color = color.as_linear_rgba_f32();
+        let sphere = Mesh::try_from(shape::Icosphere { radius: 1.0, subdivisions: 1 }).unwrap();
+        let positions = sphere.attribute(Mesh::ATTRIBUTE_POSITION).unwrap().as_float3().unwrap();
+        let indices: Vec<usize> = sphere.indices().unwrap().iter().collect();
+
+        for triangle in indices.chunks(3) {
+            let corners = [0, 1, 2].map(|i| shape(Vec3::from(positions[triangle[i]])));
+            let normal = (corners[1] - corners[0]).cross(corners[2] - corners[0]).normalize();
+            for corner in corners {
+                self.indices.push(self.positions.len() as u32);
+                self.positions.push(corner.into());
+                self.normals.push(normal.into());
+                self.colors.push(color);
+            }
+        }
+    }
+
+    fn build(self) -> Mesh {
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, self.positions);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, self.normals);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, self.colors);
+        mesh.set_indices(Some(Indices::U32(self.indices)));
+        mesh
+    }
+}
+
+pub fn setup_scatter_meshes(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+) {
+    // trunks keep their own colour, while the instance colour tints the rest
+    let mut tree = MeshBuilder::default();
+    tree.cone(Vec3::ZERO, 0.06, 0.4, 6, Color::rgb(0.3, 0.2, 0.12));
+    tree.cone(Vec3::Y * 0.2, 0.3, 0.55, 8, Color::WHITE);
+    tree.cone(Vec3::Y * 0.5, 0.22, 0.5, 8, Color::WHITE);
+
+    let mut rock = MeshBuilder::default();
+    rock.lumpy_sphere(|p| {
+        let lump = 1.0 + 0.15 * (p.x * 5.0).sin() * (p.z * 4.0).cos();
+        Vec3::new(p.x * 0.5, p.y * 0.3 + 0.15, p.z * 0.4) * lump
+    }, Color::WHITE);
+
+    let mut grass = MeshBuilder::default();
+    for i in 0..5 {
+        let angle = i as f32 * 2.4;
+        let offset = Vec3::new(angle.cos(), 0.0, angle.sin()) * 0.15 * (i as f32 / 4.0);
+        grass.cone(offset, 0.04, 0.6 + 0.4 * ((i * 3) % 5) as f32 / 4.0, 3, Color::WHITE);
+    }
+
+    commands.insert_resource(ScatterMeshes(HashMap::from_iter([
+        (ScatterKind::Tree, meshes.add(tree.build())),
+        (ScatterKind::Rock, meshes.add(rock.build())),
+        (ScatterKind::Grass, meshes.add(grass.build())),
+    ])));
+}
+
+
+/// A tile of a cube face with scattered instances, parented to its planet.
+#[derive(Component, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ScatterTile {
+    pub face: usize,
+    pub x: u32,
+    pub y: u32,
+}
+
+/// The instances of every layer of a tile, being placed in the background.
+#[derive(Component)]
+pub struct ScatterTileTask(pub Task<Vec<(ScatterKind, Vec<MeshInstance>)>>);
+
+impl ScatterTile {
+    fn containing(direction: Vec3) -> Self {
+        let (face, uv) = cubemap_uv(direction);
+        let tiles = 1 << SCATTER_TILE_DEPTH;
+        let tile = (uv * tiles as f32).floor().clamp(Vec2::ZERO, Vec2::splat((tiles - 1) as f32));
+        Self { face, x: tile.x as u32, y: tile.y as u32 }
+    }
+
+    /// A seed for one layer of this tile, mixed from the planet's so neighbouring tiles differ.
+    fn seed(&self, planet_seed: u32, layer: usize) -> u64 {
+        let mut h = planet_seed as u64;
+        for v in [layer as u64, self.face as u64, self.x as u64, self.y as u64] {
+            // splitmix64
+            h = h.wrapping_add(v).wrapping_add(0x9e3779b97f4a7c15);
+            h = (h ^ (h >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+            h = (h ^ (h >> 27)).wrapping_mul(0x94d049bb133111eb);
+            h ^= h >> 31;
+        }
+        h
+    }
+
+    /// Places each layer's instances over the tile, relative to the planet's centre.
+    fn scatter(&self, scatter: &PlanetScatter, shape_gen: &ShapeGenerator, max_elevation: f32) -> Vec<(ScatterKind, Vec<MeshInstance>)> {
+        let size = 1.0 / (1 << SCATTER_TILE_DEPTH) as f32;
+        let min = Vec2::new(self.x as f32, self.y as f32) * size;
+        let peak_height = max_elevation - shape_gen.sea_level;
+        if peak_height <= 0.0 { return Vec::new() };
+
+        scatter.layers.iter().enumerate().filter(|(_, layer)| layer.enabled).map(|(i, layer)| {
+            let mut rng = StdRng::seed_from_u64(self.seed(scatter.seed, i));
+            let noise = NoiseSimplex3d::new(scatter.seed.wrapping_add(i as u32 * 7919));
+            let color = Color::rgb(layer.color[0], layer.color[1], layer.color[2]);
+
+            let mut instances = Vec::new();
+            for _ in 0..layer.density {
+                // draw everything up front, so rejected candidates don't shift the ones after them
+                let uv = min + Vec2::new(rng.gen(), rng.gen()) * size;
+                let yaw = rng.gen::<f32>() * std::f32::consts::TAU;
+                let scale = layer.min_size + rng.gen::<f32>() * (layer.max_size - layer.min_size).max(0.0);
+                let brightness = rng.gen_range(0.8..1.2);
+                let direction = cubemap_direction(self.face, uv);
+
+                let (latitude, _) = point_to_lat_long(direction);
+                if latitude.to_degrees().abs() > layer.max_latitude { continue };
+                if noise.evaluate(direction * layer.noise_scale) * 0.5 + 0.5 < layer.noise_threshold { continue };
+
+                let elevation = shape_gen.get_elevation(direction);
+                if elevation <= shape_gen.sea_level { continue };
+                let height = (elevation - shape_gen.sea_level) / peak_height;
+                if height < layer.min_height || height > layer.max_height { continue };
+
+                let normal = shape_gen.get_normal(direction, 0.0005);
+                if normal.dot(direction).clamp(-1.0, 1.0).acos().to_degrees() > layer.max_slope { continue };
+
+                let rotation = Quat::from_rotation_arc(Vec3::Y, direction) * Quat::from_rotation_y(yaw);
+                let position = direction * (elevation - scale * layer.kind.sink());
+                let tint = Color::rgb(color.r() * brightness, color.g() * brightness, color.b() * brightness);
+                instances.push(MeshInstance::new(Mat4::from_scale_rotation_translation(Vec3::splat(scale), rotation, position), tint));
+            }
+            (layer.kind, instances)
+        }).collect()
+    }
+}
+
+/// The highest elevation over a fixed grid of directions on every cube face. Unlike the planet's
+/// own bounds it doesn't depend on which chunks happen to be loaded, so a tile always scatters
+/// the same way for the same shape.
+fn peak_elevation(shape_gen: &ShapeGenerator) -> f32 {
+    const GRID: u32 = 32;
+    let mut peak = f32::MIN;
+    for face in 0..6 {
+        for y in 0..GRID {
+            for x in 0..GRID {
+                let uv = (Vec2::new(x as f32, y as f32) + 0.5) / GRID as f32;
+                peak = peak.max(shape_gen.get_elevation(cubemap_direction(face, uv)));
+            }
+        }
+    }
+    peak
+}
+
+/// Streams scatter tiles in around the active camera when it's near a planet's surface, and
+/// rebuilds a planet's tiles when its shape or scatter settings change. Reshaping by sculpting
+/// rebuilds them once the stroke ends, rather than on every step of it.
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub fn update_scatter(
+    mut commands: Commands,
+    mut update_planet_mesh_evr: EventReader<UpdatePlanetMesh>,
+    cameras: Query<(&Camera, &GlobalTransform)>,
+    planets: Query<(Entity, &Planet, &ShapeGenerator, Ref<PlanetScatter>, &GlobalTransform)>,
+    tiles: Query<(Entity, &ScatterTile, &Parent)>,
+    sculpting: Query<(), With<Sculpting>>,
+    mut deferred: Local<HashSet<Entity>>,
+    mut peak_elevations: Local<HashMap<Entity, f32>>,
+) {
+    deferred.extend(update_planet_mesh_evr.iter().map(|ev| ev.planet));
+    let mut rebuilt: HashSet<Entity> = deferred.iter().copied().filter(|planet| !sculpting.contains(*planet)).collect();
+    deferred.retain(|planet| sculpting.contains(*planet));
+    rebuilt.extend(planets.iter().filter(|(.., scatter, _)| scatter.is_changed()).map(|(entity, ..)| entity));
+    peak_elevations.retain(|planet, _| !rebuilt.contains(planet) && planets.contains(*planet));
+
+    let Some((_, camera_transform)) = cameras.iter().find(|(camera, _)| camera.is_active) else { return };
+    let tile_angle = std::f32::consts::FRAC_PI_2 / (1 << SCATTER_TILE_DEPTH) as f32;
+
+    let mut wanted = HashSet::new();
+    for (planet_entity, _, shape_gen, scatter, planet_transform) in planets.iter() {
+        if !scatter.enabled { continue };
+
+        let local = planet_transform.affine().inverse().transform_point3(camera_transform.translation());
+        let Some(direction) = local.try_normalize() else { continue };
+        if local.length() - shape_gen.get_elevation(direction) > scatter.range { continue };
+
+        // every tile with a corner of the disc around the camera's ground point, sampled finer than the tiles
+        let angle = scatter.range / shape_gen.radius;
+        let step = tile_angle * 0.5;
+        let steps = (angle / step).ceil() as i32;
+        let (tangent, bitangent) = direction.any_orthonormal_pair();
+        for i in -steps..=steps {
+            for j in -steps..=steps {
+                let offset = Vec2::new(i as f32, j as f32) * step;
+                if offset.length() > angle + step { continue };
+                let sample = (direction + (tangent * offset.x + bitangent * offset.y)).normalize();
+                wanted.insert((planet_entity, ScatterTile::containing(sample)));
+            }
+        }
+    }
+
+    let mut built = HashSet::new();
+    for (entity, tile, parent) in tiles.iter() {
+        let key = (parent.get(), *tile);
+        if rebuilt.contains(&parent.get()) || !wanted.contains(&key) {
+            commands.entity(entity).despawn_recursive();
+        } else {
+            built.insert(key);
+        }
+    }
+
+    for (planet_entity, tile) in wanted.difference(&built) {
+        let Ok((_, _, shape_gen, scatter, _)) = planets.get(*planet_entity) else { continue };
+        let max_elevation = *peak_elevations.entry(*planet_entity).or_insert_with(|| peak_elevation(shape_gen));
+        let (tile, scatter, shape_gen) = (*tile, scatter.clone(), shape_gen.clone());
+        let task = AsyncComputeTaskPool::get().spawn(async move {
+            tile.scatter(&scatter, &shape_gen, max_elevation)
+        });
+        commands.spawn((
+            tile,
+            ScatterTileTask(task),
+            SpatialBundle::default(),
+        )).set_parent(*planet_entity);
+    }
+}
+
+/// Gives finished tiles an instanced mesh for each of their layers.
+pub fn poll_scatter_tasks(
+    mut commands: Commands,
+    mut tasks: Query<(Entity, &mut ScatterTileTask)>,
+    scatter_meshes: Res<ScatterMeshes>,
+) {
+    for (entity, mut task) in tasks.iter_mut() {
+        let Some(layers) = future::block_on(future::poll_once(&mut task.0)) else { continue };
+        commands.entity(entity).remove::<ScatterTileTask>().with_children(|parent| {
+            for (kind, instances) in layers {
+                if instances.is_empty() { continue };
+                parent.spawn((
+                    scatter_meshes.0[&kind].clone(),
+                    MeshInstances(instances),
+                    SpatialBundle::default(),
+                    NoFrustumCulling,
+                ));
+            }
+        });
+    }
+}
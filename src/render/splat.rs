@@ -0,0 +1,175 @@
+use bevy::{prelude::*, render::{render_resource::{Extent3d, TextureDimension, TextureFormat, TextureViewDescriptor, TextureViewDimension, SamplerDescriptor, AddressMode, FilterMode}, texture::ImageSampler}};
+use image::imageops::FilterType;
+use serde::{Serialize, Deserialize};
+
+use super::{planet::{Planet, UpdatePlanetMaterials}, planet_mat::{PlanetMaterial, TerrainLayerEntry}};
+
+
+/// The most layers a planet can splat, which keeps the texture arrays to a sensible size.
+pub const MAX_TERRAIN_LAYERS: usize = 8;
+
+/// A ground material painted over the color gradient wherever the surface's elevation and
+/// steepness fall inside its bands.
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TerrainLayer {
+    pub name: String,
+    pub enabled: bool,
+    /// Textures under `assets/textures`, left empty to use a plain white albedo, a flat normal or full roughness.
+    pub albedo_texture: String,
+    pub normal_texture: String,
+    pub roughness_texture: String,
+    /// Multiplied with the albedo texture.
+    pub tint: [f32; 3],
+    /// Multiplied with the roughness texture.
+    pub roughness: f32,
+    pub normal_strength: f32,
+    /// How many times the textures repeat per unit, projected along each axis.
+    pub triplanar_scale: f32,
+    /// The band this covers, using the same normalized elevation and steepness as the color gradient.
+    pub min_elevation: f32,
+    pub max_elevation: f32,
+    pub min_steepness: f32,
+    pub max_steepness: f32,
+    /// How far past the edges of its bands the layer fades out.
+    pub blend: f32,
+}
+
+impl Default for TerrainLayer {
+    fn default() -> Self {
+        Self {
+            name: "Layer".to_string(),
+            enabled: true,
+            albedo_texture: String::new(),
+            normal_texture: "normals/rocks_1.png".to_string(),
+            roughness_texture: String::new(),
+            tint: [1.0; 3],
+            roughness: 0.8,
+            normal_strength: 0.5,
+            triplanar_scale: 1.0,
+            min_elevation: 0.0,
+            max_elevation: 1.0,
+            min_steepness: 0.0,
+            max_steepness: 1.0,
+            blend: 0.05,
+        }
+    }
+}
+
+impl TerrainLayer {
+    fn entry(&self) -> TerrainLayerEntry {
+        TerrainLayerEntry {
+            tint: self.tint,
+            roughness: self.roughness,
+            elevation: Vec2::new(self.min_elevation, self.max_elevation),
+            steepness: Vec2::new(self.min_steepness, self.max_steepness),
+            blend: self.blend,
+            triplanar_scale: self.triplanar_scale,
+            normal_strength: self.normal_strength,
+        }
+    }
+
+    fn textures(&self) -> [String; 3] {
+        [self.albedo_texture.clone(), self.normal_texture.clone(), self.roughness_texture.clone()]
+    }
+}
+
+/// The ground materials splatted onto a planet, in order, with later layers painted over earlier ones.
+#[derive(Component, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PlanetTerrainLayers {
+    /// The width and height every layer texture is resized to.
+    pub texture_resolution: u32,
+    pub layers: Vec<TerrainLayer>,
+}
+
+impl Default for PlanetTerrainLayers {
+    fn default() -> Self {
+        Self {
+            texture_resolution: 512,
+            layers: Vec::new(),
+        }
+    }
+}
+
+
+/// Loads a layer texture as rgba at `resolution`, or fills it with `fallback` when there's none.
+fn load_layer_texture(path: &str, resolution: u32, fallback: [u8; 4]) -> Vec<u8> {
+    let texels = (resolution * resolution) as usize;
+    if path.is_empty() {
+        return fallback.repeat(texels);
+    }
+
+    match image::open(format!("assets/textures/{}", path)) {
+        Ok(image) => image::imageops::resize(&image.to_rgba8(), resolution, resolution, FilterType::Triangle).into_raw(),
+        Err(err) => {
+            warn!("Failed to load terrain layer texture {}: {}", path, err);
+            fallback.repeat(texels)
+        }
+    }
+}
+
+fn texture_array(layers: Vec<Vec<u8>>, resolution: u32, format: TextureFormat) -> Image {
+    let mut image = Image::new(
+        Extent3d {
+            width: resolution,
+            height: resolution,
+            depth_or_array_layers: layers.len() as u32,
+        },
+        TextureDimension::D2,
+        layers.concat(),
+        format,
+    );
+    // a single layer would otherwise get a plain 2d view
+    image.texture_view_descriptor = Some(TextureViewDescriptor {
+        dimension: Some(TextureViewDimension::D2Array),
+        ..default()
+    });
+    image.sampler_descriptor = ImageSampler::Descriptor(SamplerDescriptor {
+        address_mode_u: AddressMode::Repeat,
+        address_mode_v: AddressMode::Repeat,
+        mag_filter: FilterMode::Linear,
+        min_filter: FilterMode::Linear,
+        ..default()
+    });
+    image
+}
+
+/// Uploads the enabled terrain layers to the planet's material, only reloading the textures when
+/// one of their paths or the resolution changed.
+pub fn generate_terrain_layers(
+    planets: Query<(&Planet, &PlanetTerrainLayers)>,
+    mut materials: ResMut<Assets<PlanetMaterial>>,
+    mut images: ResMut<Assets<Image>>,
+    mut update_planet_mats_evr: EventReader<UpdatePlanetMaterials>,
+) {
+    for update_planet_mats_ev in update_planet_mats_evr.iter() {
+        let Ok((planet, terrain_layers)) = planets.get(update_planet_mats_ev.planet) else { continue };
+        let Some(mat) = materials.get_mut(&planet.material) else { continue };
+
+        let enabled: Vec<_> = terrain_layers.layers.iter().filter(|layer| layer.enabled).take(MAX_TERRAIN_LAYERS).collect();
+        mat.n_layers = enabled.len() as u32;
+        mat.layers = enabled.iter().map(|layer| layer.entry()).collect();
+
+        // an empty storage buffer can't be bound, so keep a placeholder entry around
+        if mat.layers.is_empty() {
+            mat.layers.push(TerrainLayerEntry::default());
+        }
+
+        let textures: Vec<_> = enabled.iter().map(|layer| layer.textures()).collect();
+        let resolution = terrain_layers.texture_resolution.max(1);
+        if textures.is_empty() || (textures == mat.layer_textures && resolution == mat.layer_texture_resolution) {
+            continue;
+        }
+
+        let albedo = textures.iter().map(|paths| load_layer_texture(&paths[0], resolution, [255; 4])).collect();
+        let normals = textures.iter().map(|paths| load_layer_texture(&paths[1], resolution, [128, 128, 255, 255])).collect();
+        let roughness = textures.iter().map(|paths| load_layer_texture(&paths[2], resolution, [255; 4])).collect();
+
+        mat.layer_albedo = Some(images.add(texture_array(albedo, resolution, TextureFormat::Rgba8UnormSrgb)));
+        mat.layer_normals = Some(images.add(texture_array(normals, resolution, TextureFormat::Rgba8Unorm)));
+        mat.layer_roughness = Some(images.add(texture_array(roughness, resolution, TextureFormat::Rgba8Unorm)));
+        mat.layer_textures = textures;
+        mat.layer_texture_resolution = resolution;
+    }
+}
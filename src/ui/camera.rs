@@ -4,9 +4,9 @@ use bevy_rapier3d::prelude::*;
 use super::controller::*;
 // use bevy_fps_controller::controller::*;
 
-use crate::render::{atmosphere::AtmosphereSettings, planet::TerrainFace};
+use crate::{render::{planet::{Planet, nearest_planet}, orbit::{PlanetOrbit, SimClock}}, gen::shape::ShapeGenerator};
 
-use super::render::UiVisibility;
+use super::{render::UiVisibility, planets::SelectedPlanet};
 
 
 #[derive(Resource, PartialEq, Default, Copy, Clone)]
@@ -34,32 +34,39 @@ pub fn spawn_camera(
             ..default()
         }, 
         PanOrbitCamera::default(), 
-        AtmosphereSettings::default(),
         DepthPrepass,
     ));
 }
 
 
+#[allow(clippy::too_many_arguments)]
 pub fn update_camera_mode(
     mut commands: Commands,
     camera_entities: Query<Entity, With<Camera>>,
     camera_mode: Res<CameraMode>,
-    terrain_faces: Query<&Handle<Mesh>, With<TerrainFace>>,
-    meshes: Res<Assets<Mesh>>,
+    planets: Query<&GlobalTransform, With<Planet>>,
+    selected_planet: Res<SelectedPlanet>,
     explore_cams: Query<Entity, With<FpsController>>,
     mut old_cam_mode: Local<CameraMode>,
     mut primary_window: Query<&mut Window, With<PrimaryWindow>>,
     mut ui_visibility: ResMut<UiVisibility>,
 ) {
     let mut window = primary_window.single_mut();
+    let center = selected_planet.0
+        .and_then(|entity| planets.get(entity).ok())
+        .map_or(Vec3::ZERO, |transform| transform.translation());
 
     if *camera_mode != *old_cam_mode {
         for entity in camera_entities.iter() {
             if *camera_mode == CameraMode::Edit {
                 commands.entity(entity)
                     .remove::<RenderPlayer>()
-                    .insert(Transform::from_xyz(0.0, 0.0, 5.0).looking_at(Vec3::ZERO, Vec3::Y))
-                    .insert(PanOrbitCamera::default());
+                    .insert(Transform::from_translation(center + Vec3::new(0.0, 0.0, 5.0)).looking_at(center, Vec3::Y))
+                    .insert(PanOrbitCamera {
+                        focus: center,
+                        target_focus: center,
+                        ..default()
+                    });
 
                 for explore_cam in explore_cams.iter() {
                     commands.entity(explore_cam).despawn();
@@ -70,7 +77,7 @@ pub fn update_camera_mode(
                 *ui_visibility = UiVisibility::Visible;
             } else {
                 let logical_entity = commands.spawn((
-                    TransformBundle::from_transform(Transform::from_xyz(0.0, 2.0, 0.0)),
+                    TransformBundle::from_transform(Transform::from_translation(center + Vec3::new(0.0, 2.0, 0.0))),
                     Collider::capsule(Vec3::Y * 0.005, Vec3::Y * 0.015, 0.005),
                     Friction {
                         coefficient: 0.0,
@@ -108,13 +115,6 @@ pub fn update_camera_mode(
                     .remove::<PanOrbitCamera>()
                     .insert(RenderPlayer { logical_entity });
 
-                for mesh_handle in terrain_faces.iter() {
-                    let mesh = meshes.get(mesh_handle).unwrap();
-                    let vertices = mesh.attribute(Mesh::ATTRIBUTE_POSITION).unwrap().as_float3().unwrap().iter().map(|x| Vec3::from(*x)).collect();
-                    let indices = mesh.indices().unwrap().iter().collect::<Vec<usize>>().chunks(3).map(|x| [x[0] as u32, x[1] as u32, x[2] as u32]).collect();
-                    commands.spawn(Collider::trimesh(vertices, indices));
-                }
-
                 window.cursor.grab_mode = CursorGrabMode::Confined;
                 window.cursor.visible = false;
                 *ui_visibility = UiVisibility::Hidden;
@@ -147,16 +147,60 @@ pub fn camera_cursor_grab(
     }
 }
 
+/// Moves the orbit camera's focus over to the newly selected planet.
+pub fn focus_selected_planet(
+    selected_planet: Res<SelectedPlanet>,
+    planets: Query<&GlobalTransform, With<Planet>>,
+    mut cameras: Query<&mut PanOrbitCamera>,
+) {
+    if !selected_planet.is_changed() { return };
+    let Some(planet_entity) = selected_planet.0 else { return };
+    let Ok(planet_transform) = planets.get(planet_entity) else { return };
+
+    for mut pan_orbit in cameras.iter_mut() {
+        pan_orbit.target_focus = planet_transform.translation();
+    }
+}
+
+/// Up is away from the centre of whichever planet's surface is closest.
 pub fn update_camera_local_up(
     mut controllers: Query<(&mut FpsController, &Transform), Without<RenderPlayer>>,
     mut cameras: Query<&mut Transform, With<RenderPlayer>>,
+    planets: Query<(&GlobalTransform, &ShapeGenerator), With<Planet>>,
 ) {
+    let planet_center = |position: Vec3| nearest_planet(
+        position,
+        planets.iter().map(|(transform, shape_gen)| (transform.translation(), transform.translation(), shape_gen.radius)),
+    ).unwrap_or(Vec3::ZERO);
+
     for (mut controller, transform) in controllers.iter_mut() {
-        controller.local_up = (transform.translation - Vec3::ZERO).normalize();
+        controller.local_up = (transform.translation - planet_center(transform.translation)).normalize();
     }
     for mut transform in cameras.iter_mut() {
         let forward = transform.forward();
-        let up = (transform.translation - Vec3::ZERO).normalize();
+        let up = (transform.translation - planet_center(transform.translation)).normalize();
         transform.look_to(forward, up);
     }
-}
\ No newline at end of file
+}
+
+/// Carries explorers around with the planet they're closest to as it spins, so they stay put on the
+/// ground instead of the surface sliding away underneath them.
+pub fn follow_planet_rotation(
+    mut players: Query<&mut Transform, With<LogicalPlayer>>,
+    planets: Query<(&PlanetOrbit, &ShapeGenerator, &GlobalTransform), With<Planet>>,
+    clock: Res<SimClock>,
+    mut last_time: Local<Option<f64>>,
+) {
+    let Some(previous_time) = last_time.replace(clock.time) else { return };
+    if previous_time == clock.time { return };
+
+    for mut transform in players.iter_mut() {
+        let Some((orbit, center)) = nearest_planet(
+            transform.translation,
+            planets.iter().map(|(orbit, shape_gen, planet_transform)| ((orbit, planet_transform.translation()), planet_transform.translation(), shape_gen.radius)),
+        ) else { continue };
+
+        let delta = orbit.rotation(clock.time) * orbit.rotation(previous_time).inverse();
+        transform.translation = center + delta * (transform.translation - center);
+    }
+}
@@ -0,0 +1,87 @@
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+
+use crate::render::clouds::PlanetClouds;
+
+use super::{render::UiVisibility, planets::SelectedPlanet};
+
+
+pub fn cloud_settings(
+    mut contexts: EguiContexts,
+    mut planets: Query<&mut PlanetClouds>,
+    selected_planet: Res<SelectedPlanet>,
+    ui_visibility: Res<UiVisibility>,
+) {
+    if *ui_visibility != UiVisibility::Visible { return };
+
+    let Some(planet_entity) = selected_planet.0 else { return };
+    let Ok(mut clouds) = planets.get_mut(planet_entity) else { return };
+
+    // the materials pick the clouds up every frame, so there's nothing to send when they change
+    egui::Window::new("Clouds").show(contexts.ctx_mut(), |ui| {
+        ui.horizontal(|ui| {
+            ui.label("Enabled:");
+            ui.add(egui::widgets::Checkbox::without_text(&mut clouds.enabled));
+        });
+
+        ui.add_enabled_ui(clouds.enabled, |ui| {
+            ui.horizontal(|ui| {
+                ui.label("Height:");
+                ui.add(egui::DragValue::new(&mut clouds.min_height).prefix("Min: ").speed(0.005).min_decimals(2).clamp_range(0.0..=1.0));
+                ui.add(egui::DragValue::new(&mut clouds.max_height).prefix("Max: ").speed(0.005).min_decimals(2).clamp_range(0.0..=1.0));
+            });
+            ui.horizontal(|ui| {
+                ui.label("Coverage:");
+                ui.add(egui::Slider::new(&mut clouds.coverage, 0.0..=1.0));
+            });
+            ui.horizontal(|ui| {
+                ui.label("Density:");
+                ui.add(egui::DragValue::new(&mut clouds.density).speed(0.25).min_decimals(1).clamp_range(0.0..=1000.0));
+            });
+            ui.horizontal(|ui| {
+                ui.label("Color:");
+                egui::color_picker::color_edit_button_rgb(ui, &mut clouds.color);
+            });
+            ui.horizontal(|ui| {
+                ui.label("Shadow Strength:");
+                ui.add(egui::Slider::new(&mut clouds.shadow_strength, 0.0..=1.0));
+            });
+
+            ui.separator();
+
+            ui.horizontal(|ui| {
+                ui.label("Noise Scale:");
+                ui.add(egui::DragValue::new(&mut clouds.noise_scale).speed(0.025).min_decimals(2).clamp_range(0.0..=100.0));
+            });
+            ui.horizontal(|ui| {
+                ui.label("Detail Scale:");
+                ui.add(egui::DragValue::new(&mut clouds.detail_scale).speed(0.025).min_decimals(2).clamp_range(0.0..=100.0));
+            });
+            ui.horizontal(|ui| {
+                ui.label("Seed:");
+                ui.add(egui::DragValue::new(&mut clouds.seed));
+            });
+            ui.label("Wind:");
+            ui.indent(1, |ui| {
+                ui.add(egui::DragValue::new(&mut clouds.wind[0]).speed(0.001).min_decimals(3).prefix("X: "));
+                ui.add(egui::DragValue::new(&mut clouds.wind[1]).speed(0.001).min_decimals(3).prefix("Y: "));
+                ui.add(egui::DragValue::new(&mut clouds.wind[2]).speed(0.001).min_decimals(3).prefix("Z: "));
+            });
+            ui.horizontal(|ui| {
+                ui.label("Turbulence:");
+                ui.add(egui::DragValue::new(&mut clouds.turbulence).speed(0.001).min_decimals(3).clamp_range(0.0..=10.0));
+            });
+
+            ui.separator();
+
+            ui.horizontal(|ui| {
+                ui.label("Num Steps:");
+                ui.add(egui::DragValue::new(&mut clouds.num_steps).speed(0.1).clamp_range(1..=256));
+            });
+            ui.horizontal(|ui| {
+                ui.label("Num Light Steps:");
+                ui.add(egui::DragValue::new(&mut clouds.num_light_steps).speed(0.05).clamp_range(1..=32));
+            });
+        });
+    });
+}
@@ -0,0 +1,153 @@
+use bevy::{prelude::*, utils::HashSet};
+use bevy_rapier3d::prelude::*;
+
+use crate::{render::planet::{Planet, UpdatePlanetMesh}, gen::{shape::ShapeGenerator, heightmap::{cubemap_direction, cubemap_uv}}};
+
+use super::camera::CameraMode;
+
+
+/// How finely the planets' collision is built, independently of the rendered meshes.
+#[derive(Resource)]
+pub struct PlanetCollisionSettings {
+    /// Each cube face is split into `2^tile_depth` tiles along each side.
+    pub tile_depth: u32,
+    /// Height samples along each side of a tile.
+    pub resolution: u32,
+    /// Bodies further than this above the surface get no collision built around them.
+    pub range: f32,
+}
+
+impl Default for PlanetCollisionSettings {
+    fn default() -> Self {
+        Self {
+            tile_depth: 5,
+            resolution: 32,
+            range: 0.5,
+        }
+    }
+}
+
+/// A heightfield collider covering one tile of a cube face, parented to its planet.
+#[derive(Component, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PlanetColliderTile {
+    pub face: usize,
+    pub depth: u32,
+    pub x: u32,
+    pub y: u32,
+    pub resolution: u32,
+}
+
+impl PlanetColliderTile {
+    /// The tile at `depth` that `direction` passes through.
+    fn containing(direction: Vec3, depth: u32, resolution: u32) -> Self {
+        let (face, uv) = cubemap_uv(direction);
+        let tiles = 1 << depth;
+        let tile = (uv * tiles as f32).floor().clamp(Vec2::ZERO, Vec2::splat((tiles - 1) as f32));
+        Self { face, depth, x: tile.x as u32, y: tile.y as u32, resolution }
+    }
+
+    /// Samples the planet's surface over the tile in a frame tangent to its centre, so the
+    /// heightfield's up points away from the planet and its grid follows the curvature closely.
+    fn build(&self, shape_gen: &ShapeGenerator) -> (Collider, Transform) {
+        let size = 1.0 / (1 << self.depth) as f32;
+        let min = Vec2::new(self.x as f32, self.y as f32) * size;
+
+        let center = cubemap_direction(self.face, min + size * 0.5);
+        let u_axis = cubemap_direction(self.face, min + Vec2::new(size, size * 0.5)) - cubemap_direction(self.face, min + Vec2::new(0.0, size * 0.5));
+        let bitangent = u_axis.cross(center).normalize();
+        let tangent = center.cross(bitangent);
+
+        // widest reach of the tile's corners on the tangent plane, padded so neighbouring tiles overlap
+        let extent = [Vec2::ZERO, Vec2::X, Vec2::Y, Vec2::ONE].iter().map(|corner| {
+            let direction = cubemap_direction(self.face, min + *corner * size);
+            let projected = direction / direction.dot(center);
+            projected.dot(tangent).abs().max(projected.dot(bitangent).abs())
+        }).fold(0.0, f32::max) * 1.05;
+
+        // parry stores heights column-major, with rows along z and columns along x
+        let resolution = self.resolution.max(2) as usize;
+        let step = 2.0 * extent / (resolution - 1) as f32;
+        let mut heights = Vec::with_capacity(resolution * resolution);
+        for column in 0..resolution {
+            for row in 0..resolution {
+                let x = -extent + column as f32 * step;
+                let z = -extent + row as f32 * step;
+                let direction = (center + tangent * x + bitangent * z).normalize();
+                heights.push(shape_gen.get_point(direction).dot(center));
+            }
+        }
+
+        // grid points spread out with their height, so scale the grid by the average height
+        let reference_height = heights.iter().sum::<f32>() / heights.len() as f32;
+        let width = 2.0 * extent * reference_height;
+        let collider = Collider::heightfield(heights, resolution, resolution, Vec3::new(width, 1.0, width));
+        let transform = Transform::from_rotation(Quat::from_mat3(&Mat3::from_cols(tangent, center, bitangent)));
+        (collider, transform)
+    }
+}
+
+/// Keeps heightfield tiles built around every moving physics body near a planet while exploring,
+/// rebuilding a planet's tiles when its shape changes and clearing them all when leaving Explore.
+#[allow(clippy::type_complexity)]
+pub fn update_planet_colliders(
+    mut commands: Commands,
+    camera_mode: Res<CameraMode>,
+    settings: Res<PlanetCollisionSettings>,
+    mut update_planet_mesh_evr: EventReader<UpdatePlanetMesh>,
+    bodies: Query<(&GlobalTransform, &RigidBody)>,
+    planets: Query<(Entity, &ShapeGenerator, &GlobalTransform), With<Planet>>,
+    tiles: Query<(Entity, &PlanetColliderTile, &Parent)>,
+) {
+    let reshaped: HashSet<Entity> = update_planet_mesh_evr.iter().map(|ev| ev.planet).collect();
+
+    if *camera_mode != CameraMode::Explore {
+        for (entity, _, _) in tiles.iter() {
+            commands.entity(entity).despawn_recursive();
+        }
+        return;
+    }
+
+    let depth = settings.tile_depth.min(12);
+    let tile_angle = std::f32::consts::FRAC_PI_2 / (1 << depth) as f32;
+
+    let mut wanted = HashSet::new();
+    for (body_transform, rigid_body) in bodies.iter() {
+        if *rigid_body == RigidBody::Fixed { continue };
+
+        for (planet_entity, shape_gen, planet_transform) in planets.iter() {
+            let local = planet_transform.affine().inverse().transform_point3(body_transform.translation());
+            let Some(direction) = local.try_normalize() else { continue };
+            if local.length() - shape_gen.get_elevation(direction) > settings.range { continue };
+
+            // the body's own tile and the ones bordering it, so it never walks off the edge
+            let (tangent, bitangent) = direction.any_orthonormal_pair();
+            for i in -1..=1 {
+                for j in -1..=1 {
+                    let offset = (tangent * i as f32 + bitangent * j as f32) * tile_angle * 0.75;
+                    let tile = PlanetColliderTile::containing((direction + offset).normalize(), depth, settings.resolution);
+                    wanted.insert((planet_entity, tile));
+                }
+            }
+        }
+    }
+
+    let mut built = HashSet::new();
+    for (entity, tile, parent) in tiles.iter() {
+        let key = (parent.get(), *tile);
+        if reshaped.contains(&parent.get()) || !wanted.contains(&key) {
+            commands.entity(entity).despawn_recursive();
+        } else {
+            built.insert(key);
+        }
+    }
+
+    for (planet_entity, tile) in wanted.difference(&built) {
+        let Ok((_, shape_gen, _)) = planets.get(*planet_entity) else { continue };
+        let (collider, transform) = tile.build(shape_gen);
+        commands.spawn((
+            *tile,
+            collider,
+            TransformBundle::from_transform(transform),
+        )).set_parent(*planet_entity);
+    }
+}
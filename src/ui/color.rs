@@ -2,14 +2,29 @@ use bevy::prelude::*;
 use bevy_egui::{egui, EguiContexts};
 use serde::{Serialize, Deserialize};
 
-use crate::render::{planet::UpdatePlanetMaterials, planet_mat::ColorGradient};
+use std::path::Path;
 
-use super::render::UiVisibility;
+use crate::{render::{planet::UpdatePlanetMaterials, planet_mat::{ColorGradient, ColorLut, ColorInterpolation, KeyEasing}}, export::gradient::{import_gradient, export_gradient}};
 
-#[derive(Resource, Serialize, Deserialize, Clone)]
+use super::{render::UiVisibility, planets::SelectedPlanet};
+
+#[derive(Component, Serialize, Deserialize, Clone)]
 pub struct UiColorSettings {
     pub num_colors: usize,
+    /// Colors the land by height above sea level, or the whole surface when there's no separate seabed.
     pub colors: ColorGradient,
+    /// Whether the seabed is colored by `underwater`, keyed on depth below sea level.
+    #[serde(default)]
+    pub separate_underwater: bool,
+    #[serde(default = "default_underwater_gradient")]
+    pub underwater: ColorGradient,
+}
+
+fn default_underwater_gradient() -> ColorGradient {
+    ColorGradient::from_keys([
+        (0.0, [0.45, 0.38, 0.22], KeyEasing::Linear),
+        (1.0, [0.01, 0.02, 0.05], KeyEasing::EaseOut),
+    ], ColorInterpolation::Oklab)
 }
 
 impl Default for UiColorSettings {
@@ -17,52 +32,248 @@ impl Default for UiColorSettings {
         Self {
             num_colors: 1,
             colors: ColorGradient::new(),
+            separate_underwater: false,
+            underwater: default_underwater_gradient(),
+        }
+    }
+}
+
+impl UiColorSettings {
+    /// The color at an absolute `elevation` on a surface spanning `elevation_range`, picked the same way as in `planet.wgsl`.
+    pub fn sample(&self, elevation: f32, elevation_range: (f32, f32), sea_level: f32, steepness: f32, latitude: f32) -> [f32; 3] {
+        let (min_elevation, max_elevation) = elevation_range;
+        if !self.separate_underwater {
+            let norm_elevation = (elevation - min_elevation) / (max_elevation - min_elevation).max(0.000001);
+            return self.colors.sample(norm_elevation.clamp(0.0, 1.0), steepness, latitude);
+        }
+
+        if elevation < sea_level {
+            let depth = (sea_level - elevation) / (sea_level - min_elevation).max(0.000001);
+            self.underwater.sample(depth.clamp(0.0, 1.0), steepness, latitude)
+        } else {
+            let height = (elevation - sea_level) / (max_elevation - sea_level).max(0.000001);
+            self.colors.sample(height.clamp(0.0, 1.0), steepness, latitude)
+        }
+    }
+}
+
+/// Which of a planet's gradients the color settings are editing.
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+pub enum EditedGradient {
+    #[default]
+    Land,
+    Underwater,
+}
+
+/// Where gradients are imported from and exported to.
+pub struct GradientFile {
+    path: String,
+    /// How many keys an imported strip image is sampled into.
+    strip_keys: u32,
+    status: Option<String>,
+}
+
+impl Default for GradientFile {
+    fn default() -> Self {
+        Self {
+            path: String::from("assets/gradients/gradient.ggr"),
+            strip_keys: 8,
+            status: None,
         }
     }
 }
 
+/// The baked lookup shown in the color settings, rebaked whenever the gradient changes.
+#[derive(Default)]
+pub struct ColorLutPreview {
+    planet: Option<Entity>,
+    lut: Option<ColorLut>,
+    texture: Option<egui::TextureHandle>,
+    /// The latitude texel whose slice is shown.
+    latitude: u32,
+}
+
+fn preview_image(lut: &ColorLut, latitude: u32) -> egui::ColorImage {
+    let (width, height) = (lut.size.x as usize, lut.size.y as usize);
+    let slice = lut.slice(latitude);
+
+    // flip it so steepness increases upwards
+    let pixels = (0..height).rev()
+        .flat_map(|y| slice[y * width..(y + 1) * width].iter())
+        .map(|[r, g, b]| egui::Color32::from(egui::Rgba::from_rgb(*r, *g, *b)))
+        .collect();
+    egui::ColorImage { size: [width, height], pixels }
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn color_settings(
     mut contexts: EguiContexts,
-    mut settings: ResMut<UiColorSettings>,
+    mut planets: Query<&mut UiColorSettings>,
+    selected_planet: Res<SelectedPlanet>,
     mut update_planet_mats_evw: EventWriter<UpdatePlanetMaterials>,
     ui_visibility: Res<UiVisibility>,
+    mut preview: Local<ColorLutPreview>,
+    mut gradient_file: Local<GradientFile>,
+    mut edited: Local<EditedGradient>,
 ) {
     if *ui_visibility != UiVisibility::Visible { return };
 
+    let Some(planet_entity) = selected_planet.0 else { return };
+    let Ok(mut settings) = planets.get_mut(planet_entity) else { return };
+
     let mut changed = false;
+    let old_edited = *edited;
 
     egui::Window::new("Color Settings").show(contexts.ctx_mut(), |ui| {
         ui.horizontal(|ui| {
-            ui.label("Elevation Key Colors:");
-            if ui.small_button("-").clicked() && settings.num_colors > 1 {
-                settings.num_colors -= 1;
-                let num_colors = settings.num_colors;
-                settings.colors.pop(num_colors);
+            ui.label("Separate Underwater:");
+            changed |= ui.add(egui::Checkbox::without_text(&mut settings.separate_underwater)).changed();
+        });
+        if settings.separate_underwater {
+            ui.horizontal(|ui| {
+                ui.label("Editing:");
+                ui.selectable_value(&mut *edited, EditedGradient::Land, "Land");
+                ui.selectable_value(&mut *edited, EditedGradient::Underwater, "Underwater");
+            });
+        } else {
+            *edited = EditedGradient::Land;
+        }
+        let underwater = *edited == EditedGradient::Underwater;
+        // land keys run from sea level up once the seabed has its own gradient
+        let key_axis = if underwater { "Depth" } else if settings.separate_underwater { "Height" } else { "Elevation" };
+
+        ui.separator();
+
+        let settings = &mut *settings;
+        let gradient = if underwater { &mut settings.underwater } else { &mut settings.colors };
+
+        ui.horizontal(|ui| {
+            ui.label(format!("{} Key Colors:", key_axis));
+            if ui.small_button("-").clicked() && gradient.len() > 1 {
+                gradient.pop(gradient.len() - 1);
+                changed = true;
             }
-            ui.label(format!("{}", settings.num_colors));
+            ui.label(format!("{}", gradient.len()));
             if ui.small_button("+").clicked() {
-                settings.num_colors += 1;
-                settings.colors.add([0.0; 3], 0.0, 0.0);
+                gradient.add([0.0; 3], 0.0, 0.0);
+                changed = true;
+            }
+        });
+
+        ui.horizontal(|ui| {
+            ui.label("Latitude Axis:");
+            changed |= ui.add(egui::Checkbox::without_text(&mut gradient.latitude_axis)).changed();
+        });
+        let latitude_axis = gradient.latitude_axis;
+
+        ui.horizontal(|ui| {
+            ui.label("Interpolation:");
+            for interpolation in ColorInterpolation::ALL {
+                changed |= ui.selectable_value(&mut gradient.interpolation, interpolation, interpolation.name()).changed();
             }
         });
 
-        for i in 0..settings.num_colors {
-            let old = settings.colors.get(i).clone();
+        for i in 0..gradient.len() {
+            let old = *gradient.get(i);
+            let old_latitude = gradient.get_latitude(i);
+            let old_easing = gradient.get_easing(i);
             ui.horizontal(|ui| {
                 ui.collapsing(format!("Point {}", i + 1), |ui| {
-                    ui.add(egui::Checkbox::without_text(settings.colors.get_enabled_mut(i)));
-                    ui.add_enabled_ui(*settings.colors.get_enabled(i), |ui| {
-                        ui.add(egui::DragValue::new(settings.colors.get_u_mut(i)).speed(0.005).clamp_range(0f32..=1f32).prefix("Elevation:"));
-                        ui.add(egui::DragValue::new(settings.colors.get_v_mut(i)).speed(0.005).clamp_range(0f32..=1f32).prefix("Steepness:"));
-                        egui::color_picker::color_edit_button_rgb(ui, settings.colors.get_col_mut(i));
+                    ui.add(egui::Checkbox::without_text(gradient.get_enabled_mut(i)));
+                    ui.add_enabled_ui(*gradient.get_enabled(i), |ui| {
+                        ui.add(egui::DragValue::new(gradient.get_u_mut(i)).speed(0.005).clamp_range(0f32..=1f32).prefix(format!("{}:", key_axis)));
+                        ui.add(egui::DragValue::new(gradient.get_v_mut(i)).speed(0.005).clamp_range(0f32..=1f32).prefix("Steepness:"));
+                        if latitude_axis {
+                            ui.add(egui::DragValue::new(gradient.get_latitude_mut(i)).speed(0.005).clamp_range(0f32..=1f32).prefix("Latitude:"));
+                        }
+                        egui::color_picker::color_edit_button_rgb(ui, gradient.get_col_mut(i));
+                        egui::ComboBox::from_id_source(format!("key_easing_{}", i))
+                            .selected_text(old_easing.name())
+                            .show_ui(ui, |ui| {
+                                for easing in KeyEasing::ALL {
+                                    ui.selectable_value(gradient.get_easing_mut(i), easing, easing.name());
+                                }
+                            });
                     });
                 });
             });
-            changed = changed || (old != *settings.colors.get(i));
+            changed = changed || (old != *gradient.get(i)) || old_latitude != gradient.get_latitude(i) || old_easing != gradient.get_easing(i);
         }
+
+        ui.separator();
+
+        ui.collapsing("Import / Export", |ui| {
+            ui.horizontal(|ui| {
+                ui.label("File:");
+                ui.text_edit_singleline(&mut gradient_file.path);
+            });
+            ui.horizontal(|ui| {
+                ui.label("Strip Image Keys:");
+                ui.add(egui::DragValue::new(&mut gradient_file.strip_keys).clamp_range(2..=64));
+            });
+            ui.horizontal(|ui| {
+                if ui.button("Import").clicked() {
+                    match import_gradient(Path::new(&gradient_file.path), gradient_file.strip_keys) {
+                        Ok(imported) => {
+                            gradient_file.status = Some(format!("Imported {} keys", imported.len()));
+                            *gradient = imported;
+                            changed = true;
+                        }
+                        Err(err) => gradient_file.status = Some(format!("Failed to import gradient: {}", err)),
+                    }
+                }
+                if ui.button("Export").clicked() {
+                    gradient_file.status = Some(match export_gradient(gradient, Path::new(&gradient_file.path)) {
+                        Ok(()) => format!("Exported to {}", gradient_file.path),
+                        Err(err) => format!("Failed to export gradient: {}", err),
+                    });
+                }
+            });
+            if let Some(status) = &gradient_file.status {
+                ui.label(status);
+            }
+        });
+
+        ui.separator();
+
+        if changed || preview.planet != Some(planet_entity) || *edited != old_edited {
+            preview.planet = Some(planet_entity);
+            preview.lut = None;
+            preview.texture = None;
+        }
+
+        ui.collapsing("Lookup Preview", |ui| {
+            let preview = &mut *preview;
+            let lut = preview.lut.get_or_insert_with(|| gradient.bake());
+
+            let depth = lut.size.z;
+            preview.latitude = preview.latitude.min(depth - 1);
+            if depth > 1 {
+                ui.horizontal(|ui| {
+                    ui.label("Latitude:");
+                    let slider = egui::Slider::new(&mut preview.latitude, 0..=depth - 1)
+                        .custom_formatter(|z, _| format!("{:.0}°", ColorLut::coordinate(z as u32, depth) * 90.0));
+                    if ui.add(slider).changed() {
+                        preview.texture = None;
+                    }
+                });
+            }
+
+            let texture = preview.texture.get_or_insert_with(|| {
+                ui.ctx().load_texture("color_lut_preview", preview_image(lut, preview.latitude), egui::TextureOptions::LINEAR)
+            });
+            let response = ui.image((texture.id(), egui::vec2(256.0, 256.0)));
+            ui.label(format!("{} increases to the right and steepness upwards.", key_axis));
+            if let Some(pointer) = response.hover_pos() {
+                let position = (pointer - response.rect.min) / response.rect.size();
+                ui.label(format!("{}: {:.2}, Steepness: {:.2}", key_axis, position.x, 1.0 - position.y));
+            }
+        });
+
+        settings.num_colors = settings.colors.len();
     });
 
     if changed {
-        update_planet_mats_evw.send(UpdatePlanetMaterials {});
+        update_planet_mats_evw.send(UpdatePlanetMaterials { planet: planet_entity });
     }
 }
\ No newline at end of file
@@ -0,0 +1,209 @@
+use bevy::{prelude::*, tasks::{AsyncComputeTaskPool, Task}};
+use bevy_egui::{egui, EguiContexts};
+use futures_lite::future;
+
+use crate::{gen::{shape::ShapeGenerator, heightmap::HeightmapProjection}, render::planet::Planet, export::{mesh::{MeshFormat, MeshExportOptions, export_planet_mesh}, heightmap::{HeightmapFormat, export_heightmap}, bake::{NormalSpace, BakeOptions, export_textures}}};
+
+use super::{render::{UiVisibility, UiRenderSettings}, color::UiColorSettings, planets::SelectedPlanet};
+
+
+#[derive(Resource)]
+pub struct UiExportSettings {
+    pub path: String,
+    pub mesh_format: MeshFormat,
+    pub mesh_resolution: u32,
+    pub include_ocean: bool,
+    pub heightmap_projection: HeightmapProjection,
+    pub heightmap_format: HeightmapFormat,
+    pub heightmap_resolution: u32,
+    pub texture_projection: HeightmapProjection,
+    pub texture_resolution: u32,
+    pub normal_space: NormalSpace,
+    pub status: Option<String>,
+}
+
+impl Default for UiExportSettings {
+    fn default() -> Self {
+        Self {
+            path: String::from("planet"),
+            mesh_format: MeshFormat::default(),
+            mesh_resolution: 256,
+            include_ocean: true,
+            heightmap_projection: HeightmapProjection::default(),
+            heightmap_format: HeightmapFormat::default(),
+            heightmap_resolution: 1024,
+            texture_projection: HeightmapProjection::default(),
+            texture_resolution: 1024,
+            normal_space: NormalSpace::default(),
+            status: None,
+        }
+    }
+}
+
+
+/// Builds and writes meshes, heightmaps and textures in the background, since a high resolution
+/// planet takes a while. Each task finishes with the status to show.
+#[allow(clippy::too_many_arguments)]
+pub fn export_settings(
+    mut contexts: EguiContexts,
+    mut settings: ResMut<UiExportSettings>,
+    ui_visibility: Res<UiVisibility>,
+    planets: Query<(&Planet, &ShapeGenerator, &UiColorSettings, &UiRenderSettings)>,
+    selected_planet: Res<SelectedPlanet>,
+    mut mesh_export: Local<Option<Task<String>>>,
+    mut heightmap_export: Local<Option<Task<String>>>,
+    mut texture_bake: Local<Option<Task<String>>>,
+) {
+    for export in [&mut *mesh_export, &mut *heightmap_export, &mut *texture_bake] {
+        if let Some(task) = export.as_mut() {
+            if let Some(status) = future::block_on(future::poll_once(task)) {
+                settings.status = Some(status);
+                *export = None;
+            }
+        }
+    }
+
+    if *ui_visibility != UiVisibility::Visible { return };
+
+    let Some(planet_entity) = selected_planet.0 else { return };
+    let Ok((planet, shape_gen, colors, render_settings)) = planets.get(planet_entity) else { return };
+
+    egui::Window::new("Export").default_open(false).show(contexts.ctx_mut(), |ui| {
+        ui.horizontal(|ui| {
+            ui.label("File Name:");
+            ui.text_edit_singleline(&mut settings.path);
+        });
+
+        ui.separator();
+
+        ui.horizontal(|ui| {
+            ui.label("Mesh Format:");
+            egui::ComboBox::from_id_source("mesh_format")
+                .selected_text(settings.mesh_format.name())
+                .show_ui(ui, |ui| {
+                    for format in MeshFormat::ALL {
+                        ui.selectable_value(&mut settings.mesh_format, format, format.name());
+                    }
+                });
+        });
+        ui.horizontal(|ui| {
+            ui.label("Mesh Resolution:");
+            ui.add(egui::DragValue::new(&mut settings.mesh_resolution).clamp_range(2..=2048));
+        });
+        ui.add(egui::Checkbox::new(&mut settings.include_ocean, "Include Ocean"));
+
+        if ui.add_enabled(mesh_export.is_none(), egui::Button::new("Export Mesh")).clicked() {
+            let options = MeshExportOptions {
+                resolution: settings.mesh_resolution,
+                topology: planet.topology,
+                mapping: planet.mapping,
+                ocean: settings.include_ocean.then_some((render_settings.ocean_radius, render_settings.ocean_color_1)),
+            };
+            let path = format!("exports/{}.{}", settings.path, settings.mesh_format.extension());
+            let (format, shape_gen, colors) = (settings.mesh_format, shape_gen.clone(), colors.clone());
+
+            settings.status = Some(format!("Exporting {}...", path));
+            *mesh_export = Some(AsyncComputeTaskPool::get().spawn(async move {
+                let result = std::fs::create_dir_all("exports")
+                    .and_then(|_| export_planet_mesh(&path, format, &shape_gen, &colors, &options));
+                match result {
+                    Ok(()) => format!("Exported {}", path),
+                    Err(err) => format!("Export failed: {}", err),
+                }
+            }));
+        }
+
+        ui.separator();
+
+        ui.horizontal(|ui| {
+            ui.label("Heightmap Projection:");
+            egui::ComboBox::from_id_source("heightmap_projection")
+                .selected_text(settings.heightmap_projection.name())
+                .show_ui(ui, |ui| {
+                    for projection in HeightmapProjection::ALL {
+                        ui.selectable_value(&mut settings.heightmap_projection, projection, projection.name());
+                    }
+                });
+        });
+        ui.horizontal(|ui| {
+            ui.label("Heightmap Format:");
+            egui::ComboBox::from_id_source("heightmap_format")
+                .selected_text(settings.heightmap_format.name())
+                .show_ui(ui, |ui| {
+                    for format in HeightmapFormat::ALL {
+                        ui.selectable_value(&mut settings.heightmap_format, format, format.name());
+                    }
+                });
+        });
+        ui.horizontal(|ui| {
+            ui.label("Heightmap Resolution:");
+            ui.add(egui::DragValue::new(&mut settings.heightmap_resolution).clamp_range(16..=8192));
+        });
+
+        if ui.add_enabled(heightmap_export.is_none(), egui::Button::new("Export Heightmap")).clicked() {
+            let path = format!("exports/{}", settings.path);
+            let (projection, format, resolution, shape_gen) = (settings.heightmap_projection, settings.heightmap_format, settings.heightmap_resolution, shape_gen.clone());
+
+            settings.status = Some(format!("Exporting {}...", path));
+            *heightmap_export = Some(AsyncComputeTaskPool::get().spawn(async move {
+                let result = std::fs::create_dir_all("exports")
+                    .and_then(|_| export_heightmap(&path, projection, format, resolution, &shape_gen));
+                match result {
+                    Ok(metadata) => format!("Exported {} ({:.3} to {:.3})", path, metadata.min_elevation, metadata.max_elevation),
+                    Err(err) => format!("Export failed: {}", err),
+                }
+            }));
+        }
+
+        ui.separator();
+
+        ui.horizontal(|ui| {
+            ui.label("Texture Projection:");
+            egui::ComboBox::from_id_source("texture_projection")
+                .selected_text(settings.texture_projection.name())
+                .show_ui(ui, |ui| {
+                    for projection in HeightmapProjection::ALL {
+                        ui.selectable_value(&mut settings.texture_projection, projection, projection.name());
+                    }
+                });
+        });
+        ui.horizontal(|ui| {
+            ui.label("Normals:");
+            egui::ComboBox::from_id_source("normal_space")
+                .selected_text(settings.normal_space.name())
+                .show_ui(ui, |ui| {
+                    for normal_space in NormalSpace::ALL {
+                        ui.selectable_value(&mut settings.normal_space, normal_space, normal_space.name());
+                    }
+                });
+        });
+        ui.horizontal(|ui| {
+            ui.label("Texture Resolution:");
+            ui.add(egui::DragValue::new(&mut settings.texture_resolution).clamp_range(16..=8192));
+        });
+
+        if ui.add_enabled(texture_bake.is_none(), egui::Button::new("Bake Textures")).clicked() {
+            let options = BakeOptions {
+                projection: settings.texture_projection,
+                resolution: settings.texture_resolution,
+                normal_space: settings.normal_space,
+            };
+            let path = format!("exports/{}", settings.path);
+            let (shape_gen, colors) = (shape_gen.clone(), colors.clone());
+
+            settings.status = Some(format!("Baking textures to {}...", path));
+            *texture_bake = Some(AsyncComputeTaskPool::get().spawn(async move {
+                let result = std::fs::create_dir_all("exports")
+                    .and_then(|_| export_textures(&path, &shape_gen, &colors, &options));
+                match result {
+                    Ok(()) => format!("Baked textures to {}", path),
+                    Err(err) => format!("Bake failed: {}", err),
+                }
+            }));
+        }
+
+        if let Some(status) = &settings.status {
+            ui.label(status);
+        }
+    });
+}
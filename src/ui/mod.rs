@@ -4,16 +4,33 @@ pub mod color;
 pub mod render;
 pub mod save;
 pub mod controller;
+pub mod export;
+pub mod planets;
+pub mod collision;
+pub mod picking;
+pub mod sculpt;
+pub mod scatter;
+pub mod splat;
+pub mod clouds;
 
 use bevy::prelude::*;
-use bevy_panorbit_camera::PanOrbitCameraPlugin;
+use bevy_panorbit_camera::{PanOrbitCameraPlugin, PanOrbitCameraSystemSet};
 use super::controller::FpsControllerPlugin;
+use crate::render::planet::{generate_mesh, generate_materials};
 
 use bevy_rapier3d::prelude::{RapierPhysicsPlugin, NoUserData};
 use camera::*;
 use shape::*;
 use color::*;
 use render::*;
+use export::*;
+use planets::*;
+use collision::*;
+use picking::*;
+use sculpt::*;
+use scatter::*;
+use splat::*;
+use clouds::*;
 
 
 pub struct UIPlugin;
@@ -21,23 +38,39 @@ pub struct UIPlugin;
 impl Plugin for UIPlugin {
     fn build(&self, app: &mut App) {
         app
-            .init_resource::<UiRenderSettings>()
-            .init_resource::<UiColorSettings>()
+            .init_resource::<UiSceneSettings>()
+            .init_resource::<SelectedPlanet>()
             .init_resource::<UiVisibility>()
+            .init_resource::<UiExportSettings>()
             .init_resource::<CameraMode>()
+            .init_resource::<PlanetCollisionSettings>()
+            .init_resource::<UiSculptSettings>()
             .add_plugins(PanOrbitCameraPlugin)
             .add_plugins(RapierPhysicsPlugin::<NoUserData>::default())
             .add_plugins(FpsControllerPlugin)
             .add_systems(Startup, spawn_camera)
+            .add_systems(PreUpdate, update_selected_planet)
             .add_systems(Update, (
                 update_camera_mode,
+                update_planet_colliders,
                 camera_cursor_grab,
                 update_camera_local_up,
+                follow_planet_rotation,
+                focus_selected_planet,
 
                 render_settings,
                 shape_settings,
                 color_settings,
+                export_settings,
+                surface_picking,
+                sculpt_settings,
+                scatter_settings,
+                terrain_layer_settings,
+                cloud_settings,
             ))
+            .add_systems(Update, sculpt_brush.before(PanOrbitCameraSystemSet))
+            // a new planet only exists once this frame's commands are applied, so its first updates are read next frame
+            .add_systems(Update, planet_settings.after(generate_mesh).after(generate_materials))
         ;
     }
 }
\ No newline at end of file
@@ -0,0 +1,149 @@
+use bevy::{prelude::*, window::PrimaryWindow};
+use bevy_egui::{egui, EguiContexts};
+
+use crate::{render::planet::Planet, gen::shape::{ShapeGenerator, point_to_lat_long}};
+
+use super::{camera::CameraMode, render::UiVisibility};
+
+
+/// Steps taken along the part of the ray inside a planet's bounds before refining a hit.
+const MARCH_STEPS: usize = 256;
+const REFINE_STEPS: usize = 16;
+
+/// Distance along `ray` to where it first meets the displaced surface, with the ray in the planet's
+/// local space. Marches between the sphere of the highest terrain and the far side of it, then
+/// bisects the step that crossed the surface.
+fn intersect_surface(ray: Ray, shape_gen: &ShapeGenerator, bound_radius: f32) -> Option<f32> {
+    // ray-sphere intersection against the bounds, the direction is normalized
+    let b = ray.origin.dot(ray.direction);
+    let c = ray.origin.length_squared() - bound_radius * bound_radius;
+    let discriminant = b * b - c;
+    if discriminant < 0.0 { return None };
+    let t_far = -b + discriminant.sqrt();
+    let t_near = (-b - discriminant.sqrt()).max(0.0);
+    if t_far <= t_near { return None };
+
+    let height_above_surface = |t: f32| {
+        let point = ray.get_point(t);
+        match point.try_normalize() {
+            Some(direction) => point.length() - shape_gen.get_elevation(direction),
+            None => -1.0,
+        }
+    };
+
+    let step = (t_far - t_near) / MARCH_STEPS as f32;
+    let mut t_prev = t_near;
+    if height_above_surface(t_prev) <= 0.0 { return Some(t_prev) };
+
+    for i in 1..=MARCH_STEPS {
+        let t = t_near + step * i as f32;
+        if height_above_surface(t) <= 0.0 {
+            let (mut above, mut below) = (t_prev, t);
+            for _ in 0..REFINE_STEPS {
+                let mid = (above + below) * 0.5;
+                if height_above_surface(mid) > 0.0 { above = mid } else { below = mid }
+            }
+            return Some((above + below) * 0.5);
+        }
+        t_prev = t;
+    }
+    None
+}
+
+/// The world space ray under the cursor from the active camera.
+pub fn cursor_ray(
+    primary_window: &Query<&Window, With<PrimaryWindow>>,
+    cameras: &Query<(&Camera, &GlobalTransform)>,
+) -> Option<Ray> {
+    let cursor_position = primary_window.get_single().ok()?.cursor_position()?;
+    let (camera, camera_transform) = cameras.iter().find(|(camera, _)| camera.is_active)?;
+    camera.viewport_to_world(camera_transform, cursor_position)
+}
+
+/// The planet whose surface `ray` hits first, along with the hit's direction from that planet's
+/// centre in its local space.
+pub fn pick_planet<'a, T>(
+    ray: Ray,
+    planets: impl IntoIterator<Item = (T, &'a Planet, &'a ShapeGenerator, &'a GlobalTransform)>,
+) -> Option<(T, Vec3)> {
+    let mut closest: Option<(f32, T, Vec3)> = None;
+    for (item, planet, shape_gen, planet_transform) in planets {
+        let to_local = planet_transform.affine().inverse();
+        let local_origin = to_local.transform_point3(ray.origin);
+        let Some(local_direction) = to_local.transform_vector3(ray.direction).try_normalize() else { continue };
+        let local_ray = Ray { origin: local_origin, direction: local_direction };
+
+        // the extents are only known once a mesh has been built, so fall back to a generous bound
+        let bound_radius = if planet.max_elevation > planet.min_elevation { planet.max_elevation * 1.01 } else { shape_gen.radius * 2.0 };
+        let Some(t) = intersect_surface(local_ray, shape_gen, bound_radius) else { continue };
+
+        let local_point = local_ray.get_point(t);
+        let distance = planet_transform.transform_point(local_point).distance(ray.origin);
+        if !closest.as_ref().is_some_and(|(closest_distance, ..)| *closest_distance <= distance) {
+            closest = Some((distance, item, local_point.normalize()));
+        }
+    }
+    closest.map(|(_, item, direction)| (item, direction))
+}
+
+/// Shows what's under the cursor in Edit mode: where it is, how high, how steep, and how much each
+/// noise layer adds there.
+#[allow(clippy::too_many_arguments)]
+pub fn surface_picking(
+    mut contexts: EguiContexts,
+    camera_mode: Res<CameraMode>,
+    ui_visibility: Res<UiVisibility>,
+    mouse_buttons: Res<Input<MouseButton>>,
+    primary_window: Query<&Window, With<PrimaryWindow>>,
+    cameras: Query<(&Camera, &GlobalTransform)>,
+    planets: Query<(&Name, &Planet, &ShapeGenerator, &GlobalTransform)>,
+) {
+    if *camera_mode != CameraMode::Edit || *ui_visibility != UiVisibility::Visible { return };
+    // orbiting drags the cursor across the surface, where the tooltip only gets in the way
+    if mouse_buttons.any_pressed([MouseButton::Left, MouseButton::Right, MouseButton::Middle]) { return };
+
+    let ctx = contexts.ctx_mut();
+    if ctx.is_pointer_over_area() { return };
+
+    let Some(ray) = cursor_ray(&primary_window, &cameras) else { return };
+    let Some(((name, planet, shape_gen), direction)) = pick_planet(ray, planets.iter().map(|(name, planet, shape_gen, transform)| ((name, planet, shape_gen), planet, shape_gen, transform))) else { return };
+
+    let elevation = shape_gen.get_elevation(direction);
+    let normal = shape_gen.get_normal(direction, 0.0001);
+    let slope = normal.dot(direction).clamp(-1.0, 1.0).acos().to_degrees();
+    let (latitude, longitude) = point_to_lat_long(direction);
+    let (latitude, longitude) = (latitude.to_degrees(), longitude.to_degrees());
+    let contributions = shape_gen.get_layer_contributions(direction);
+
+    egui::show_tooltip_at_pointer(ctx, egui::Id::new("surface_picking"), |ui| {
+        ui.strong(name.as_str());
+        ui.label(format!(
+            "{:.3}°{} {:.3}°{}",
+            latitude.abs(), if latitude >= 0.0 { "N" } else { "S" },
+            longitude.abs(), if longitude >= 0.0 { "E" } else { "W" },
+        ));
+
+        ui.separator();
+
+        ui.label(format!("Elevation: {:.5}", elevation));
+        if planet.max_elevation > planet.min_elevation {
+            ui.label(format!("Normalized: {:.3}", (elevation - planet.min_elevation) / (planet.max_elevation - planet.min_elevation)));
+        }
+        ui.label(format!("Slope: {:.1}°", slope));
+        if elevation < shape_gen.sea_level {
+            ui.label(format!("Depth: {:.5}", shape_gen.sea_level - elevation));
+        } else {
+            ui.label(format!("Above Sea Level: {:.5}", elevation - shape_gen.sea_level));
+        }
+
+        ui.separator();
+
+        for (i, (layer, contribution)) in shape_gen.noise_layers.iter().zip(contributions.iter()).enumerate().take(shape_gen.num_layers as usize) {
+            let note = if !layer.enabled { " (disabled)" } else if layer.is_warp { " (warp)" } else { "" };
+            ui.label(format!("Layer {}: {:+.5}{}", i + 1, contribution, note));
+        }
+        if !shape_gen.sculpt.is_empty() {
+            ui.label(format!("Sculpted: {:+.5}", shape_gen.sculpt.sample(direction) * shape_gen.radius));
+        }
+    });
+}
@@ -0,0 +1,86 @@
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+
+use crate::render::{planet::{Planet, PlanetBundle, UpdatePlanetMesh, UpdatePlanetMaterials, spawn_planet}, planet_mat::PlanetMaterial, ocean::OceanMaterial};
+
+use super::render::UiVisibility;
+
+
+/// The planet the settings windows edit.
+#[derive(Resource, Default)]
+pub struct SelectedPlanet(pub Option<Entity>);
+
+/// Falls back to any remaining planet when nothing is selected or the selection was removed.
+pub fn update_selected_planet(
+    mut selected: ResMut<SelectedPlanet>,
+    planets: Query<Entity, With<Planet>>,
+) {
+    if selected.0.is_some_and(|entity| planets.contains(entity)) { return };
+    selected.0 = planets.iter().min();
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn planet_settings(
+    mut commands: Commands,
+    mut contexts: EguiContexts,
+    mut selected: ResMut<SelectedPlanet>,
+    mut planets: Query<(Entity, &mut Name, &mut Transform), With<Planet>>,
+    mut materials: ResMut<Assets<PlanetMaterial>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut ocean_materials: ResMut<Assets<OceanMaterial>>,
+    mut update_planet_mesh_evw: EventWriter<UpdatePlanetMesh>,
+    mut update_planet_materials_evw: EventWriter<UpdatePlanetMaterials>,
+    ui_visibility: Res<UiVisibility>,
+) {
+    if *ui_visibility != UiVisibility::Visible { return };
+
+    egui::Window::new("Planets").show(contexts.ctx_mut(), |ui| {
+        let mut entries: Vec<(Entity, String)> = planets.iter().map(|(entity, name, _)| (entity, name.to_string())).collect();
+        entries.sort_by_key(|(entity, _)| *entity);
+
+        for (entity, name) in entries.iter() {
+            if ui.selectable_label(selected.0 == Some(*entity), name).clicked() {
+                selected.0 = Some(*entity);
+            }
+        }
+
+        ui.horizontal(|ui| {
+            if ui.button("Add Planet").clicked() {
+                // line new planets up past the furthest one so they don't overlap
+                let x = planets.iter().map(|(_, _, transform)| transform.translation.x).fold(-5.0, f32::max) + 5.0;
+                let bundle = PlanetBundle::new(format!("Planet {}", entries.len() + 1), Vec3::new(x, 0.0, 0.0));
+                let planet = spawn_planet(&mut commands, &mut materials, &mut meshes, &mut ocean_materials, bundle);
+                update_planet_mesh_evw.send(UpdatePlanetMesh { planet });
+                update_planet_materials_evw.send(UpdatePlanetMaterials { planet });
+                selected.0 = Some(planet);
+            }
+
+            let can_remove = entries.len() > 1;
+            if ui.add_enabled(can_remove, egui::Button::new("Remove Planet")).clicked() {
+                if let Some(entity) = selected.0.take() {
+                    commands.entity(entity).despawn_recursive();
+                }
+            }
+        });
+
+        let Some(planet_entity) = selected.0 else { return };
+        let Ok((_, mut name, mut transform)) = planets.get_mut(planet_entity) else { return };
+
+        ui.separator();
+
+        ui.horizontal(|ui| {
+            ui.label("Name:");
+            let mut new_name = name.to_string();
+            if ui.text_edit_singleline(&mut new_name).changed() {
+                name.set(new_name);
+            }
+        });
+
+        ui.horizontal(|ui| {
+            ui.label("Position:");
+            ui.add(egui::DragValue::new(&mut transform.translation.x).prefix("X: ").min_decimals(2).speed(0.025));
+            ui.add(egui::DragValue::new(&mut transform.translation.y).prefix("Y: ").min_decimals(2).speed(0.025));
+            ui.add(egui::DragValue::new(&mut transform.translation.z).prefix("Z: ").min_decimals(2).speed(0.025));
+        });
+    });
+}
@@ -2,9 +2,9 @@ use bevy::{prelude::*, pbr::wireframe::WireframeConfig};
 use bevy_egui::{egui, EguiContexts};
 use serde::{Serialize, Deserialize};
 
-use crate::{render::planet::{UpdatePlanetMesh, Planet, UpdatePlanetMaterials}, gen::shape::ShapeGenerator};
+use crate::{render::{atmosphere::AtmospherePreset, planet::{UpdatePlanetMesh, Planet, UpdatePlanetMaterials, CubeSphereMapping, PlanetTopology}, orbit::{PlanetOrbit, SimClock}, scatter::PlanetScatter, splat::PlanetTerrainLayers, clouds::PlanetClouds}, gen::shape::ShapeGenerator};
 
-use super::{save::{SaveState, restore_save}, color::UiColorSettings, camera::CameraMode};
+use super::{save::{SaveState, restore_save}, color::UiColorSettings, camera::CameraMode, collision::PlanetCollisionSettings, planets::SelectedPlanet};
 
 #[derive(Resource, Default, PartialEq)]
 pub enum UiVisibility {
@@ -14,10 +14,22 @@ pub enum UiVisibility {
 }
 
 
-#[derive(Resource, Serialize, Deserialize, Clone)]
+/// Settings shared by every planet in the scene.
+#[derive(Resource, Default)]
+pub struct UiSceneSettings {
+    pub load_path: String,
+    pub save_path: String,
+}
+
+/// Per-planet render settings, stored on the planet entity.
+#[derive(Component, Serialize, Deserialize, Clone)]
+#[serde(default)]
 pub struct UiRenderSettings {
     pub planet_resolution: u32,
-    pub light_euler_rot: Vec3,
+    pub planet_max_lod_depth: u32,
+    pub planet_lod_error: f32,
+    pub planet_mapping: CubeSphereMapping,
+    pub planet_topology: PlanetTopology,
 
     pub ocean_radius: f32,
     pub ocean_depth_mul: f32,
@@ -32,6 +44,25 @@ pub struct UiRenderSettings {
     pub atmosphere_density_falloff: f32,
     pub atmosphere_scatter_strength: f32,
     pub atmosphere_scatter_coeffs: [f32; 3],
+    /// Scattering by haze and dust, which is brightest looking towards the sun.
+    pub atmosphere_mie_strength: f32,
+    pub atmosphere_mie_color: [f32; 3],
+    pub atmosphere_mie_falloff: f32,
+    /// How much of the light Mie scattering sends forwards, from -1 (all back) to 1 (all forwards).
+    pub atmosphere_mie_anisotropy: f32,
+    /// Light the haze absorbs as well as scatters, as a fraction of its strength.
+    pub atmosphere_mie_absorption: f32,
+    pub atmosphere_ozone_absorption: [f32; 3],
+    /// Height of the middle of the ozone layer, as a fraction of the way from sea level to the top
+    /// of the atmosphere.
+    pub atmosphere_ozone_height: f32,
+    pub atmosphere_ozone_width: f32,
+    /// Apparent diameter of the sun, in degrees.
+    pub sun_disk_size: f32,
+    pub sun_disk_intensity: f32,
+    pub sun_limb_darkening: f32,
+    /// How strongly the sky lights the ground, on top of the scene's ambient light.
+    pub sky_ambient_strength: f32,
 
     pub waves_normal_map_1: u32,
     pub waves_normal_map_2: u32,
@@ -42,18 +73,17 @@ pub struct UiRenderSettings {
     pub surface_normal_map: u32,
     pub surface_strength: f32,
     pub surface_scale: f32,
-
-    #[serde(skip)]
-    pub load_path: String,
-    #[serde(skip)]
-    pub save_path: String,
+    pub surface_roughness: f32,
 }
 
 impl Default for UiRenderSettings {
     fn default() -> Self {
         Self {
             planet_resolution: 10,
-            light_euler_rot: Vec3::ZERO,
+            planet_max_lod_depth: 8,
+            planet_lod_error: 8.0,
+            planet_mapping: CubeSphereMapping::default(),
+            planet_topology: PlanetTopology::default(),
 
             ocean_radius: 1.0,
             ocean_depth_mul: 1.0,
@@ -64,10 +94,22 @@ impl Default for UiRenderSettings {
 
             atmosphere_radius: 1.5,
             atmosphere_sample_points: 10,
-            atmosphere_optical_depth_points: 10,
+            atmosphere_optical_depth_points: 40,
             atmosphere_density_falloff: 4.0,
             atmosphere_scatter_strength: 20.0,
             atmosphere_scatter_coeffs: [700.0, 530.0, 440.0],
+            atmosphere_mie_strength: 2.0,
+            atmosphere_mie_color: [1.0; 3],
+            atmosphere_mie_falloff: 20.0,
+            atmosphere_mie_anisotropy: 0.76,
+            atmosphere_mie_absorption: 0.1,
+            atmosphere_ozone_absorption: [0.3, 0.8, 0.04],
+            atmosphere_ozone_height: 0.25,
+            atmosphere_ozone_width: 0.15,
+            sun_disk_size: 1.0,
+            sun_disk_intensity: 2.0,
+            sun_limb_darkening: 0.6,
+            sky_ambient_strength: 0.2,
 
             wave_strength: 0.3,
             wave_scale: 2.0,
@@ -78,22 +120,22 @@ impl Default for UiRenderSettings {
             surface_normal_map: 1,
             surface_strength: 0.1,
             surface_scale: 1.0,
-
-            load_path: String::from(""),
-            save_path: String::from(""),
+            surface_roughness: 0.9,
         }
     }
 }
 
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
 pub fn render_settings(
     mut contexts: EguiContexts,
-    mut settings: ResMut<UiRenderSettings>,
-    mut planet: ResMut<Planet>,
+    mut scene_settings: ResMut<UiSceneSettings>,
+    mut planets: Query<(&mut Planet, &mut UiRenderSettings, &mut ShapeGenerator, &mut UiColorSettings, &mut PlanetOrbit, &mut PlanetScatter, &mut PlanetTerrainLayers, &mut PlanetClouds)>,
+    selected_planet: Res<SelectedPlanet>,
     mut update_planet_mesh_evw: EventWriter<UpdatePlanetMesh>,
     mut update_planet_materials_evw: EventWriter<UpdatePlanetMaterials>,
-    mut shape_gen: ResMut<ShapeGenerator>,
-    mut colors: ResMut<UiColorSettings>,
     mut camera_mode: ResMut<CameraMode>,
+    mut collision_settings: ResMut<PlanetCollisionSettings>,
+    mut clock: ResMut<SimClock>,
 
     time: Res<Time>,
     mut wireframe_config: ResMut<WireframeConfig>,
@@ -110,6 +152,9 @@ pub fn render_settings(
 
     if *ui_visibility != UiVisibility::Visible { return };
 
+    let Some(planet_entity) = selected_planet.0 else { return };
+    let Ok((mut planet, mut settings, mut shape_gen, mut colors, mut orbit, mut scatter, mut terrain_layers, mut clouds)) = planets.get_mut(planet_entity) else { return };
+
     egui::Window::new("Settings").show(contexts.ctx_mut(), |ui| {
         let mut fps_value = last_fps_update.0;
         if last_fps_update.1 > 0.25 {
@@ -125,16 +170,16 @@ pub fn render_settings(
 
         ui.horizontal(|ui| {
             if ui.button("Load Planet:").clicked() {
-                if let Ok(file_contents) = std::fs::read(format!("assets/saves/{}.ron", settings.load_path)) {
+                if let Ok(file_contents) = std::fs::read(format!("assets/saves/{}.ron", scene_settings.load_path)) {
                     let deserialized: SaveState = ron::de::from_bytes(&file_contents).unwrap();
-                    restore_save(deserialized, settings.as_mut(), shape_gen.as_mut(), colors.as_mut());
+                    restore_save(deserialized, settings.as_mut(), shape_gen.as_mut(), colors.as_mut(), orbit.as_mut(), scatter.as_mut(), terrain_layers.as_mut(), clouds.as_mut());
 
-                    planet.resolution = settings.planet_resolution;
-                    update_planet_mesh_evw.send(UpdatePlanetMesh {});
-                    update_planet_materials_evw.send(UpdatePlanetMaterials {});
+                    planet.apply_settings(&settings);
+                    update_planet_mesh_evw.send(UpdatePlanetMesh { planet: planet_entity });
+                    update_planet_materials_evw.send(UpdatePlanetMaterials { planet: planet_entity });
                 }
             }
-            ui.text_edit_singleline(&mut settings.load_path);
+            ui.text_edit_singleline(&mut scene_settings.load_path);
         });
         ui.horizontal(|ui| {
             if ui.button("Save Planet:").clicked() {
@@ -142,12 +187,16 @@ pub fn render_settings(
                     shape_gen: shape_gen.clone(),
                     colors: colors.clone(),
                     settings: settings.clone(),
+                    orbit: orbit.clone(),
+                    scatter: scatter.clone(),
+                    terrain_layers: terrain_layers.clone(),
+                    clouds: clouds.clone(),
                 };
 
                 let serialized = ron::ser::to_string_pretty(&savestate, ron::ser::PrettyConfig::default()).unwrap();
-                std::fs::write(format!("assets/saves/{}.ron", settings.save_path), serialized).unwrap();
+                std::fs::write(format!("assets/saves/{}.ron", scene_settings.save_path), serialized).unwrap();
             }
-            ui.text_edit_singleline(&mut settings.save_path);
+            ui.text_edit_singleline(&mut scene_settings.save_path);
         });
 
         ui.separator();
@@ -157,15 +206,68 @@ pub fn render_settings(
             ui.selectable_value(camera_mode.as_mut(), CameraMode::Edit, "Edit");
             ui.selectable_value(camera_mode.as_mut(), CameraMode::Explore, "Explore");
         });
+        ui.horizontal(|ui| {
+            ui.label("Collision Tiles:");
+            ui.add(egui::widgets::DragValue::new(&mut collision_settings.tile_depth).prefix("Depth: ").clamp_range(0..=12));
+            ui.add(egui::widgets::DragValue::new(&mut collision_settings.resolution).prefix("Resolution: ").clamp_range(2..=256));
+        });
 
         ui.separator();
 
         ui.horizontal(|ui| {
-            ui.label("Mesh Resolution:");
+            ui.label("Chunk Resolution:");
             ui.add(egui::widgets::DragValue::new(&mut settings.planet_resolution).clamp_range(3..=512));
             if ui.button("Update").clicked() {
                 planet.resolution = settings.planet_resolution;
-                update_planet_mesh_evw.send(UpdatePlanetMesh {});
+                update_planet_mesh_evw.send(UpdatePlanetMesh { planet: planet_entity });
+            }
+        });
+
+        ui.horizontal(|ui| {
+            ui.label("Topology:");
+            let old = settings.planet_topology;
+            egui::ComboBox::from_id_source("planet_topology")
+                .selected_text(format!("{:?}", settings.planet_topology))
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut settings.planet_topology, PlanetTopology::CubeSphere, "CubeSphere");
+                    ui.selectable_value(&mut settings.planet_topology, PlanetTopology::Icosphere, "Icosphere");
+                });
+            if old != settings.planet_topology {
+                planet.topology = settings.planet_topology;
+            }
+        });
+
+        ui.horizontal(|ui| {
+            ui.label("Cube Mapping:");
+            let old = settings.planet_mapping;
+            egui::ComboBox::from_id_source("cube_mapping")
+                .selected_text(format!("{:?}", settings.planet_mapping))
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut settings.planet_mapping, CubeSphereMapping::Normalized, "Normalized");
+                    ui.selectable_value(&mut settings.planet_mapping, CubeSphereMapping::Spherified, "Spherified");
+                    ui.selectable_value(&mut settings.planet_mapping, CubeSphereMapping::TangentAdjusted, "TangentAdjusted");
+                });
+            if old != settings.planet_mapping {
+                planet.mapping = settings.planet_mapping;
+                update_planet_mesh_evw.send(UpdatePlanetMesh { planet: planet_entity });
+            }
+        });
+
+        ui.horizontal(|ui| {
+            ui.label("Max LOD Depth:");
+            let old = settings.planet_max_lod_depth;
+            ui.add(egui::widgets::DragValue::new(&mut settings.planet_max_lod_depth).clamp_range(0..=16).speed(0.05));
+            if old != settings.planet_max_lod_depth {
+                planet.max_lod_depth = settings.planet_max_lod_depth;
+            }
+        });
+
+        ui.horizontal(|ui| {
+            ui.label("LOD Error (px):");
+            let old = settings.planet_lod_error;
+            ui.add(egui::widgets::DragValue::new(&mut settings.planet_lod_error).clamp_range(1f32..=64f32).min_decimals(1).speed(0.1));
+            if old != settings.planet_lod_error {
+                planet.lod_error_threshold = settings.planet_lod_error;
             }
         });
 
@@ -175,9 +277,70 @@ pub fn render_settings(
         });
 
         ui.horizontal(|ui| {
-            ui.label("Light Direction:");
-            ui.add(egui::DragValue::new(&mut settings.light_euler_rot.y).prefix("Pitch: ").clamp_range(0..=360).speed(2));
-            ui.add(egui::DragValue::new(&mut settings.light_euler_rot.x).prefix("Yaw: ").clamp_range(0..=360).speed(2));
+            ui.label("Time:");
+            if ui.button(if clock.paused { "Play" } else { "Pause" }).clicked() {
+                clock.paused = !clock.paused;
+            }
+            ui.add(egui::DragValue::new(&mut clock.speed).prefix("Speed: ").suffix("x").clamp_range(0f32..=100000f32).speed(0.1));
+            ui.add(egui::DragValue::new(&mut clock.time).suffix(" s").speed(1.0));
+        });
+
+        ui.horizontal(|ui| {
+            ui.label("Time of Day:");
+            let mut time_of_day = orbit.time_of_day(clock.time);
+            if ui.add(egui::Slider::new(&mut time_of_day, 0f32..=1f32)).changed() && orbit.rotation_period > 0.0 {
+                let period = orbit.rotation_period as f64;
+                clock.time = (clock.time / period).floor() * period + time_of_day as f64 * period;
+            }
+        });
+
+        ui.separator();
+
+        ui.collapsing("Rotation & Orbit", |ui| {
+            ui.horizontal(|ui| {
+                ui.label("Axial Tilt:");
+                ui.add(egui::DragValue::new(&mut orbit.axial_tilt).suffix("°").clamp_range(-180f32..=180f32).speed(0.25));
+            });
+
+            ui.horizontal(|ui| {
+                ui.label("Rotation Period:");
+                ui.add(egui::DragValue::new(&mut orbit.rotation_period).suffix(" s").clamp_range(0f32..=f32::MAX).speed(1.0));
+            });
+
+            ui.horizontal(|ui| {
+                ui.label("Orbital Period:");
+                ui.add(egui::DragValue::new(&mut orbit.orbital_period).suffix(" s").clamp_range(0f32..=f32::MAX).speed(10.0));
+            });
+
+            ui.horizontal(|ui| {
+                ui.label("Semi-Major Axis:");
+                ui.add(egui::DragValue::new(&mut orbit.semi_major_axis).clamp_range(0.01f32..=1000f32).min_decimals(2).speed(0.01));
+            });
+
+            ui.horizontal(|ui| {
+                ui.label("Eccentricity:");
+                ui.add(egui::DragValue::new(&mut orbit.eccentricity).clamp_range(0f32..=0.99f32).min_decimals(3).speed(0.001));
+            });
+
+            ui.horizontal(|ui| {
+                ui.label("Inclination:");
+                ui.add(egui::DragValue::new(&mut orbit.inclination).suffix("°").clamp_range(-180f32..=180f32).speed(0.25));
+            });
+
+            ui.horizontal(|ui| {
+                ui.label("Longitude of Ascending Node:");
+                ui.add(egui::DragValue::new(&mut orbit.longitude_of_ascending_node).suffix("°").clamp_range(0f32..=360f32).speed(0.5));
+            });
+
+            ui.horizontal(|ui| {
+                ui.label("Argument of Periapsis:");
+                ui.add(egui::DragValue::new(&mut orbit.argument_of_periapsis).suffix("°").clamp_range(0f32..=360f32).speed(0.5));
+            });
+
+            ui.horizontal(|ui| {
+                ui.label("Mean Anomaly at Epoch:");
+                ui.add(egui::DragValue::new(&mut orbit.mean_anomaly_at_epoch).suffix("°").clamp_range(0f32..=360f32).speed(0.5));
+            });
         });
 
         ui.separator();
@@ -188,7 +351,7 @@ pub fn render_settings(
                 ui.add(egui::DragValue::new(&mut settings.ocean_radius).speed(0.025).min_decimals(2).clamp_range(0f32..=100f32));
                 if shape_gen.sea_level != settings.ocean_radius {
                     shape_gen.sea_level = settings.ocean_radius;
-                    update_planet_mesh_evw.send(UpdatePlanetMesh {});
+                    update_planet_mesh_evw.send(UpdatePlanetMesh { planet: planet_entity });
                 }
             });
 
@@ -221,6 +384,15 @@ pub fn render_settings(
         ui.separator();
 
         ui.collapsing("Atmosphere", |ui| {
+            ui.horizontal(|ui| {
+                ui.label("Preset:");
+                for preset in AtmospherePreset::ALL {
+                    if ui.button(preset.name()).clicked() {
+                        preset.apply(&mut settings);
+                    }
+                }
+            });
+
             ui.horizontal(|ui| {
                 ui.label("Radius:");
                 ui.add(egui::DragValue::new(&mut settings.atmosphere_radius).speed(0.025).min_decimals(2).clamp_range(0f32..=100f32));
@@ -252,6 +424,76 @@ pub fn render_settings(
                 ui.add(egui::DragValue::new(&mut settings.atmosphere_scatter_coeffs[1]).speed(0.25).max_decimals(1).clamp_range(0f32..=1000f32).prefix("Green: "));
                 ui.add(egui::DragValue::new(&mut settings.atmosphere_scatter_coeffs[2]).speed(0.25).max_decimals(1).clamp_range(0f32..=1000f32).prefix("Blue: "));
             });
+
+            ui.separator();
+
+            ui.horizontal(|ui| {
+                ui.label("Mie Strength:");
+                ui.add(egui::DragValue::new(&mut settings.atmosphere_mie_strength).speed(0.025).min_decimals(2).clamp_range(0f32..=100f32));
+            });
+
+            ui.horizontal(|ui| {
+                ui.label("Mie Color:");
+                egui::color_picker::color_edit_button_rgb(ui, &mut settings.atmosphere_mie_color);
+            });
+
+            ui.horizontal(|ui| {
+                ui.label("Mie Falloff:");
+                ui.add(egui::DragValue::new(&mut settings.atmosphere_mie_falloff).speed(0.025).min_decimals(2).clamp_range(0f32..=100f32));
+            });
+
+            ui.horizontal(|ui| {
+                ui.label("Mie Anisotropy:");
+                ui.add(egui::DragValue::new(&mut settings.atmosphere_mie_anisotropy).speed(0.005).min_decimals(2).clamp_range(-0.99f32..=0.99f32));
+            });
+
+            ui.horizontal(|ui| {
+                ui.label("Mie Absorption:");
+                ui.add(egui::DragValue::new(&mut settings.atmosphere_mie_absorption).speed(0.005).min_decimals(2).clamp_range(0f32..=10f32));
+            });
+
+            ui.separator();
+
+            ui.label("Ozone Absorption:");
+            ui.indent(2, |ui| {
+                ui.add(egui::DragValue::new(&mut settings.atmosphere_ozone_absorption[0]).speed(0.005).min_decimals(2).clamp_range(0f32..=100f32).prefix("Red: "));
+                ui.add(egui::DragValue::new(&mut settings.atmosphere_ozone_absorption[1]).speed(0.005).min_decimals(2).clamp_range(0f32..=100f32).prefix("Green: "));
+                ui.add(egui::DragValue::new(&mut settings.atmosphere_ozone_absorption[2]).speed(0.005).min_decimals(2).clamp_range(0f32..=100f32).prefix("Blue: "));
+            });
+
+            ui.horizontal(|ui| {
+                ui.label("Ozone Height:");
+                ui.add(egui::DragValue::new(&mut settings.atmosphere_ozone_height).speed(0.005).min_decimals(2).clamp_range(0f32..=1f32));
+            });
+
+            ui.horizontal(|ui| {
+                ui.label("Ozone Width:");
+                ui.add(egui::DragValue::new(&mut settings.atmosphere_ozone_width).speed(0.005).min_decimals(2).clamp_range(0.01f32..=1f32));
+            });
+
+            ui.separator();
+
+            ui.horizontal(|ui| {
+                ui.label("Sun Disk Size:");
+                ui.add(egui::DragValue::new(&mut settings.sun_disk_size).speed(0.01).min_decimals(2).clamp_range(0f32..=30f32).suffix("°"));
+            });
+
+            ui.horizontal(|ui| {
+                ui.label("Sun Disk Intensity:");
+                ui.add(egui::DragValue::new(&mut settings.sun_disk_intensity).speed(0.01).min_decimals(2).clamp_range(0f32..=100f32));
+            });
+
+            ui.horizontal(|ui| {
+                ui.label("Limb Darkening:");
+                ui.add(egui::DragValue::new(&mut settings.sun_limb_darkening).speed(0.005).min_decimals(2).clamp_range(0f32..=1f32));
+            });
+
+            ui.separator();
+
+            ui.horizontal(|ui| {
+                ui.label("Sky Ambient Strength:");
+                ui.add(egui::DragValue::new(&mut settings.sky_ambient_strength).speed(0.005).min_decimals(2).clamp_range(0f32..=10f32));
+            });
         });
 
         ui.separator();
@@ -269,6 +511,7 @@ pub fn render_settings(
             ui.add(egui::DragValue::new(&mut settings.surface_normal_map).clamp_range(1..=5).max_decimals(0).speed(0.05).prefix("Surface Normal Map: "));
             ui.add(egui::DragValue::new(&mut settings.surface_strength).clamp_range(0f32..=1f32).min_decimals(2).speed(0.025).prefix("Surface Strength: "));
             ui.add(egui::DragValue::new(&mut settings.surface_scale).clamp_range(0f32..=100f32).min_decimals(2).speed(0.025).prefix("Surface Scale: "));
+            ui.add(egui::DragValue::new(&mut settings.surface_roughness).clamp_range(0f32..=1f32).min_decimals(2).speed(0.005).prefix("Surface Roughness: "));
         });
     });
 }
\ No newline at end of file
@@ -1,6 +1,7 @@
+use bevy::log::warn;
 use serde::{Serialize, Deserialize};
 
-use crate::gen::{shape::ShapeGenerator, noise::NoiseSimplex3d};
+use crate::{gen::{shape::ShapeGenerator, noise::NoiseSimplex3d, noise_filter::NoiseFilterType}, render::{orbit::PlanetOrbit, scatter::PlanetScatter, splat::PlanetTerrainLayers, clouds::PlanetClouds}};
 
 use super::{color::UiColorSettings, render::UiRenderSettings};
 
@@ -10,20 +11,42 @@ pub struct SaveState {
     pub shape_gen: ShapeGenerator,
     pub colors: UiColorSettings,
     pub settings: UiRenderSettings,
+    #[serde(default)]
+    pub orbit: PlanetOrbit,
+    #[serde(default)]
+    pub scatter: PlanetScatter,
+    #[serde(default)]
+    pub terrain_layers: PlanetTerrainLayers,
+    #[serde(default)]
+    pub clouds: PlanetClouds,
 }
 
 
+#[allow(clippy::too_many_arguments)]
 pub fn restore_save(
     save: SaveState,
     settings: &mut UiRenderSettings,
     shape_gen: &mut ShapeGenerator,
     colors: &mut UiColorSettings,
+    orbit: &mut PlanetOrbit,
+    scatter: &mut PlanetScatter,
+    terrain_layers: &mut PlanetTerrainLayers,
+    clouds: &mut PlanetClouds,
 ) {
     *settings = save.settings;
+    *orbit = save.orbit;
+    *scatter = save.scatter;
+    *terrain_layers = save.terrain_layers;
+    *clouds = save.clouds;
     *colors = save.colors;
     *shape_gen = save.shape_gen;
 
     for layer in shape_gen.noise_layers.iter_mut() {
         layer.filter.simplex_3d = NoiseSimplex3d::new(layer.filter.noise_seed);
+        if layer.filter.ty == NoiseFilterType::Heightmap {
+            if let Err(err) = layer.filter.load_heightmap() {
+                warn!("Failed to load heightmap {}: {}", layer.filter.heightmap_path, err);
+            }
+        }
     }
 }
\ No newline at end of file
@@ -0,0 +1,100 @@
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+
+use crate::render::scatter::{PlanetScatter, ScatterKind, ScatterLayer};
+
+use super::{render::UiVisibility, planets::SelectedPlanet};
+
+
+pub fn scatter_settings(
+    mut contexts: EguiContexts,
+    mut planets: Query<&mut PlanetScatter>,
+    selected_planet: Res<SelectedPlanet>,
+    ui_visibility: Res<UiVisibility>,
+) {
+    if *ui_visibility != UiVisibility::Visible { return };
+
+    let Some(planet_entity) = selected_planet.0 else { return };
+    let Ok(mut scatter) = planets.get_mut(planet_entity) else { return };
+
+    // edit a copy so the tiles are only rebuilt when something actually changes
+    let mut edited = scatter.clone();
+
+    egui::Window::new("Scatter").show(contexts.ctx_mut(), |ui| {
+        ui.horizontal(|ui| {
+            ui.label("Enabled:");
+            ui.add(egui::widgets::Checkbox::without_text(&mut edited.enabled));
+        });
+        ui.horizontal(|ui| {
+            ui.label("Seed:");
+            ui.add(egui::DragValue::new(&mut edited.seed));
+        });
+        ui.horizontal(|ui| {
+            ui.label("Range:");
+            ui.add(egui::DragValue::new(&mut edited.range).speed(0.005).min_decimals(3).clamp_range(0.0..=1.0));
+        });
+
+        ui.separator();
+
+        let mut removed = None;
+        for (i, layer) in edited.layers.iter_mut().enumerate() {
+            egui::containers::CollapsingHeader::new(format!("Layer {}: {}", i + 1, layer.kind.name())).id_source(i).show(ui, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("Kind:");
+                    for kind in ScatterKind::ALL {
+                        ui.selectable_value(&mut layer.kind, kind, kind.name());
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Enabled:");
+                    ui.add(egui::widgets::Checkbox::without_text(&mut layer.enabled));
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Density:");
+                    ui.add(egui::DragValue::new(&mut layer.density).clamp_range(0..=1024));
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Height:");
+                    ui.add(egui::DragValue::new(&mut layer.min_height).prefix("Min: ").speed(0.005).min_decimals(2).clamp_range(0.0..=1.0));
+                    ui.add(egui::DragValue::new(&mut layer.max_height).prefix("Max: ").speed(0.005).min_decimals(2).clamp_range(0.0..=1.0));
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Max Slope:");
+                    ui.add(egui::Slider::new(&mut layer.max_slope, 0.0..=90.0).suffix("°"));
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Max Latitude:");
+                    ui.add(egui::Slider::new(&mut layer.max_latitude, 0.0..=90.0).suffix("°"));
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Noise:");
+                    ui.add(egui::DragValue::new(&mut layer.noise_scale).prefix("Scale: ").speed(0.1).min_decimals(1).clamp_range(0.0..=1000.0));
+                    ui.add(egui::DragValue::new(&mut layer.noise_threshold).prefix("Threshold: ").speed(0.005).min_decimals(2).clamp_range(0.0..=1.0));
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Size:");
+                    ui.add(egui::DragValue::new(&mut layer.min_size).prefix("Min: ").speed(0.0005).min_decimals(4).clamp_range(0.0..=1.0));
+                    ui.add(egui::DragValue::new(&mut layer.max_size).prefix("Max: ").speed(0.0005).min_decimals(4).clamp_range(0.0..=1.0));
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Color:");
+                    egui::color_picker::color_edit_button_rgb(ui, &mut layer.color);
+                });
+                if ui.button("Remove Layer").clicked() {
+                    removed = Some(i);
+                }
+            });
+        }
+        if let Some(i) = removed {
+            edited.layers.remove(i);
+        }
+
+        if ui.button("Add Layer").clicked() {
+            edited.layers.push(ScatterLayer::default());
+        }
+    });
+
+    if edited != *scatter {
+        *scatter = edited;
+    }
+}
@@ -0,0 +1,170 @@
+use bevy::{prelude::*, window::PrimaryWindow};
+use bevy_egui::{egui, EguiContexts};
+use bevy_panorbit_camera::PanOrbitCamera;
+
+use crate::{render::planet::{Planet, UpdatePlanetMesh}, gen::{shape::ShapeGenerator, sculpt::{SculptBrush, SculptTool, Sculpting}}};
+
+use super::{camera::CameraMode, render::UiVisibility, planets::SelectedPlanet, picking::{cursor_ray, pick_planet}};
+
+
+#[derive(Resource, Default)]
+pub struct UiSculptSettings {
+    /// Whether left dragging over a planet paints with the brush instead of orbiting.
+    pub painting: bool,
+    pub brush: SculptBrush,
+}
+
+/// How often, in seconds, a stroke rebuilds the planet's mesh while it's painted.
+const STROKE_MESH_UPDATE_INTERVAL: f32 = 0.1;
+
+/// The stroke being painted while the mouse is held, on the planet it started on.
+pub struct SculptStroke {
+    planet: Entity,
+    flatten_elevation: f32,
+    /// When the mesh was last rebuilt, and whether it's been painted on since.
+    last_mesh_update: f32,
+    unmeshed: bool,
+}
+
+pub fn sculpt_settings(
+    mut contexts: EguiContexts,
+    mut sculpt_settings: ResMut<UiSculptSettings>,
+    mut planets: Query<&mut ShapeGenerator>,
+    selected_planet: Res<SelectedPlanet>,
+    mut update_planet_mesh_evw: EventWriter<UpdatePlanetMesh>,
+    ui_visibility: Res<UiVisibility>,
+) {
+    if *ui_visibility != UiVisibility::Visible { return };
+
+    let Some(planet_entity) = selected_planet.0 else { return };
+    let Ok(mut shape_gen) = planets.get_mut(planet_entity) else { return };
+
+    egui::Window::new("Sculpt").show(contexts.ctx_mut(), |ui| {
+        ui.horizontal(|ui| {
+            ui.label("Paint:");
+            ui.add(egui::widgets::Checkbox::without_text(&mut sculpt_settings.painting));
+        });
+
+        ui.horizontal(|ui| {
+            ui.label("Brush:");
+            for tool in SculptTool::ALL {
+                ui.selectable_value(&mut sculpt_settings.brush.tool, tool, tool.name());
+            }
+        });
+
+        let brush = &mut sculpt_settings.brush;
+        ui.horizontal(|ui| {
+            ui.label("Radius:");
+            ui.add(egui::DragValue::new(&mut brush.radius).speed(0.001).min_decimals(3).clamp_range(0.001..=SculptBrush::MAX_RADIUS));
+        });
+        ui.horizontal(|ui| {
+            ui.label("Strength:");
+            ui.add(egui::DragValue::new(&mut brush.strength).speed(0.001).min_decimals(3).clamp_range(0.0..=1.0));
+        });
+        ui.horizontal(|ui| {
+            ui.label("Falloff:");
+            ui.add(egui::Slider::new(&mut brush.falloff, 0.0..=1.0));
+        });
+        if brush.tool == SculptTool::NoiseStamp {
+            ui.horizontal(|ui| {
+                ui.label("Noise Scale:");
+                ui.add(egui::DragValue::new(&mut brush.noise_scale).speed(0.1).min_decimals(1).clamp_range(0.1..=1000.0));
+            });
+            ui.horizontal(|ui| {
+                ui.label("Noise Seed:");
+                ui.add(egui::DragValue::new(&mut brush.noise_seed));
+            });
+        }
+
+        ui.separator();
+
+        ui.horizontal(|ui| {
+            ui.label("Resolution:");
+            let mut resolution = shape_gen.sculpt.resolution;
+            ui.add(egui::DragValue::new(&mut resolution).clamp_range(2..=2048));
+            if resolution != shape_gen.sculpt.resolution {
+                shape_gen.sculpt.set_resolution(resolution);
+                update_planet_mesh_evw.send(UpdatePlanetMesh { planet: planet_entity });
+            }
+        });
+
+        if ui.add_enabled(!shape_gen.sculpt.is_empty(), egui::Button::new("Clear Sculpting")).clicked() {
+            shape_gen.sculpt.clear();
+            update_planet_mesh_evw.send(UpdatePlanetMesh { planet: planet_entity });
+        }
+    });
+}
+
+/// Paints onto the planet under the cursor while the left mouse button is held, holding the orbit
+/// camera still for the duration of the stroke.
+#[allow(clippy::too_many_arguments)]
+pub fn sculpt_brush(
+    mut commands: Commands,
+    mut contexts: EguiContexts,
+    sculpt_settings: Res<UiSculptSettings>,
+    camera_mode: Res<CameraMode>,
+    ui_visibility: Res<UiVisibility>,
+    mouse_buttons: Res<Input<MouseButton>>,
+    time: Res<Time>,
+    primary_window: Query<&Window, With<PrimaryWindow>>,
+    cameras: Query<(&Camera, &GlobalTransform)>,
+    mut pan_orbit_cameras: Query<&mut PanOrbitCamera>,
+    mut planets: Query<(Entity, &Planet, &mut ShapeGenerator, &GlobalTransform)>,
+    mut update_planet_mesh_evw: EventWriter<UpdatePlanetMesh>,
+    mut gizmos: Gizmos,
+    mut stroke: Local<Option<SculptStroke>>,
+) {
+    let active = sculpt_settings.painting && *camera_mode == CameraMode::Edit && *ui_visibility == UiVisibility::Visible;
+    if !active || !mouse_buttons.pressed(MouseButton::Left) {
+        if let Some(finished) = stroke.take() {
+            if finished.unmeshed {
+                update_planet_mesh_evw.send(UpdatePlanetMesh { planet: finished.planet });
+            }
+            if let Some(mut planet) = commands.get_entity(finished.planet) {
+                planet.remove::<Sculpting>();
+            }
+            for mut pan_orbit in pan_orbit_cameras.iter_mut() {
+                pan_orbit.enabled = true;
+            }
+        }
+        if !active { return };
+    }
+
+    let over_ui = contexts.ctx_mut().is_pointer_over_area();
+    let Some(ray) = cursor_ray(&primary_window, &cameras) else { return };
+    let Some((planet_entity, direction)) = pick_planet(ray, planets.iter()) else { return };
+    let Ok((_, _, mut shape_gen, planet_transform)) = planets.get_mut(planet_entity) else { return };
+
+    // outline the brush on the surface, tilted to match the planet
+    let brush = &sculpt_settings.brush;
+    let elevation = shape_gen.get_elevation(direction);
+    let (_, planet_rotation, planet_center) = planet_transform.to_scale_rotation_translation();
+    let world_up = planet_rotation * direction;
+    let outline_center = planet_center + world_up * elevation * brush.radius.cos();
+    gizmos.circle(outline_center, world_up, elevation * brush.radius.sin(), Color::WHITE);
+
+    if stroke.is_none() && mouse_buttons.just_pressed(MouseButton::Left) && !over_ui {
+        *stroke = Some(SculptStroke {
+            planet: planet_entity,
+            flatten_elevation: elevation,
+            last_mesh_update: time.elapsed_seconds(),
+            unmeshed: false,
+        });
+        commands.entity(planet_entity).insert(Sculpting);
+        for mut pan_orbit in pan_orbit_cameras.iter_mut() {
+            pan_orbit.enabled = false;
+        }
+    }
+    let Some(current) = stroke.as_mut() else { return };
+    if current.planet != planet_entity { return };
+
+    brush.apply(&mut shape_gen, direction, time.delta_seconds(), current.flatten_elevation);
+    current.unmeshed = true;
+
+    // rebuilding the mesh every frame would fall behind, so it catches up now and then and when the stroke ends
+    if time.elapsed_seconds() - current.last_mesh_update >= STROKE_MESH_UPDATE_INTERVAL {
+        update_planet_mesh_evw.send(UpdatePlanetMesh { planet: planet_entity });
+        current.last_mesh_update = time.elapsed_seconds();
+        current.unmeshed = false;
+    }
+}
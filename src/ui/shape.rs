@@ -1,9 +1,9 @@
-use bevy::prelude::*;
+use bevy::{prelude::*, utils::HashMap};
 use bevy_egui::{egui, EguiContexts};
 
-use crate::{gen::{shape::ShapeGenerator, noise_filter::{NoiseLayer, NoiseFilterType}}, render::planet::UpdatePlanetMesh};
+use crate::{gen::{shape::ShapeGenerator, noise_filter::{NoiseLayer, NoiseFilterType, NoiseFilter}, heightmap::{HeightmapProjection, HeightmapFiltering}}, render::planet::{UpdatePlanetMesh, Planet}};
 
-use super::render::UiVisibility;
+use super::{render::UiVisibility, planets::SelectedPlanet};
 
 pub struct AutoUpdateState(bool);
 impl Default for AutoUpdateState {
@@ -13,16 +13,43 @@ impl Default for AutoUpdateState {
 }
 
 
+/// The last heightmap load error of each planet's noise layers, by layer index.
+#[derive(Default)]
+pub struct HeightmapLoadErrors(HashMap<(Entity, usize), String>);
+
+impl HeightmapLoadErrors {
+    /// Loads the layer's heightmap, remembering why if it fails. Returns whether it loaded.
+    fn load(&mut self, filter: &mut NoiseFilter, planet: Entity, layer: usize) -> bool {
+        match filter.load_heightmap() {
+            Ok(()) => {
+                self.0.remove(&(planet, layer));
+                true
+            }
+            Err(err) => {
+                self.0.insert((planet, layer), format!("Failed to load heightmap: {}", err));
+                false
+            }
+        }
+    }
+}
+
+
+#[allow(clippy::too_many_arguments)]
 pub fn shape_settings(
     mut contexts: EguiContexts,
-    mut shape_gen: ResMut<ShapeGenerator>,
+    mut planets: Query<(&Planet, &mut ShapeGenerator)>,
+    selected_planet: Res<SelectedPlanet>,
     mut update_planet_mesh_evw: EventWriter<UpdatePlanetMesh>,
     mut auto_update: Local<AutoUpdateState>,
+    mut heightmap_errors: Local<HeightmapLoadErrors>,
     ui_visibility: Res<UiVisibility>,
     time: Res<Time>,
 ) {
     if *ui_visibility != UiVisibility::Visible { return };
 
+    let Some(planet_entity) = selected_planet.0 else { return };
+    let Ok((planet, mut shape_gen)) = planets.get_mut(planet_entity) else { return };
+
     let mut changed = false;
 
     egui::Window::new("Shape Settings").show(contexts.ctx_mut(), |ui| {
@@ -36,10 +63,18 @@ pub fn shape_settings(
         ui.add(egui::Checkbox::new(&mut auto_update.0, "Auto-Update"));
         if !auto_update.0 {
             if ui.button("Update Mesh").clicked() {
-                update_planet_mesh_evw.send(UpdatePlanetMesh {});
+                update_planet_mesh_evw.send(UpdatePlanetMesh { planet: planet_entity });
             }
         }
 
+        if let Some(regeneration) = &planet.regeneration {
+            ui.add(egui::ProgressBar::new(regeneration.progress()).text(format!(
+                "Generating mesh: {}/{} chunks",
+                regeneration.total - regeneration.remaining,
+                regeneration.total,
+            )));
+        }
+
         if ui.button("Randomize Planet Shape").clicked() {
             for i in 0..shape_gen.num_layers {
                 let layer = &mut shape_gen.noise_layers[i as usize];
@@ -53,9 +88,14 @@ pub fn shape_settings(
             if ui.small_button("-").clicked() && shape_gen.num_layers > 1 {
                 shape_gen.num_layers -= 1;
                 shape_gen.noise_layers.pop();
+                let num_layers = shape_gen.num_layers as usize;
+                heightmap_errors.0.retain(|(planet, layer), _| *planet != planet_entity || *layer < num_layers);
             }
             ui.label(format!("{}", shape_gen.num_layers));
-            if ui.small_button("+").clicked() {
+            let can_add = shape_gen.num_layers < ShapeGenerator::MAX_NOISE_LAYERS;
+            let add = ui.add_enabled(can_add, egui::Button::new("+").small())
+                .on_disabled_hover_text(format!("Planets can have at most {} noise layers", ShapeGenerator::MAX_NOISE_LAYERS));
+            if add.clicked() {
                 shape_gen.num_layers += 1;
                 let num_layers = shape_gen.num_layers;
                 shape_gen.noise_layers.push(NoiseLayer::new(num_layers, false));
@@ -76,6 +116,7 @@ pub fn shape_settings(
                             ui.selectable_value(&mut layer.filter.ty, NoiseFilterType::Standard, "Standard");
                             ui.selectable_value(&mut layer.filter.ty, NoiseFilterType::Rigid, "Rigid");
                             ui.selectable_value(&mut layer.filter.ty, NoiseFilterType::Warp, "Warp");
+                            ui.selectable_value(&mut layer.filter.ty, NoiseFilterType::Heightmap, "Heightmap");
                         });
                     layer.is_warp = layer.filter.ty == NoiseFilterType::Warp;
                     changed = changed || (old != layer.filter.ty);
@@ -113,40 +154,89 @@ pub fn shape_settings(
                     });
                 }
 
-                ui.horizontal(|ui| {
-                    ui.label("Noise Octaves:");
-                    let old = layer.filter.num_octaves;
-                    ui.add(egui::widgets::DragValue::new(&mut layer.filter.num_octaves).clamp_range(0..=8).max_decimals(0).speed(0.05));
-                    changed = changed || (old != layer.filter.num_octaves);
-                });
+                let is_heightmap = layer.filter.ty == NoiseFilterType::Heightmap;
+                if is_heightmap {
+                    ui.horizontal(|ui| {
+                        if ui.button("Load Heightmap:").clicked() && heightmap_errors.load(&mut layer.filter, planet_entity, i as usize) {
+                            changed = true;
+                        }
+                        ui.text_edit_singleline(&mut layer.filter.heightmap_path);
+                    });
+                    if let Some(err) = heightmap_errors.0.get(&(planet_entity, i as usize)) {
+                        ui.colored_label(egui::Color32::RED, err);
+                    }
+
+                    ui.horizontal(|ui| {
+                        ui.label("Projection:");
+                        let old = layer.filter.heightmap_projection;
+                        egui::ComboBox::from_id_source(format!("heightmap_projection_{}", i))
+                            .selected_text(layer.filter.heightmap_projection.name())
+                            .show_ui(ui, |ui| {
+                                for projection in HeightmapProjection::ALL {
+                                    ui.selectable_value(&mut layer.filter.heightmap_projection, projection, projection.name());
+                                }
+                            });
+                        // the projections read different files, so a loaded heightmap has to be read again
+                        if old != layer.filter.heightmap_projection {
+                            if layer.filter.heightmap.is_some() {
+                                heightmap_errors.load(&mut layer.filter, planet_entity, i as usize);
+                            }
+                            changed = true;
+                        }
+                    });
+
+                    ui.horizontal(|ui| {
+                        ui.label("Filtering:");
+                        let old = layer.filter.heightmap_filtering;
+                        egui::ComboBox::from_id_source(format!("heightmap_filtering_{}", i))
+                            .selected_text(layer.filter.heightmap_filtering.name())
+                            .show_ui(ui, |ui| {
+                                for filtering in HeightmapFiltering::ALL {
+                                    ui.selectable_value(&mut layer.filter.heightmap_filtering, filtering, filtering.name());
+                                }
+                            });
+                        changed = changed || (old != layer.filter.heightmap_filtering);
+                    });
+                }
+
+                if !is_heightmap {
+                    ui.horizontal(|ui| {
+                        ui.label("Noise Octaves:");
+                        let old = layer.filter.num_octaves;
+                        ui.add(egui::widgets::DragValue::new(&mut layer.filter.num_octaves).clamp_range(0..=8).max_decimals(0).speed(0.05));
+                        changed = changed || (old != layer.filter.num_octaves);
+                    });
+                }
                 
                 ui.horizontal(|ui| {
-                    ui.label("Noise Strength:");
+                    ui.label(if is_heightmap { "Height Scale:" } else { "Noise Strength:" });
                     let old = layer.filter.strength;
                     ui.add(egui::widgets::DragValue::new(&mut layer.filter.strength).clamp_range(0f32..=100f32).min_decimals(2).speed(0.025));
                     changed = changed || (old != layer.filter.strength);
                 });
                 
-                ui.horizontal(|ui| {
-                    ui.label("Noise Roughness:");
-                    let old = layer.filter.roughness;
-                    ui.add(egui::widgets::DragValue::new(&mut layer.filter.roughness).clamp_range(0f32..=100f32).min_decimals(2).speed(0.025));
-                    changed = changed || (old != layer.filter.roughness);
-                });
+                if !is_heightmap {
+                    ui.horizontal(|ui| {
+                        ui.label("Noise Roughness:");
+                        let old = layer.filter.roughness;
+                        ui.add(egui::widgets::DragValue::new(&mut layer.filter.roughness).clamp_range(0f32..=100f32).min_decimals(2).speed(0.025));
+                        changed = changed || (old != layer.filter.roughness);
+                    });
                 
-                ui.horizontal(|ui| {
-                    ui.label("Noise Lacunarity:");
-                    let old = layer.filter.lacunarity;
-                    ui.add(egui::widgets::DragValue::new(&mut layer.filter.lacunarity).clamp_range(0f32..=100f32).min_decimals(2).speed(0.025));
-                    changed = changed || (old != layer.filter.lacunarity);
-                });
+                    ui.horizontal(|ui| {
+                        ui.label("Noise Lacunarity:");
+                        let old = layer.filter.lacunarity;
+                        ui.add(egui::widgets::DragValue::new(&mut layer.filter.lacunarity).clamp_range(0f32..=100f32).min_decimals(2).speed(0.025));
+                        changed = changed || (old != layer.filter.lacunarity);
+                    });
                 
-                ui.horizontal(|ui| {
-                    ui.label("Noise Persistence:");
-                    let old = layer.filter.persistence;
-                    ui.add(egui::widgets::DragValue::new(&mut layer.filter.persistence).clamp_range(0f32..=100f32).min_decimals(2).speed(0.025));
-                    changed = changed || (old != layer.filter.persistence);
-                });
+                    ui.horizontal(|ui| {
+                        ui.label("Noise Persistence:");
+                        let old = layer.filter.persistence;
+                        ui.add(egui::widgets::DragValue::new(&mut layer.filter.persistence).clamp_range(0f32..=100f32).min_decimals(2).speed(0.025));
+                        changed = changed || (old != layer.filter.persistence);
+                    });
+                }
                 
                 ui.horizontal(|ui| {
                     ui.label("Vertical Offset:");
@@ -162,19 +252,21 @@ pub fn shape_settings(
                     changed = changed || (old != layer.filter.floor);
                 });
         
-                ui.horizontal(|ui| {
-                    ui.label("Noise Center:");
-                    let old = layer.filter.center;
-                    ui.add(egui::widgets::DragValue::new(&mut layer.filter.center.x).prefix("X: ").clamp_range(0f32..=100f32).min_decimals(2).speed(0.025));
-                    ui.add(egui::widgets::DragValue::new(&mut layer.filter.center.y).prefix("Y: ").clamp_range(0f32..=100f32).min_decimals(2).speed(0.025));
-                    ui.add(egui::widgets::DragValue::new(&mut layer.filter.center.z).prefix("Z: ").clamp_range(0f32..=100f32).min_decimals(2).speed(0.025));
-                    changed = changed || (old != layer.filter.center);
-                });
+                if !is_heightmap {
+                    ui.horizontal(|ui| {
+                        ui.label("Noise Center:");
+                        let old = layer.filter.center;
+                        ui.add(egui::widgets::DragValue::new(&mut layer.filter.center.x).prefix("X: ").clamp_range(0f32..=100f32).min_decimals(2).speed(0.025));
+                        ui.add(egui::widgets::DragValue::new(&mut layer.filter.center.y).prefix("Y: ").clamp_range(0f32..=100f32).min_decimals(2).speed(0.025));
+                        ui.add(egui::widgets::DragValue::new(&mut layer.filter.center.z).prefix("Z: ").clamp_range(0f32..=100f32).min_decimals(2).speed(0.025));
+                        changed = changed || (old != layer.filter.center);
+                    });
+                }
             });
         }
     });
 
     if changed && auto_update.0 {
-        update_planet_mesh_evw.send(UpdatePlanetMesh {});
+        update_planet_mesh_evw.send(UpdatePlanetMesh { planet: planet_entity });
     }
 }
\ No newline at end of file
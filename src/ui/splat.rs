@@ -0,0 +1,128 @@
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+
+use crate::render::{planet::UpdatePlanetMaterials, splat::{PlanetTerrainLayers, TerrainLayer, MAX_TERRAIN_LAYERS}};
+
+use super::{render::UiVisibility, planets::SelectedPlanet};
+
+
+const TEXTURE_RESOLUTIONS: [u32; 5] = [128, 256, 512, 1024, 2048];
+
+pub fn terrain_layer_settings(
+    mut contexts: EguiContexts,
+    mut planets: Query<&mut PlanetTerrainLayers>,
+    selected_planet: Res<SelectedPlanet>,
+    mut update_planet_mats_evw: EventWriter<UpdatePlanetMaterials>,
+    ui_visibility: Res<UiVisibility>,
+) {
+    if *ui_visibility != UiVisibility::Visible { return };
+
+    let Some(planet_entity) = selected_planet.0 else { return };
+    let Ok(mut terrain_layers) = planets.get_mut(planet_entity) else { return };
+
+    let mut changed = false;
+
+    egui::Window::new("Terrain Layers").show(contexts.ctx_mut(), |ui| {
+        ui.horizontal(|ui| {
+            ui.label("Texture Resolution:");
+            for resolution in TEXTURE_RESOLUTIONS {
+                changed |= ui.selectable_value(&mut terrain_layers.texture_resolution, resolution, resolution.to_string()).changed();
+            }
+        });
+
+        ui.separator();
+
+        let mut removed = None;
+        let mut moved_up = None;
+        let count = terrain_layers.layers.len();
+        for (i, layer) in terrain_layers.layers.iter_mut().enumerate() {
+            egui::containers::CollapsingHeader::new(format!("Layer {}: {}", i + 1, layer.name)).id_source(i).show(ui, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("Name:");
+                    ui.text_edit_singleline(&mut layer.name);
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Enabled:");
+                    changed |= ui.add(egui::widgets::Checkbox::without_text(&mut layer.enabled)).changed();
+                });
+
+                // the textures are only reloaded once a path is done being edited
+                ui.horizontal(|ui| {
+                    ui.label("Albedo Texture:");
+                    changed |= ui.text_edit_singleline(&mut layer.albedo_texture).lost_focus();
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Normal Texture:");
+                    changed |= ui.text_edit_singleline(&mut layer.normal_texture).lost_focus();
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Roughness Texture:");
+                    changed |= ui.text_edit_singleline(&mut layer.roughness_texture).lost_focus();
+                });
+
+                ui.horizontal(|ui| {
+                    ui.label("Tint:");
+                    changed |= egui::color_picker::color_edit_button_rgb(ui, &mut layer.tint).changed();
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Roughness:");
+                    changed |= ui.add(egui::Slider::new(&mut layer.roughness, 0.0..=1.0)).changed();
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Normal Strength:");
+                    changed |= ui.add(egui::Slider::new(&mut layer.normal_strength, 0.0..=1.0)).changed();
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Triplanar Scale:");
+                    changed |= ui.add(egui::DragValue::new(&mut layer.triplanar_scale).speed(0.025).min_decimals(2).clamp_range(0.0..=1000.0)).changed();
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Elevation:");
+                    changed |= ui.add(egui::DragValue::new(&mut layer.min_elevation).prefix("Min: ").speed(0.005).min_decimals(2).clamp_range(0.0..=1.0)).changed();
+                    changed |= ui.add(egui::DragValue::new(&mut layer.max_elevation).prefix("Max: ").speed(0.005).min_decimals(2).clamp_range(0.0..=1.0)).changed();
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Steepness:");
+                    changed |= ui.add(egui::DragValue::new(&mut layer.min_steepness).prefix("Min: ").speed(0.005).min_decimals(2).clamp_range(0.0..=1.0)).changed();
+                    changed |= ui.add(egui::DragValue::new(&mut layer.max_steepness).prefix("Max: ").speed(0.005).min_decimals(2).clamp_range(0.0..=1.0)).changed();
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Blend:");
+                    changed |= ui.add(egui::DragValue::new(&mut layer.blend).speed(0.005).min_decimals(2).clamp_range(0.0..=1.0)).changed();
+                });
+
+                ui.horizontal(|ui| {
+                    if ui.add_enabled(i > 0, egui::Button::new("Move Up")).clicked() {
+                        moved_up = Some(i);
+                    }
+                    if ui.add_enabled(i + 1 < count, egui::Button::new("Move Down")).clicked() {
+                        moved_up = Some(i + 1);
+                    }
+                    if ui.button("Remove Layer").clicked() {
+                        removed = Some(i);
+                    }
+                });
+            });
+        }
+        if let Some(i) = moved_up {
+            terrain_layers.layers.swap(i - 1, i);
+            changed = true;
+        }
+        if let Some(i) = removed {
+            terrain_layers.layers.remove(i);
+            changed = true;
+        }
+
+        let can_add = terrain_layers.layers.len() < MAX_TERRAIN_LAYERS;
+        let add_button = ui.add_enabled(can_add, egui::Button::new("Add Layer"))
+            .on_disabled_hover_text(format!("A planet can have at most {} layers", MAX_TERRAIN_LAYERS));
+        if add_button.clicked() {
+            terrain_layers.layers.push(TerrainLayer::default());
+            changed = true;
+        }
+    });
+
+    if changed {
+        update_planet_mats_evw.send(UpdatePlanetMaterials { planet: planet_entity });
+    }
+}